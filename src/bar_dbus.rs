@@ -0,0 +1,145 @@
+//! The D-Bus interface of the bar
+//!
+//! The bar requests the `rs.swaystatus.Bar` name on the session bus and serves the `/` path, which
+//! implements the `rs.swaystatus.Bar` interface:
+//! ```text
+//! method void rs.swaystatus.Bar.RefreshBlock(QString name)
+//! method void rs.swaystatus.Bar.ToggleBlock(QString name)
+//! method void rs.swaystatus.Bar.SwitchTheme(QString theme)
+//! signal void rs.swaystatus.Bar.BlockClicked(QString name, QString instance, QString button)
+//! ```
+//!
+//! Blocks are referred to by their `name` option. Clicks on blocks without a name are reported
+//! with the block's type as the name.
+//!
+//! # Example
+//!
+//! ```sh
+//! busctl --user call rs.swaystatus.Bar / rs.swaystatus.Bar RefreshBlock s clock
+//! busctl --user call rs.swaystatus.Bar / rs.swaystatus.Bar SwitchTheme s solarized-dark
+//! dbus-monitor "type='signal',interface='rs.swaystatus.Bar'"
+//! ```
+
+use std::sync::Arc;
+
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::MatchRule;
+use dbus::nonblock::stdintf::org_freedesktop_dbus::RequestNameReply;
+use dbus::nonblock::SyncConnection;
+use dbus::{Message, MethodErr};
+use dbus_crossroads::{Crossroads, IfaceBuilder};
+use dbus_tokio::connection;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::click::MouseButton;
+use crate::errors::*;
+use crate::ipc::{BlockRef, IpcRequest, Request};
+
+const NAME: &str = "rs.swaystatus.Bar";
+const INTERFACE: &str = "rs.swaystatus.Bar";
+
+pub struct BarDbus {
+    dbus_conn: Arc<SyncConnection>,
+}
+
+impl BarDbus {
+    /// Serve the interface. The method calls are passed to the main loop through `sender`, the
+    /// same way as the requests from the control socket.
+    pub async fn new(sender: mpsc::Sender<IpcRequest>) -> Result<Self> {
+        let (resource, dbus_conn) = connection::new_session_sync()
+            .internal_error("bar D-Bus", "failed to open D-Bus connection")?;
+        tokio::spawn(async {
+            let err = resource.await;
+            log::warn!("Lost connection to D-Bus: {}", err);
+        });
+
+        // Another bar may own the name already. It can still be reached by its unique name.
+        let reply = dbus_conn
+            .request_name(NAME, false, false, true)
+            .await
+            .internal_error("bar D-Bus", "request_name() failed")?;
+        if reply != RequestNameReply::PrimaryOwner {
+            log::warn!("D-Bus name {} is already taken", NAME);
+        }
+
+        let mut crossroads = Crossroads::new();
+        crossroads.set_async_support(Some((
+            dbus_conn.clone(),
+            Box::new(|x| {
+                tokio::spawn(x);
+            }),
+        )));
+
+        let iface_token = crossroads.register(INTERFACE, |b| {
+            add_method(b, &sender, "RefreshBlock", "name", |name| Request::Update {
+                block: BlockRef::Name(name),
+            });
+            add_method(b, &sender, "ToggleBlock", "name", |name| Request::Toggle {
+                block: BlockRef::Name(name),
+            });
+            add_method(b, &sender, "SwitchTheme", "theme", |theme| {
+                Request::SetTheme { theme }
+            });
+            b.signal::<(String, String, String), _>("BlockClicked", ("name", "instance", "button"));
+        });
+        crossroads.insert("/", &[iface_token], ());
+
+        dbus_conn.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |msg, conn| {
+                crossroads.handle_message(msg, conn).unwrap();
+                true
+            }),
+        );
+
+        Ok(Self { dbus_conn })
+    }
+
+    /// Emit the `BlockClicked` signal. `instance` is an empty string if the click has no instance.
+    pub fn block_clicked(&self, name: &str, instance: Option<usize>, button: MouseButton) {
+        let instance = instance.map(|i| i.to_string()).unwrap_or_default();
+        let signal = Message::new_signal("/", INTERFACE, "BlockClicked")
+            .unwrap()
+            .append3(name, instance, button.name());
+        // Nobody may be listening, so failing to send is not an error
+        let _ = self.dbus_conn.send(signal);
+    }
+
+    /// Give up the bus name, so that another bar can take it right away
+    pub async fn close(self) {
+        let _ = self.dbus_conn.release_name(NAME).await;
+    }
+}
+
+/// Add a method which takes a single string and is handled by the main loop
+fn add_method(
+    b: &mut IfaceBuilder<()>,
+    sender: &mpsc::Sender<IpcRequest>,
+    method: &'static str,
+    arg: &'static str,
+    request: fn(String) -> Request,
+) {
+    let sender = sender.clone();
+    b.method_with_cr_async(method, (arg,), (), move |mut ctx, _, (arg,): (String,)| {
+        let sender = sender.clone();
+        async move {
+            let result = send_request(&sender, request(arg)).await;
+            ctx.reply(result.map_err(|error| MethodErr::failed(&error)))
+        }
+    });
+}
+
+async fn send_request(
+    sender: &mpsc::Sender<IpcRequest>,
+    request: Request,
+) -> StdResult<(), String> {
+    let (reply, reply_rx) = oneshot::channel();
+    sender
+        .send(IpcRequest { request, reply })
+        .await
+        .map_err(|_| "the bar is shutting down".to_string())?;
+    reply_rx
+        .await
+        .map_err(|_| "the request was dropped".to_string())?
+        .map(|_| ())
+}
@@ -1,59 +1,221 @@
-mod backlight;
-mod battery;
-mod cpu;
-mod custom;
-mod custom_dbus;
-mod disk_space;
-mod focused_window;
-mod github;
-mod load;
-mod memory;
-mod music;
-mod net;
-mod pomodoro;
-mod sound;
-mod speedtest;
-mod sway_kbd;
-mod taskwarrior;
-mod temperature;
-mod time;
-mod weather;
-
-use serde::de::Deserialize;
+use std::any::Any;
 use std::collections::HashMap;
-use tokio::sync::mpsc;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::future::{BoxFuture, FutureExt};
+use serde::de::{Deserialize, DeserializeOwned};
+use serde_json::Value as JsonValue;
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
 use toml::value::{Table, Value};
 
+use crate::check::Problem;
 use crate::click::ClickHandler;
 use crate::config::SharedConfig;
+use crate::de::{deserialize_duration, struct_fields};
 use crate::errors::*;
+use crate::formatting::value::Value as FormatValue;
+use crate::formatting::FormatTemplate;
 use crate::protocol::i3bar_block::I3BarBlock;
 use crate::protocol::i3bar_event::I3BarEvent;
 use crate::signals::Signal;
+use crate::state::BlockState;
+use crate::util::did_you_mean;
+use crate::widget::{State, Widget};
+
+/// Declare the modules of all blocks and register them.
+///
+/// To add a new block, implement [`Block`] in `src/blocks/<name>.rs` and add a `<name>::<Type>`
+/// line here.
+macro_rules! define_blocks {
+    ($($module:ident :: $block:ident,)*) => {
+        $(mod $module;)*
+
+        /// All available blocks, sorted by name
+        pub static BLOCKS: &[BlockEntry] = &[
+            $(
+                BlockEntry {
+                    name: <$module::$block as Block>::NAME,
+                    placeholders: <$module::$block as Block>::PLACEHOLDERS,
+                    icons: <$module::$block as Block>::ICONS,
+                    widgets: <$module::$block as Block>::WIDGETS,
+                    config_fields: struct_fields::<<$module::$block as Block>::Config>,
+                    run: run_block::<$module::$block>,
+                    check: check_block::<$module::$block>,
+                },
+            )*
+        ];
+    };
+}
+
+define_blocks!(
+    backlight::Backlight,
+    battery::Battery,
+    cpu::Cpu,
+    custom::Custom,
+    custom_dbus::CustomDbus,
+    disk_space::DiskSpace,
+    focused_window::FocusedWindow,
+    github::Github,
+    load::Load,
+    memory::Memory,
+    music::Music,
+    net::Net,
+    pomodoro::Pomodoro,
+    sound::Sound,
+    speedtest::Speedtest,
+    sway_kbd::SwayKbd,
+    taskwarrior::Taskwarrior,
+    temperature::Temperature,
+    time::Time,
+    weather::Weather,
+);
+
+/// The instance of the widget of `B` called `name`, which must be in `B::WIDGETS`
+pub fn widget_instance<B: Block>(name: &str) -> usize {
+    B::WIDGETS
+        .iter()
+        .position(|widget| *widget == name)
+        .expect("the widget is not in WIDGETS")
+}
+
+/// The name of the widget with `instance`, if the block declares it in `WIDGETS`
+pub fn widget_name(
+    widgets: &'static [&'static str],
+    instance: Option<usize>,
+) -> Option<&'static str> {
+    widgets.get(instance?).copied()
+}
+
+/// Find a block by the name used in the config file
+pub fn find_block(name: &str) -> Option<&'static BlockEntry> {
+    BLOCKS.iter().find(|b| b.name == name)
+}
+
+/// The interface every block implements.
+///
+/// The runtime deserializes `Config`, calls `init` once (followed by `restore_state` if there is a
+/// saved state) and then `update` every time the block needs to be redrawn: on start, every
+/// `interval`, when `wait_for_change` resolves and after `handle_event` returns `true`.
+#[async_trait]
+pub trait Block: Sized + Send + 'static {
+    /// The name used in the config file (`block = "<NAME>"`)
+    const NAME: &'static str;
+
+    /// The placeholders which can be used in this block's format strings. A trailing `*` matches
+    /// any suffix (e.g. `"utilization*"` matches `"utilization"` and `"utilization1"`).
+    const PLACEHOLDERS: &'static [&'static str] = &[];
+
+    /// The icons this block may use
+    const ICONS: &'static [&'static str] = &[];
+
+    /// The names of the widgets which can be clicked on their own, for the `widget` option of
+    /// click handlers. The widget called `WIDGETS[i]` is given the instance `i`, see
+    /// [`widget_instance`].
+    const WIDGETS: &'static [&'static str] = &[];
+
+    /// Block-specific configuration
+    type Config: DeserializeOwned + Send;
+
+    /// Create the block
+    async fn init(
+        id: usize,
+        block_config: Self::Config,
+        shared_config: SharedConfig,
+    ) -> Result<Self>;
+
+    /// Render the block
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>>;
+
+    /// Handle a click, a signal or a `ToggleFormat` request. Returns `true` if the block should be
+    /// updated.
+    ///
+    /// By default the block is updated on every click and ignores signals.
+    async fn handle_event(&mut self, event: BlockEvent) -> Result<bool> {
+        Ok(matches!(event, BlockEvent::I3Bar(_)))
+    }
+
+    /// How often `update` should be called. `None` means that the block is updated only on events.
+    ///
+    /// The updates are timed by the [scheduler](crate::scheduler), so that blocks wake up together.
+    fn interval(&self) -> Option<Duration> {
+        None
+    }
 
-#[derive(serde_derive::Deserialize, Debug, Clone, Copy, PartialEq)]
-#[serde(rename_all = "snake_case")]
-pub enum BlockType {
-    Backlight,
-    Battery,
-    Cpu,
-    Custom,
-    CustomDbus,
-    DiskSpace,
-    FocusedWindow,
-    Github,
-    Load,
-    Memory,
-    Music,
-    Net,
-    Pomodoro,
-    Sound,
-    Speedtest,
-    SwayKbd,
-    Taskwarrior,
-    Temperature,
-    Time,
-    Weather,
+    /// Resolve when the block has new information to display. Must be cancel-safe.
+    ///
+    /// Never resolves by default.
+    async fn wait_for_change(&mut self) -> Result<()> {
+        futures::future::pending().await
+    }
+
+    /// The state to keep when the block or swaystatus is restarted, such as which format is
+    /// shown. Called after every `update`.
+    ///
+    /// `None` by default, meaning that there is nothing to keep.
+    fn state(&self) -> Option<JsonValue> {
+        None
+    }
+
+    /// Restore the state returned by `state`. A state which doesn't apply anymore (e.g. because
+    /// the config was changed) should be ignored.
+    fn restore_state(&mut self, _state: JsonValue) {}
+
+    /// The values of the placeholders shown by the last `update`, which click commands can use.
+    /// Called after every `update`.
+    ///
+    /// Empty by default.
+    fn values(&self) -> HashMap<String, FormatValue> {
+        HashMap::new()
+    }
+}
+
+/// Runs a block until it fails. The last argument tells whether the bar is hidden, in which case
+/// the block is not updated on its interval.
+pub type BlockRunner = fn(
+    BlockIdentity,
+    Value,
+    SharedConfig,
+    BlockState,
+    mpsc::Sender<BlockMessage>,
+    mpsc::Receiver<BlockEvent>,
+    watch::Receiver<bool>,
+) -> BoxFuture<'static, Result<()>>;
+
+/// How the runtime refers to a running block
+#[derive(Debug, Clone)]
+pub struct BlockIdentity {
+    /// The `name` of the block's widgets on the bar
+    pub id: usize,
+    /// The target of the log messages about the block, see [`crate::logging::block_target`]
+    pub log_target: String,
+}
+
+/// A registered block
+pub struct BlockEntry {
+    pub name: &'static str,
+    pub placeholders: &'static [&'static str],
+    pub icons: &'static [&'static str],
+    pub widgets: &'static [&'static str],
+    /// The keys accepted by the block's `Config`
+    pub config_fields: fn() -> &'static [&'static str],
+    pub run: BlockRunner,
+    /// Validate the block's config without starting the block
+    pub check: fn(Value, &SharedConfig) -> Vec<Problem>,
+}
+
+impl BlockEntry {
+    /// The name given to the block with the common `name` option. Blocks with a `name` option of
+    /// their own, such as `custom_dbus`, are only known by their type and position.
+    pub fn block_name<'a>(&self, config: &'a Value) -> Option<&'a str> {
+        if (self.config_fields)().contains(&"name") {
+            return None;
+        }
+        config.get("name").and_then(Value::as_str)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -62,28 +224,66 @@ pub struct BlockMessage {
     pub widgets: Vec<I3BarBlock>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum BlockEvent {
     I3Bar(I3BarEvent),
     Signal(Signal),
+    /// Update the block now. Handled by the runtime, never passed to `handle_event`.
+    Update,
+    /// Swap the block's `format` and `format_alt`. Sent by the `toggle_format` click action to the
+    /// blocks which have a `format_alt` option of their own, and remember which one they show.
+    ToggleFormat,
 }
 
 #[derive(serde_derive::Deserialize, Debug, Clone)]
 struct CommonConfig {
+    /// A unique name to refer to the block by in the control socket, D-Bus and logs. Unlike the
+    /// position of the block, it does not change when the config is edited.
+    #[serde(default)]
+    name: Option<String>,
     #[serde(default)]
     click: ClickHandler,
+    /// Update the block when `SIGRTMIN+<signal>` is received
+    #[serde(default)]
+    signal: Option<i32>,
     #[serde(default)]
     icons_format: Option<String>,
     #[serde(default)]
     theme_overrides: Option<HashMap<String, String>>,
+    /// How long to wait before restarting a failed block. Doubled after each consecutive failure.
+    #[serde(
+        default = "CommonConfig::default_restart_delay",
+        deserialize_with = "deserialize_duration"
+    )]
+    restart_delay: Duration,
+    /// The upper bound of `restart_delay`
+    #[serde(
+        default = "CommonConfig::default_max_restart_delay",
+        deserialize_with = "deserialize_duration"
+    )]
+    max_restart_delay: Duration,
 }
 
 impl CommonConfig {
-    pub fn new(from: &mut Value) -> Result<Self> {
-        const FIELDS: &[&str] = &["click", "theme_overrides", "icons_format"];
+    const FIELDS: &'static [&'static str] = &[
+        "name",
+        "click",
+        "signal",
+        "theme_overrides",
+        "icons_format",
+        "restart_delay",
+        "max_restart_delay",
+    ];
+
+    /// Take the common options out of a block's config. The options which the block's own config
+    /// declares (`block_fields`, e.g. the bus `name` of `custom_dbus`) are left to the block.
+    pub fn new(from: &mut Value, block_fields: &[&str]) -> Result<Self> {
         let mut common_table = Table::new();
         if let Some(table) = from.as_table_mut() {
-            for &field in FIELDS {
+            for &field in Self::FIELDS {
+                if block_fields.contains(&field) {
+                    continue;
+                }
                 if let Some(it) = table.remove(field) {
                     common_table.insert(field.to_string(), it);
                 }
@@ -92,73 +292,739 @@ impl CommonConfig {
         let common_value: Value = common_table.into();
         CommonConfig::deserialize(common_value).config_error()
     }
+
+    fn default_restart_delay() -> Duration {
+        Duration::from_secs(5)
+    }
+
+    fn default_max_restart_delay() -> Duration {
+        Duration::from_secs(300)
+    }
 }
 
-pub async fn run_block(
-    id: usize,
-    block_type: BlockType,
+/// Remove `format_alt`, the format the `toggle_format` click action switches to, from the config
+/// of the blocks which don't have a `format_alt` option of their own
+fn take_format_alt<B: Block>(block_config: &mut Value) {
+    if !has_format_alt(struct_fields::<B::Config>()) {
+        if let Some(table) = block_config.as_table_mut() {
+            table.remove("format_alt");
+        }
+    }
+}
+
+/// Whether a block with these config fields switches between its `format` and `format_alt`
+/// itself. The `toggle_format` action swaps the formats of the other blocks in their config.
+pub fn has_format_alt(config_fields: &[&str]) -> bool {
+    config_fields.contains(&"format_alt")
+}
+
+/// The options accepted by every block
+pub fn common_config_fields() -> &'static [&'static str] {
+    CommonConfig::FIELDS
+}
+
+fn run_block<B: Block>(
+    identity: BlockIdentity,
+    block_config: Value,
+    shared_config: SharedConfig,
+    block_state: BlockState,
+    message_tx: mpsc::Sender<BlockMessage>,
+    events_reciever: mpsc::Receiver<BlockEvent>,
+    paused: watch::Receiver<bool>,
+) -> BoxFuture<'static, Result<()>> {
+    Box::pin(supervise_block::<B>(
+        identity,
+        block_config,
+        shared_config,
+        block_state,
+        message_tx,
+        events_reciever,
+        paused,
+    ))
+}
+
+/// Find mistakes in the block's config: invalid options, unknown placeholders in format strings and
+/// icons missing from the icon set. Does not initialize the block.
+fn check_block<B: Block>(mut block_config: Value, shared_config: &SharedConfig) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    take_format_alt::<B>(&mut block_config);
+    match CommonConfig::new(&mut block_config, struct_fields::<B::Config>()) {
+        Ok(common_config) => {
+            problems.extend(check_click_widgets::<B>(&common_config.click));
+            problems.extend(check_click_values::<B>(&common_config.click));
+            if let Some(theme_overrides) = common_config.theme_overrides {
+                let mut theme = shared_config.theme.as_ref().clone();
+                if let Err(error) = theme.apply_overrides(&theme_overrides) {
+                    problems.push(Problem::new(Some("theme_overrides"), error_cause(error)));
+                }
+            }
+        }
+        Err(error) => problems.push(Problem::new(None, error_cause(error))),
+    }
+
+    if let Some(table) = block_config.as_table_mut() {
+        problems.extend(take_unknown_options::<B>(table));
+        problems.extend(check_formats::<B>(table));
+    }
+
+    if let Err(error) = B::Config::deserialize(block_config) {
+        problems.push(Problem::new(None, error.to_string()));
+    }
+
+    for icon in B::ICONS {
+        if !shared_config.icons.0.contains_key(*icon) {
+            problems.push(Problem::new(
+                None,
+                format!("icon '{}' is missing from the icon set", icon),
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Find the click handlers bound to widgets the block doesn't have
+fn check_click_widgets<B: Block>(click: &ClickHandler) -> Vec<Problem> {
+    click
+        .widgets()
+        .filter(|widget| !B::WIDGETS.contains(widget))
+        .map(|widget| {
+            let candidates = B::WIDGETS.iter().copied();
+            Problem::new(
+                Some("click"),
+                format!(
+                    "unknown widget '{}'{}",
+                    widget,
+                    did_you_mean(widget, candidates)
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Find the placeholders and `SWAYSTATUS_VALUE_<name>` variables used by click commands which the
+/// block doesn't set
+fn check_click_values<B: Block>(click: &ClickHandler) -> Vec<Problem> {
+    let candidates = || B::PLACEHOLDERS.iter().map(|p| p.trim_end_matches('*'));
+    let placeholders = click
+        .used_placeholders()
+        .into_iter()
+        .filter(|name| !placeholder_exists(B::PLACEHOLDERS, name))
+        .map(|name| {
+            Problem::new(
+                Some("click"),
+                format!(
+                    "unknown placeholder '{}' in cmd{}",
+                    name,
+                    did_you_mean(&name, candidates())
+                ),
+            )
+        });
+    let values = click
+        .used_values()
+        .filter(|name| !placeholder_exists(B::PLACEHOLDERS, name))
+        .map(|name| {
+            Problem::new(
+                Some("click"),
+                format!(
+                    "unknown value SWAYSTATUS_VALUE_{}{}",
+                    name,
+                    did_you_mean(name, candidates())
+                ),
+            )
+        });
+    placeholders.chain(values).collect()
+}
+
+/// Remove the options which are not known to the block from its config
+fn take_unknown_options<B: Block>(table: &mut Table) -> Vec<Problem> {
+    let fields = struct_fields::<B::Config>();
+    if fields.is_empty() {
+        return Vec::new();
+    }
+
+    let unknown: Vec<String> = table
+        .keys()
+        .filter(|key| !fields.contains(&key.as_str()))
+        .cloned()
+        .collect();
+    unknown
+        .into_iter()
+        .map(|key| {
+            table.remove(&key);
+            let candidates = fields.iter().chain(CommonConfig::FIELDS).copied();
+            Problem::new(
+                Some(&key),
+                format!("unknown option{}", did_you_mean(&key, candidates)),
+            )
+        })
+        .collect()
+}
+
+/// Check that the block's format strings parse and use only the placeholders it provides. Format
+/// strings which fail to parse are replaced with empty ones, so that they are reported only once.
+fn check_formats<B: Block>(table: &mut Table) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for (key, value) in table.iter_mut().filter(|(key, _)| key.contains("format")) {
+        match FormatTemplate::deserialize(value.clone()) {
+            Ok(format) => {
+                for placeholder in format.placeholders() {
+                    if !placeholder_exists(B::PLACEHOLDERS, placeholder) {
+                        let candidates = B::PLACEHOLDERS.iter().map(|p| p.trim_end_matches('*'));
+                        problems.push(Problem::new(
+                            Some(key),
+                            format!(
+                                "unknown placeholder '{}'{}",
+                                placeholder,
+                                did_you_mean(placeholder, candidates)
+                            ),
+                        ));
+                    }
+                }
+            }
+            Err(error) => {
+                problems.push(Problem::new(Some(key), error.to_string()));
+                *value = Value::String(String::new());
+            }
+        }
+    }
+    problems
+}
+
+/// Deserialize the block's config, rejecting unknown options and placeholders
+fn deserialize_block_config<B: Block>(mut block_config: Value) -> Result<B::Config> {
+    if let Some(table) = block_config.as_table_mut() {
+        let mut problems = take_unknown_options::<B>(table);
+        problems.extend(check_formats::<B>(table));
+        if let Some(problem) = problems.into_iter().next() {
+            return Err(Error::Config {
+                block: Some(B::NAME.to_string()),
+                cause: problem.to_string(),
+                cause_dbg: format!("{:?}", problem),
+            });
+        }
+    }
+    B::Config::deserialize(block_config).block_config_error(B::NAME)
+}
+
+/// Whether `name` is one of `placeholders`, taking wildcards into account
+fn placeholder_exists(placeholders: &[&str], name: &str) -> bool {
+    placeholders.iter().any(|p| match p.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => *p == name,
+    })
+}
+
+fn error_cause(error: Error) -> String {
+    match error {
+        Error::Config { cause, .. } => cause,
+        error => error.to_string(),
+    }
+}
+
+/// Run the block, restarting it with exponential backoff if it fails. While the block is down,
+/// its slot on the bar is occupied by an error widget.
+///
+/// Configuration errors are not retried, since restarting would not fix them.
+async fn supervise_block<B: Block>(
+    identity: BlockIdentity,
     mut block_config: Value,
     mut shared_config: SharedConfig,
+    block_state: BlockState,
     message_tx: mpsc::Sender<BlockMessage>,
     mut events_reciever: mpsc::Receiver<BlockEvent>,
+    mut paused: watch::Receiver<bool>,
 ) -> Result<()> {
-    let common_config = CommonConfig::new(&mut block_config)?;
+    let BlockIdentity { id, log_target } = identity;
+    let common_config = match CommonConfig::new(&mut block_config, struct_fields::<B::Config>()) {
+        Ok(common_config) => common_config,
+        Err(error) => {
+            return send_error_widget(id, B::NAME, &error, shared_config, &message_tx).await;
+        }
+    };
+    take_format_alt::<B>(&mut block_config);
+    if let Some(problem) = check_click_widgets::<B>(&common_config.click)
+        .into_iter()
+        .next()
+    {
+        let error = Error::Config {
+            block: Some(B::NAME.to_string()),
+            cause: problem.to_string(),
+            cause_dbg: format!("{:?}", problem),
+        };
+        return send_error_widget(id, B::NAME, &error, shared_config, &message_tx).await;
+    }
 
     if let Some(icons_format) = common_config.icons_format {
-        *shared_config.icons_format.to_mut() = icons_format;
+        shared_config.icons_format = Arc::new(icons_format);
     }
     if let Some(theme_overrides) = common_config.theme_overrides {
-        shared_config
-            .theme
-            .to_mut()
-            .apply_overrides(&theme_overrides)?;
+        if let Err(error) =
+            Arc::make_mut(&mut shared_config.theme).apply_overrides(&theme_overrides)
+        {
+            return send_error_widget(id, B::NAME, &error, shared_config, &message_tx).await;
+        }
     }
     let click_handler = common_config.click;
+    let update_signal = common_config.signal;
+    let block_name = common_config.name.as_deref().unwrap_or(B::NAME).to_string();
+    // The commands still run, as they are written or with the variable unset
+    for problem in check_click_values::<B>(&click_handler) {
+        log::warn!(target: &log_target, "{}", problem);
+    }
+    // The values last shown by the block, for click commands
+    let values = Arc::new(Mutex::new(HashMap::new()));
 
-    // Spawn event handler
-    let (evets_tx, events_rx) = mpsc::channel(64);
-    tokio::spawn(async move {
-        while let Some(event) = events_reciever.recv().await {
-            if let BlockEvent::I3Bar(click) = event {
-                let update = click_handler.handle(click.button).await;
-                if !update {
-                    continue;
+    // The event handler runs in the same task as the block rather than in a task of its own, so
+    // that the click commands still running are killed as soon as the block is stopped
+    let (evets_tx, mut events_rx) = mpsc::channel(64);
+    let click_values = values.clone();
+    let click_log_target = log_target.clone();
+    let event_handler = async move {
+        while let Some(mut event) = events_reciever.recv().await {
+            match event {
+                BlockEvent::I3Bar(ref mut click) => {
+                    let values = click_values.lock().unwrap().clone();
+                    let widget = widget_name(B::WIDGETS, click.instance);
+                    click.handled = click_handler.handles(click, widget);
+                    log::debug!(
+                        target: &click_log_target,
+                        "{:?} click on instance {:?} (widget {:?})",
+                        click.button,
+                        click.instance,
+                        widget
+                    );
+                    let update = click_handler
+                        .handle(click, widget, &block_name, &values)
+                        .await;
+                    if !update {
+                        continue;
+                    }
                 }
+                BlockEvent::Signal(Signal::Custom(signal)) if Some(signal) == update_signal => {
+                    event = BlockEvent::Update;
+                }
+                _ => (),
             }
             // Reciever might be droped -- but we don't care
             let _ = evets_tx.send(event).await;
         }
-    });
-
-    use BlockType::*;
-    match block_type {
-        Backlight => backlight::run(id, block_config, shared_config, message_tx, events_rx).await,
-        Battery => battery::run(id, block_config, shared_config, message_tx, events_rx).await,
-        Cpu => cpu::run(id, block_config, shared_config, message_tx, events_rx).await,
-        Custom => custom::run(id, block_config, shared_config, message_tx, events_rx).await,
-        CustomDbus => {
-            custom_dbus::run(id, block_config, shared_config, message_tx, events_rx).await
-        }
-        DiskSpace => disk_space::run(id, block_config, shared_config, message_tx, events_rx).await,
-        FocusedWindow => {
-            focused_window::run(id, block_config, shared_config, message_tx, events_rx).await
-        }
-        Github => github::run(id, block_config, shared_config, message_tx, events_rx).await,
-        Load => load::run(id, block_config, shared_config, message_tx, events_rx).await,
-        Memory => memory::run(id, block_config, shared_config, message_tx, events_rx).await,
-        Music => music::run(id, block_config, shared_config, message_tx, events_rx).await,
-        Net => net::run(id, block_config, shared_config, message_tx, events_rx).await,
-        Pomodoro => pomodoro::run(id, block_config, shared_config, message_tx, events_rx).await,
-        Sound => sound::run(id, block_config, shared_config, message_tx, events_rx).await,
-        Speedtest => speedtest::run(id, block_config, shared_config, message_tx, events_rx).await,
-        SwayKbd => sway_kbd::run(id, block_config, shared_config, message_tx, events_rx).await,
-        Taskwarrior => {
-            taskwarrior::run(id, block_config, shared_config, message_tx, events_rx).await
-        }
-        Temperature => {
-            temperature::run(id, block_config, shared_config, message_tx, events_rx).await
-        }
-        Time => time::run(id, block_config, shared_config, message_tx, events_rx).await,
-        Weather => weather::run(id, block_config, shared_config, message_tx, events_rx).await,
+    };
+
+    let CommonConfig {
+        name,
+        restart_delay: min_restart_delay,
+        max_restart_delay,
+        ..
+    } = common_config;
+    let supervisor = async {
+        let mut restart_delay = min_restart_delay;
+        loop {
+            let started = Instant::now();
+            let result = AssertUnwindSafe(run_block_inner::<B>(
+                id,
+                block_config.clone(),
+                shared_config.clone(),
+                &block_state,
+                &values,
+                &message_tx,
+                &mut events_rx,
+                &mut paused,
+            ))
+            .catch_unwind()
+            .await;
+
+            let error = match result {
+                Ok(Ok(())) => return Ok::<(), Error>(()),
+                Ok(Err(error)) => error,
+                Err(panic) => panic_error(B::NAME, "block panicked", panic),
+            };
+            let is_config_error = matches!(error, Error::Config { .. });
+            let name = name.as_deref().unwrap_or(B::NAME);
+            log::error!(target: &log_target, "Block failed: {}", error);
+            log::debug!(target: &log_target, "{:?}", error);
+            send_error_widget(id, name, &error, shared_config.clone(), &message_tx).await?;
+            if is_config_error {
+                return Ok(());
+            }
+
+            // The block has been working for a while, so this is not a consecutive failure
+            if started.elapsed() >= max_restart_delay {
+                restart_delay = min_restart_delay;
+            }
+            log::info!(
+                target: &log_target,
+                "Restarting in {:?}",
+                restart_delay
+            );
+            // The events are dropped while the block is down, so that the bar never waits for it
+            let restart = tokio::time::sleep(restart_delay);
+            tokio::pin!(restart);
+            loop {
+                tokio::select! {
+                    _ = &mut restart => break,
+                    Some(_) = events_rx.recv() => (),
+                }
+            }
+            restart_delay = (restart_delay * 2).min(max_restart_delay);
+        }
+    };
+
+    tokio::select! {
+        result = supervisor => result,
+        result = AssertUnwindSafe(event_handler).catch_unwind() => match result {
+            // The bar has dropped the block, which is about to be stopped
+            Ok(()) => Ok(()),
+            // The block can't get events anymore, so it is stopped like after a config error
+            Err(panic) => {
+                let error = panic_error(B::NAME, "event handler panicked", panic);
+                log::error!(target: &log_target, "Block failed: {}", error);
+                let name = name.as_deref().unwrap_or(B::NAME);
+                send_error_widget(id, name, &error, shared_config.clone(), &message_tx).await
+            }
+        },
+    }
+}
+
+/// The error reported for a panic of the block or of its event handler
+fn panic_error(block: &str, message: &str, panic: Box<dyn Any + Send>) -> Error {
+    let cause = panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    Error::Block {
+        block: block.to_string(),
+        message: message.to_string(),
+        cause: Some(cause.clone()),
+        cause_dbg: Some(cause),
+    }
+}
+
+/// Replace the widgets of a failed block with a single critical widget describing the error
+async fn send_error_widget(
+    id: usize,
+    name: &str,
+    error: &Error,
+    shared_config: SharedConfig,
+    message_tx: &mpsc::Sender<BlockMessage>,
+) -> Result<()> {
+    let widget = Widget::new(id, shared_config)
+        .with_state(State::Critical)
+        .with_text((error.to_string(), Some(name.to_string())));
+    message_tx
+        .send(BlockMessage {
+            id,
+            widgets: vec![widget.get_data()],
+        })
+        .await
+        .internal_error(name, "failed to send message")
+}
+
+async fn run_block_inner<B: Block>(
+    id: usize,
+    block_config: Value,
+    shared_config: SharedConfig,
+    block_state: &BlockState,
+    values: &Mutex<HashMap<String, FormatValue>>,
+    message_tx: &mpsc::Sender<BlockMessage>,
+    events_rx: &mut mpsc::Receiver<BlockEvent>,
+    paused: &mut watch::Receiver<bool>,
+) -> Result<()> {
+    let block_config = deserialize_block_config::<B>(block_config)?;
+    let scheduler = shared_config.scheduler.clone();
+    let mut block = B::init(id, block_config, shared_config).await?;
+    if let Some(state) = block_state.get() {
+        block.restore_state(state);
+    }
+
+    loop {
+        let widgets = block.update().await?;
+        if let Some(state) = block.state() {
+            block_state.set(state);
+        }
+        *values.lock().unwrap() = block.values();
+        message_tx
+            .send(BlockMessage { id, widgets })
+            .await
+            .internal_error(B::NAME, "failed to send message")?;
+
+        let mut next_update = block
+            .interval()
+            .filter(|_| !*paused.borrow())
+            .map(|interval| scheduler.next_tick(interval));
+
+        // Wait for something that requires an update
+        loop {
+            let event = tokio::select! {
+                _ = tokio::time::sleep_until(next_update.unwrap_or_else(Instant::now)), if next_update.is_some() => break,
+                result = block.wait_for_change() => {
+                    result?;
+                    break;
+                }
+                // Stop updating on `interval` while the bar is hidden, and update as soon as it
+                // is visible again
+                Ok(()) = paused.changed() => {
+                    if *paused.borrow() {
+                        next_update = None;
+                        continue;
+                    }
+                    break;
+                }
+                Some(event) = events_rx.recv() => event,
+            };
+            match event {
+                BlockEvent::Update => break,
+                event => {
+                    if block.handle_event(event).await? {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateStore;
+
+    /// A block which fails `lifetime` seconds after being started
+    struct Failing;
+
+    #[derive(serde_derive::Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct FailingConfig {
+        lifetime: u64,
+    }
+
+    #[async_trait]
+    impl Block for Failing {
+        const NAME: &'static str = "failing";
+        type Config = FailingConfig;
+
+        async fn init(_: usize, config: FailingConfig, _: SharedConfig) -> Result<Self> {
+            tokio::time::sleep(Duration::from_secs(config.lifetime)).await;
+            block_error("failing", "broken")
+        }
+
+        async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+            Ok(Vec::new())
+        }
+    }
+
+    /// The times in seconds at which the first `count` error widgets of a `Failing` block with
+    /// `config` are shown
+    async fn failures(config: &str, count: usize) -> Vec<u64> {
+        let config = toml::from_str(config).unwrap();
+        let (message_tx, mut message_rx) = mpsc::channel(64);
+        let (_events_tx, events_rx) = mpsc::channel(64);
+        let state = StateStore::default().block("failing", None, 0);
+        let identity = BlockIdentity {
+            id: 7,
+            log_target: "block::failing:0".to_string(),
+        };
+        let block = tokio::spawn(supervise_block::<Failing>(
+            identity,
+            config,
+            SharedConfig::default(),
+            state,
+            message_tx,
+            events_rx,
+            watch::channel(false).1,
+        ));
+
+        let start = Instant::now();
+        let mut times = Vec::new();
+        for _ in 0..count {
+            let message = message_rx.recv().await.unwrap();
+            assert_eq!(message.id, 7);
+            assert_eq!(message.widgets.len(), 1);
+            assert!(message.widgets[0]
+                .full_text
+                .contains("Error in block 'failing': broken"));
+            times.push(start.elapsed().as_secs());
+        }
+        block.abort();
+        times
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restarts() {
+        // The delay is doubled after each failure, up to `max_restart_delay`
+        assert_eq!(
+            failures("lifetime = 0\nrestart_delay = 1\nmax_restart_delay = 3", 5).await,
+            [0, 1, 3, 6, 9]
+        );
+        // A block which worked for `max_restart_delay` is restarted after `restart_delay` again
+        assert_eq!(
+            failures("lifetime = 3\nrestart_delay = 1\nmax_restart_delay = 3", 3).await,
+            [3, 7, 11]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_events_dropped_while_down() {
+        let (message_tx, mut message_rx) = mpsc::channel(64);
+        let (events_tx, events_rx) = mpsc::channel(64);
+        let identity = BlockIdentity {
+            id: 0,
+            log_target: "block::failing:0".to_string(),
+        };
+        let block = tokio::spawn(supervise_block::<Failing>(
+            identity,
+            toml::from_str("lifetime = 0\nrestart_delay = 100").unwrap(),
+            SharedConfig::default(),
+            StateStore::default().block("failing", None, 0),
+            message_tx,
+            events_rx,
+            watch::channel(false).1,
+        ));
+        message_rx.recv().await.unwrap();
+
+        // Far more events than the channels hold are accepted before the block is restarted
+        let send_all = async {
+            for _ in 0..1000 {
+                events_tx.send(BlockEvent::Update).await.unwrap();
+            }
+        };
+        assert!(tokio::time::timeout(Duration::from_secs(10), send_all)
+            .await
+            .is_ok());
+        block.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_config_error_not_retried() {
+        let (message_tx, mut message_rx) = mpsc::channel(64);
+        let (_events_tx, events_rx) = mpsc::channel(64);
+        let identity = BlockIdentity {
+            id: 0,
+            log_target: "block::failing:0".to_string(),
+        };
+        let result = supervise_block::<Failing>(
+            identity,
+            toml::from_str("lifetime = 'x'\nrestart_delay = 1").unwrap(),
+            SharedConfig::default(),
+            StateStore::default().block("failing", None, 0),
+            message_tx,
+            events_rx,
+            watch::channel(false).1,
+        )
+        .await;
+        assert!(result.is_ok());
+        let message = message_rx.recv().await.unwrap();
+        assert!(message.widgets[0]
+            .full_text
+            .contains("Configuration error in block 'failing'"));
+        assert!(message_rx.recv().await.is_none());
+    }
+
+    #[test]
+    fn test_registry() {
+        assert!(BLOCKS.windows(2).all(|w| w[0].name < w[1].name));
+        assert!(find_block("cpuu").is_none());
+
+        let cpu = find_block("cpu").unwrap();
+        assert_eq!((cpu.config_fields)(), &["format", "format_alt", "interval"]);
+        assert!(cpu.placeholders.contains(&"utilization*"));
+
+        let music = find_block("music").unwrap();
+        assert_eq!(widget_name(music.widgets, Some(1)), Some("next"));
+        assert_eq!(widget_name(music.widgets, Some(3)), None);
+        assert_eq!(widget_name(music.widgets, None), None);
+    }
+
+    #[test]
+    fn test_common_config() {
+        let mut config: Value = toml::from_str("name = 'a'\nsignal = 3\nformat = 'x'").unwrap();
+        let common = CommonConfig::new(&mut config.clone(), &["format"]).unwrap();
+        assert_eq!(common.name.as_deref(), Some("a"));
+        assert_eq!(common.signal, Some(3));
+
+        // Options of the block itself are not taken
+        let common = CommonConfig::new(&mut config, &["name"]).unwrap();
+        assert_eq!(common.name, None);
+        assert_eq!(common.signal, Some(3));
+        assert_eq!(config.get("name").and_then(Value::as_str), Some("a"));
+        assert!(config.get("signal").is_none());
+
+        let config: Value = toml::from_str("name = 'my.example.block'").unwrap();
+        assert_eq!(
+            find_block("time").unwrap().block_name(&config),
+            Some("my.example.block")
+        );
+        assert_eq!(find_block("custom_dbus").unwrap().block_name(&config), None);
+    }
+
+    #[test]
+    fn test_check_block() {
+        let cpu = find_block("cpu").unwrap();
+        let check = |config: &str| {
+            let config: Value = toml::from_str(config).unwrap();
+            (cpu.check)(config, &SharedConfig::default())
+                .into_iter()
+                .map(|problem| (problem.key, problem.message))
+                .filter(|(_, message)| !message.starts_with("icon"))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(check("format = '{utilization2} {frequency}'").is_empty());
+        assert_eq!(
+            check("format = '{utilisation}'"),
+            &[(
+                Some("format".to_string()),
+                "unknown placeholder 'utilisation', did you mean 'utilization'?".to_string()
+            )]
+        );
+        assert_eq!(
+            check("format = '{utilization'")[0].0.as_deref(),
+            Some("format")
+        );
+        assert_eq!(check("interval = 'x'").len(), 1);
+        assert_eq!(
+            check("intervall = 1"),
+            &[(
+                Some("intervall".to_string()),
+                "unknown option, did you mean 'interval'?".to_string()
+            )]
+        );
+
+        // `name` is an option of custom_dbus itself
+        let custom_dbus = find_block("custom_dbus").unwrap();
+        let config = toml::from_str("name = 'my.example.block'").unwrap();
+        assert!((custom_dbus.check)(config, &SharedConfig::default()).is_empty());
+
+        let net = find_block("net").unwrap();
+        let config = toml::from_str(
+            "click = [
+                { button = 'left', cmd = 'echo $SWAYSTATUS_VALUE_sssid' },
+                { button = 'right', cmd = 'nm-connection-editor --edit {sssid}' },
+            ]",
+        )
+        .unwrap();
+        assert_eq!(
+            (net.check)(config, &SharedConfig::default()),
+            [
+                Problem::new(
+                    Some("click"),
+                    "unknown placeholder 'sssid' in cmd, did you mean 'ssid'?"
+                ),
+                Problem::new(
+                    Some("click"),
+                    "unknown value SWAYSTATUS_VALUE_sssid, did you mean 'ssid'?"
+                ),
+            ]
+        );
+
+        let music = find_block("music").unwrap();
+        let config = toml::from_str("click = [{ button = 'left', widget = 'nxet' }]").unwrap();
+        let problems = (music.check)(config, &SharedConfig::default());
+        assert!(problems.contains(&Problem::new(
+            Some("click"),
+            "unknown widget 'nxet', did you mean 'next'?"
+        )));
     }
 }
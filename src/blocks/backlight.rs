@@ -3,7 +3,8 @@
 //! This block reads brightness information directly from the filesystem, so it works under both
 //! X11 and Wayland. The block uses `inotify` to listen for changes in the device's brightness
 //! directly, so there is no need to set an update interval. This block uses DBus to set brightness
-//! level using the mouse wheel.
+//! level using the mouse wheel, or to the position of a left click on the widget (clicking at 70%
+//! of its width sets 70%).
 //!
 //! # Root scaling
 //!
@@ -35,20 +36,20 @@ use std::convert::TryInto;
 use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
 
-use inotify::{Inotify, WatchMask};
-use serde::de::Deserialize;
+use async_trait::async_trait;
+use inotify::{EventStream, Inotify, WatchMask};
 use serde_derive::Deserialize;
 use tokio::fs::read_dir;
-use tokio::sync::mpsc;
 use tokio_stream::StreamExt;
 
-use crate::blocks::{BlockEvent, BlockMessage};
+use crate::blocks::{Block, BlockEvent};
 use crate::click::MouseButton;
 use crate::config::SharedConfig;
+use crate::dbus_connection::DbusConnections;
 use crate::errors::{OptionExt, Result, ResultExt};
-use crate::util::read_file;
+use crate::protocol::i3bar_block::I3BarBlock;
+use crate::util::{read_file, sys_path};
 use crate::widget::Widget;
 
 /// Location of backlight devices
@@ -121,25 +122,17 @@ pub struct BacklitDevice {
     device_path: PathBuf,
     max_brightness: u64,
     root_scaling: f64,
-    dbus_proxy: dbus::nonblock::Proxy<'static, Arc<dbus::nonblock::SyncConnection>>,
+    /// Only used to set the brightness
+    dbus: Arc<DbusConnections>,
 }
 
 impl BacklitDevice {
-    fn new(max_brightness: u64, device_path: PathBuf, root_scaling: f64) -> Result<Self> {
-        let (ressource, dbus_conn) = dbus_tokio::connection::new_system_sync()
-            .block_error("backlight", "Failed to open dbus connection")?;
-
-        tokio::spawn(async move {
-            let err = ressource.await;
-            panic!("Lost connection to D-Bus: {}", err);
-        });
-
-        let dbus_proxy = dbus::nonblock::Proxy::new(
-            "org.freedesktop.login1",
-            "/org/freedesktop/login1/session/auto",
-            Duration::from_secs(2),
-            dbus_conn,
-        );
+    fn new(
+        max_brightness: u64,
+        device_path: PathBuf,
+        root_scaling: f64,
+        dbus: Arc<DbusConnections>,
+    ) -> Result<Self> {
 
         Ok(Self {
             max_brightness,
@@ -151,19 +144,23 @@ impl BacklitDevice {
                     ROOT_SCALDING_RANGE.end
                 }
             },
-            dbus_proxy,
+            dbus,
         })
     }
 
-    async fn from_path(device_path: PathBuf, root_scaling: f64) -> Result<Self> {
+    async fn from_path(
+        device_path: PathBuf,
+        root_scaling: f64,
+        dbus: Arc<DbusConnections>,
+    ) -> Result<Self> {
         let max_brightness = read_brightness_raw(&device_path.join(FILE_MAX_BRIGHTNESS)).await?;
-        Self::new(max_brightness, device_path, root_scaling)
+        Self::new(max_brightness, device_path, root_scaling, dbus)
     }
 
     /// Use the default backlit device, i.e. the first one found in the
     /// `/sys/class/backlight` directory.
-    pub async fn default(root_scaling: f64) -> Result<Self> {
-        let device = read_dir(DEVICES_PATH)
+    pub async fn default(root_scaling: f64, dbus: Arc<DbusConnections>) -> Result<Self> {
+        let device = read_dir(sys_path(DEVICES_PATH))
             .await
             .block_error("backlight", "Failed to read backlight device directory")?
             .next_entry()
@@ -171,13 +168,22 @@ impl BacklitDevice {
             .block_error("backlight", "No backlit devices found")?
             .block_error("backlight", "Failed to read default device file")?;
 
-        Self::from_path(device.path(), root_scaling).await
+        Self::from_path(device.path(), root_scaling, dbus).await
     }
 
     /// Use the backlit device `device`. Returns an error if a directory for
     /// that device is not found.
-    pub async fn from_device(device: &str, root_scaling: f64) -> Result<Self> {
-        Self::from_path(Path::new(DEVICES_PATH).join(device), root_scaling).await
+    pub async fn from_device(
+        device: &str,
+        root_scaling: f64,
+        dbus: Arc<DbusConnections>,
+    ) -> Result<Self> {
+        Self::from_path(
+            sys_path(DEVICES_PATH).join(device),
+            root_scaling,
+            dbus,
+        )
+        .await
     }
 
     /// Query the brightness value for this backlit device, as a percent.
@@ -209,7 +215,12 @@ impl BacklitDevice {
             .and_then(|x| x.to_str())
             .block_error("backlight", "Malformed device path")?;
 
-        self.dbus_proxy
+        self.dbus
+            .system()?
+            .proxy(
+                "org.freedesktop.login1",
+                "/org/freedesktop/login1/session/auto",
+            )
             .method_call(
                 "org.freedesktop.login1.Session",
                 "SetBrightness",
@@ -233,80 +244,100 @@ impl BacklitDevice {
     }
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    mut events_receiver: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    let block_config =
-        BacklightConfig::deserialize(block_config).block_config_error("backlight")?;
-
-    let device = match &block_config.device {
-        None => BacklitDevice::default(block_config.root_scaling).await?,
-        Some(path) => BacklitDevice::from_device(path, block_config.root_scaling).await?,
-    };
-
-    // Render and send widget
-    let update = || async {
-        let brightness = device.brightness().await?;
+pub struct Backlight {
+    widget: Widget,
+    device: BacklitDevice,
+    step_width: u8,
+    invert_icons: bool,
+    file_changes: EventStream<[u8; 1024]>,
+}
+
+#[async_trait]
+impl Block for Backlight {
+    const NAME: &'static str = "backlight";
+    const ICONS: &'static [&'static str] = BACKLIGHT_ICONS;
+
+    type Config = BacklightConfig;
+
+    async fn init(
+        id: usize,
+        block_config: BacklightConfig,
+        shared_config: SharedConfig,
+    ) -> Result<Self> {
+        let dbus = shared_config.dbus.clone();
+        let device = match &block_config.device {
+            None => BacklitDevice::default(block_config.root_scaling, dbus).await?,
+            Some(path) => {
+                BacklitDevice::from_device(path, block_config.root_scaling, dbus).await?
+            }
+        };
+
+        // Watch for brightness changes
+        let mut notify = Inotify::init().block_error("backlight", "Failed to start inotify")?;
+
+        notify
+            .add_watch(device.brightness_file(), WatchMask::MODIFY)
+            .block_error("backlight", "Failed to watch brightness file")?;
+
+        let file_changes = notify
+            .event_stream([0; 1024])
+            .block_error("backlight", "Failed to create event stream")?;
+
+        Ok(Self {
+            widget: Widget::new(id, shared_config),
+            device,
+            step_width: block_config.step_width,
+            invert_icons: block_config.invert_icons,
+            file_changes,
+        })
+    }
+
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        let brightness = self.device.brightness().await?;
         let mut icon_index = (usize::from(brightness) * BACKLIGHT_ICONS.len()) / 101;
 
-        if block_config.invert_icons {
+        if self.invert_icons {
             icon_index = BACKLIGHT_ICONS.len() - icon_index;
         }
 
-        let widget = Widget::new(id, shared_config.clone())
-            .with_full_text(format!("{}%", brightness)) // TODO use format string
-            .with_icon(BACKLIGHT_ICONS[icon_index])?
-            .get_data();
+        self.widget.set_full_text(format!("{}%", brightness)); // TODO use format string
+        self.widget.set_icon(BACKLIGHT_ICONS[icon_index])?;
 
-        message_sender
-            .send(BlockMessage {
-                id,
-                widgets: vec![widget],
-            })
-            .await
-            .internal_error("backlight", "failed to send message")?;
+        Ok(vec![self.widget.get_data()])
+    }
 
-        Ok(())
-    };
-
-    // Initial block value
-    update().await?;
-
-    // Watch for brightness changes
-    let mut notify = Inotify::init().block_error("backlight", "Failed to start inotify")?;
-    let mut buffer = [0; 1024];
-
-    notify
-        .add_watch(device.brightness_file(), WatchMask::MODIFY)
-        .block_error("backlight", "Failed to watch brightness file")?;
-
-    let mut file_changes = notify
-        .event_stream(&mut buffer)
-        .block_error("backlight", "Failed to create event stream")?;
-
-    loop {
-        tokio::select! {
-            _ = file_changes.next() => update().await?,
-            Some(BlockEvent::I3Bar(event)) = events_receiver.recv() => {
-                let brightness = device.brightness().await?;
-                match event.button {
-                    MouseButton::WheelUp => {
-                        device
-                            .set_brightness(brightness + block_config.step_width)
-                            .await?;
-                    }
-                    MouseButton::WheelDown => {
-                        device
-                            .set_brightness(brightness.saturating_sub(block_config.step_width))
+    async fn handle_event(&mut self, event: BlockEvent) -> Result<bool> {
+        if let BlockEvent::I3Bar(event) = event {
+            let brightness = self.device.brightness().await?;
+            match event.button {
+                MouseButton::WheelUp => {
+                    self.device
+                        .set_brightness(brightness + self.step_width)
+                        .await?;
+                }
+                MouseButton::WheelDown => {
+                    self.device
+                        .set_brightness(brightness.saturating_sub(self.step_width))
+                        .await?;
+                }
+                // Set the brightness to where the text was clicked, unless the click is bound in
+                // the config
+                MouseButton::Left if !event.handled => {
+                    if let Some(position) = self.widget.text_position(&event) {
+                        self.device
+                            .set_brightness((position * 100.0).round() as u8)
                             .await?;
                     }
-                    _ => (),
                 }
+                _ => (),
             }
         }
+        // The brightness file will be modified, so don't update here
+        Ok(false)
+    }
+
+    async fn wait_for_change(&mut self) -> Result<()> {
+        self.file_changes.next().await;
+        Ok(())
     }
 }
@@ -30,6 +30,7 @@
 //! # TODO
 //! - Refactor
 
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
@@ -38,20 +39,19 @@ use std::time::Duration;
 
 use async_trait::async_trait;
 use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
-use futures::StreamExt;
-use serde::de::Deserialize;
 use serde_derive::Deserialize;
 use tokio::fs::{read_dir, read_to_string};
-use tokio::sync::mpsc;
 use tokio::time::{Instant, Interval};
 
-use crate::blocks::{BlockEvent, BlockMessage};
+use crate::blocks::Block;
 use crate::config::SharedConfig;
+use crate::dbus_connection::{DbusConnection, Subscription};
 use crate::de::deserialize_duration;
 use crate::errors::*;
 use crate::formatting::value::Value;
 use crate::formatting::FormatTemplate;
-use crate::util::read_file;
+use crate::protocol::i3bar_block::I3BarBlock;
+use crate::util::{read_file, sys_path};
 use crate::widget::{Spacing, State, Widget};
 
 /// Path for the power supply devices
@@ -75,12 +75,11 @@ const BATTERY_UNAVAILABLE_ICON: &str = "bat_not_available";
 const UPOWER_DBUS_NAME: &str = "org.freedesktop.UPower";
 const UPOWER_DBUS_ROOT_INTERFACE: &str = "org.freedesktop.UPower";
 const UPOWER_DBUS_DEVICE_INTERFACE: &str = "org.freedesktop.UPower.Device";
-const UPOWER_DBUS_PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
 const UPOWER_DBUS_ROOT_PATH: &str = "/org/freedesktop/UPower";
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields, default)]
-struct BatteryConfig {
+pub struct BatteryConfig {
     device: Option<String>,
     driver: BatteryDriver,
     #[serde(deserialize_with = "deserialize_duration")]
@@ -146,6 +145,8 @@ trait BatteryDevice {
     async fn usage(&self) -> Result<f64>;
     async fn status(&self) -> Result<BatteryStatus>;
     async fn time_remaining(&self) -> Result<u64>;
+
+    /// Resolve when the device's properties might have changed. Must be cancel-safe.
     async fn wait_for_change(&mut self) -> Result<()>;
 }
 
@@ -195,7 +196,7 @@ impl PowerSupplyDevice {
         let interval = tokio::time::interval_at(Instant::now() + interval, interval);
 
         Self {
-            device_path: Path::new(POWER_SUPPLY_DEVICES_PATH).join(device),
+            device_path: sys_path(POWER_SUPPLY_DEVICES_PATH).join(device),
             interval,
         }
     }
@@ -304,34 +305,21 @@ impl BatteryDevice for PowerSupplyDevice {
 // ---
 
 pub struct UPowerDevice {
-    dbus_conn: Arc<dbus::nonblock::SyncConnection>,
     dbus_proxy: dbus::nonblock::Proxy<'static, Arc<dbus::nonblock::SyncConnection>>,
-    device_path: dbus::Path<'static>,
+    signals: Subscription,
 }
 
 impl UPowerDevice {
-    async fn from_device(device: &str) -> Result<Self> {
-        let (ressource, dbus_conn) = dbus_tokio::connection::new_system_sync()
-            .block_error("battery", "Failed to open dbus connection")?;
-
-        tokio::spawn(async move {
-            let err = ressource.await;
-            panic!("Lost connection to D-Bus: {}", err);
-        });
-
+    async fn from_device(device: &str, dbus_conn: DbusConnection) -> Result<Self> {
         // Fetch device name
         let device_path = {
             if device == "DisplayDevice" {
                 format!("{}/devices/DisplayDevice", UPOWER_DBUS_ROOT_PATH).into()
             } else {
                 let (paths,): (Vec<dbus::Path>,) = {
-                    dbus::nonblock::Proxy::new(
-                        UPOWER_DBUS_NAME,
-                        UPOWER_DBUS_ROOT_PATH,
-                        Duration::from_secs(2),
-                        dbus_conn.clone(),
-                    )
-                    .method_call(UPOWER_DBUS_ROOT_INTERFACE, "EnumerateDevices", ())
+                    dbus_conn
+                        .proxy(UPOWER_DBUS_NAME, UPOWER_DBUS_ROOT_PATH)
+                        .method_call(UPOWER_DBUS_ROOT_INTERFACE, "EnumerateDevices", ())
                     .await
                     .block_error("battery", "Failed to retrieve DBus devices")?
                 };
@@ -343,12 +331,7 @@ impl UPowerDevice {
             }
         };
 
-        let dbus_proxy = dbus::nonblock::Proxy::new(
-            UPOWER_DBUS_NAME,
-            device_path.clone(),
-            Duration::from_secs(2),
-            dbus_conn.clone(),
-        );
+        let dbus_proxy = dbus_conn.proxy(UPOWER_DBUS_NAME, device_path.clone());
 
         // Verify device name
         let upower_type: u32 = dbus_proxy
@@ -362,10 +345,12 @@ impl UPowerDevice {
             return block_error("battery", "UPower device is not a battery.");
         }
 
+        // Setup signal monitoring
+        let signals = dbus_conn.properties_changed(device_path).await?;
+
         Ok(Self {
-            dbus_conn,
             dbus_proxy,
-            device_path,
+            signals,
         })
     }
 }
@@ -431,30 +416,8 @@ impl BatteryDevice for UPowerDevice {
     }
 
     async fn wait_for_change(&mut self) -> Result<()> {
-        // Setup signal monitoring
-        let mut match_rule = dbus::message::MatchRule::new_signal(
-            UPOWER_DBUS_PROPERTIES_INTERFACE,
-            "PropertiesChanged",
-        );
-
-        match_rule.path.replace(self.device_path.clone());
-
-        let (incoming_signal, mut stream) = self
-            .dbus_conn
-            .add_match(match_rule)
-            .await
-            .block_error("battery", "Failed to add D-Bus match rule.")?
-            .msg_stream();
-
         // Wait for signal
-        stream.next().await;
-
-        // Release match rule
-        self.dbus_conn
-            .remove_match(incoming_signal.token())
-            .await
-            .block_error("battery", "Failed to remove D-Bus match rule.")?;
-
+        self.signals.next().await?;
         Ok(())
     }
 }
@@ -465,7 +428,7 @@ impl BatteryDevice for UPowerDevice {
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "lowercase")]
-enum BatteryDriver {
+pub enum BatteryDriver {
     Sysfs,
     Upower,
 }
@@ -476,81 +439,93 @@ impl Default for BatteryDriver {
     }
 }
 
-pub async fn run(
+pub struct Battery {
     id: usize,
-    block_config: toml::Value,
     shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    events_receiver: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    drop(events_receiver);
-    let block_config = BatteryConfig::deserialize(block_config).block_config_error("battery")?;
-
-    let format = block_config.format.clone().or_default("{percentage}")?;
-    let format_full = block_config.full_format.clone().or_default("")?;
-    let format_missing = block_config
-        .missing_format
-        .clone()
-        .or_default("{percentage}")?;
-
-    // Get _any_ battery device if not set in the config
-    let device = match block_config.device {
-        Some(d) => d,
-        None => {
-            let mut sysfs_dir = read_dir("/sys/class/power_supply")
-                .await
-                .block_error("battery", "failed to read /sys/class/power_supply direcory")?;
-            let mut device = None;
-            while let Some(dir) = sysfs_dir
-                .next_entry()
-                .await
-                .block_error("battery", "failed to read /sys/class/power_supply direcory")?
-            {
-                if read_to_string(dir.path().join("type"))
+    device: Box<dyn BatteryDevice + Send + Sync>,
+    format: FormatTemplate,
+    format_full: FormatTemplate,
+    format_missing: FormatTemplate,
+    block_config: BatteryConfig,
+    values: HashMap<String, Value>,
+}
+
+#[async_trait]
+impl Block for Battery {
+    const NAME: &'static str = "battery";
+    const PLACEHOLDERS: &'static [&'static str] = &["percentage", "time", "power"];
+    const ICONS: &'static [&'static str] = &[
+        "bat_empty",
+        "bat_quarter",
+        "bat_half",
+        "bat_three_quarters",
+        "bat_full",
+        "bat_not_available",
+    ];
+
+    type Config = BatteryConfig;
+
+    async fn init(id: usize, block_config: BatteryConfig, shared_config: SharedConfig) -> Result<Self> {
+        let format = block_config.format.clone().or_default("{percentage}")?;
+        let format_full = block_config.full_format.clone().or_default("")?;
+        let format_missing = block_config
+            .missing_format
+            .clone()
+            .or_default("{percentage}")?;
+
+        // Get _any_ battery device if not set in the config
+        let device = match &block_config.device {
+            Some(d) => d.clone(),
+            None => {
+                let mut sysfs_dir = read_dir(sys_path(POWER_SUPPLY_DEVICES_PATH))
                     .await
-                    .map(|t| t.trim() == "Battery")
-                    .unwrap_or(false)
+                    .block_error("battery", "failed to read /sys/class/power_supply direcory")?;
+                let mut device = None;
+                while let Some(dir) = sysfs_dir
+                    .next_entry()
+                    .await
+                    .block_error("battery", "failed to read /sys/class/power_supply direcory")?
                 {
-                    device = Some(dir.file_name().to_str().unwrap().to_string());
-                    break;
+                    if read_to_string(dir.path().join("type"))
+                        .await
+                        .map(|t| t.trim() == "Battery")
+                        .unwrap_or(false)
+                    {
+                        device = Some(dir.file_name().to_str().unwrap().to_string());
+                        break;
+                    }
                 }
+                device.block_error("battery", "failed to determine default battery - please set your battery device in the configuration file")?
             }
-            device.block_error("battery", "failed to determine default battery - please set your battery device in the configuration file")?
-        }
-    };
-
-    let mut device: Box<dyn BatteryDevice + Send> = match block_config.driver {
-        BatteryDriver::Sysfs => Box::new(PowerSupplyDevice::from_device(
-            &device,
-            block_config.interval,
-        )),
-        BatteryDriver::Upower => Box::new(UPowerDevice::from_device(&device).await?),
-    };
-
-    macro_rules! send_widget {
-        () => {
-            message_sender
-                .send(BlockMessage {
-                    id,
-                    widgets: vec![],
-                })
-                .await
-                .internal_error("backlight", "failed to send message")?;
-            device.wait_for_change().await?;
         };
-        ($widget:ident) => {
-            message_sender
-                .send(BlockMessage {
-                    id,
-                    widgets: vec![$widget.get_data()],
-                })
-                .await
-                .internal_error("backlight", "failed to send message")?;
-            device.wait_for_change().await?;
+
+        let device: Box<dyn BatteryDevice + Send + Sync> = match block_config.driver {
+            BatteryDriver::Sysfs => Box::new(PowerSupplyDevice::from_device(
+                &device,
+                block_config.interval,
+            )),
+            BatteryDriver::Upower => Box::new(
+                UPowerDevice::from_device(&device, shared_config.dbus.system()?).await?,
+            ),
         };
+
+        Ok(Self {
+            id,
+            shared_config,
+            device,
+            format,
+            format_full,
+            format_missing,
+            block_config,
+            values: HashMap::new(),
+        })
     }
 
-    loop {
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        let id = self.id;
+        let block_config = &self.block_config;
+        let device = &self.device;
+
         let (is_available, status, capacity, time, power) = tokio::join!(
             device.is_available(),
             device.status(),
@@ -560,28 +535,22 @@ pub async fn run(
         );
 
         let fmt = match status {
-            Err(_) if block_config.hide_missing => {
-                send_widget!();
-                continue;
-            }
-            Err(_) => &format_missing,
-            Ok(BatteryStatus::Full) if block_config.hide_full => {
-                send_widget!();
-                continue;
-            }
-            Ok(BatteryStatus::Full) => &format_full,
-            Ok(_) => &format,
+            Err(_) if block_config.hide_missing => return Ok(vec![]),
+            Err(_) => &self.format_missing,
+            Ok(BatteryStatus::Full) if block_config.hide_full => return Ok(vec![]),
+            Ok(BatteryStatus::Full) => &self.format_full,
+            Ok(_) => &self.format,
         };
 
         let vars = {
             if !is_available && block_config.allow_missing {
-                map! {
+                map_to_owned! {
                     "percentage" => Value::from_string("X".to_string()),
                     "time" => Value::from_string("xx:xx".to_string()),
                     "power" => Value::from_string("N/A".to_string()),
                 }
             } else {
-                map! {
+                map_to_owned! {
                     "percentage" => capacity.clone()
                         .map(|c| Value::from_integer(c as i64).percents())
                         .unwrap_or_else(|_| Value::from_string("×".to_string())),
@@ -605,6 +574,7 @@ pub async fn run(
             }
         };
 
+        let shared_config = &self.shared_config;
         let widget = match (
             status.unwrap_or_default(),
             capacity.ok().map(|c| c.clamp(0, 100)),
@@ -646,7 +616,16 @@ pub async fn run(
                 .with_state(State::Warning)
                 .with_spacing(Spacing::Hidden),
         };
+        self.values = vars;
+
+        Ok(vec![widget.get_data()])
+    }
+
+    async fn wait_for_change(&mut self) -> Result<()> {
+        self.device.wait_for_change().await
+    }
 
-        send_widget!(widget);
+    fn values(&self) -> HashMap<String, Value> {
+        self.values.clone()
     }
 }
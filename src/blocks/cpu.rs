@@ -1,23 +1,19 @@
-use std::path::Path;
-use std::str::FromStr;
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::sync::mpsc;
 
-use serde::de::Deserialize;
+use async_trait::async_trait;
+use serde_json::Value as JsonValue;
 
-use super::{BlockEvent, BlockMessage};
+use super::{Block, BlockEvent};
 use crate::click::MouseButton;
 use crate::config::SharedConfig;
 use crate::errors::*;
 use crate::formatting::{value::Value, FormatTemplate};
-use crate::util::read_file;
+use crate::protocol::i3bar_block::I3BarBlock;
+use crate::sampler::{ProcStat, Sampler};
 use crate::widget::{State, Widget};
 
-const CPU_BOOST_PATH: &str = "/sys/devices/system/cpu/cpufreq/boost";
-const CPU_NO_TURBO_PATH: &str = "/sys/devices/system/cpu/intel_pstate/no_turbo";
-
 #[derive(serde_derive::Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields, default)]
 pub struct CpuConfig {
@@ -36,45 +32,65 @@ impl Default for CpuConfig {
     }
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    mut events_reciever: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    let block_config = CpuConfig::deserialize(block_config).block_config_error("cpu")?;
-    let mut format = block_config.format.or_default("{utilization}")?;
-    let mut format_alt = block_config.format_alt;
-
-    let boost_icon_on = shared_config.get_icon("cpu_boost_on")?;
-    let boost_icon_off = shared_config.get_icon("cpu_boost_off")?;
-
-    let mut text = Widget::new(id, shared_config).with_icon("cpu")?;
-    let interval = Duration::from_secs(block_config.interval);
-
+pub struct Cpu {
+    text: Widget,
+    format: FormatTemplate,
+    format_alt: Option<FormatTemplate>,
+    /// Whether `format` and `format_alt` have been swapped
+    showing_alt: bool,
+    interval: Duration,
+    boost_icon_on: String,
+    boost_icon_off: String,
+    sampler: Arc<Sampler>,
     // Store previous /proc/stat state
-    let mut cputime = read_proc_stat().await?;
-    let cores = cputime.1.len();
+    cputime: Arc<ProcStat>,
+    values: HashMap<String, Value>,
+}
+
+#[async_trait]
+impl Block for Cpu {
+    const NAME: &'static str = "cpu";
+    const PLACEHOLDERS: &'static [&'static str] =
+        &["barchart", "boost", "frequency*", "utilization*"];
+    const ICONS: &'static [&'static str] = &["cpu", "cpu_boost_on", "cpu_boost_off"];
+
+    type Config = CpuConfig;
+
+    async fn init(id: usize, block_config: CpuConfig, shared_config: SharedConfig) -> Result<Self> {
+        Ok(Self {
+            format: block_config.format.or_default("{utilization}")?,
+            format_alt: block_config.format_alt,
+            showing_alt: false,
+            interval: Duration::from_secs(block_config.interval),
+            boost_icon_on: shared_config.get_icon("cpu_boost_on")?,
+            boost_icon_off: shared_config.get_icon("cpu_boost_off")?,
+            sampler: shared_config.sampler.clone(),
+            cputime: shared_config.sampler.proc_stat().await?,
+            text: Widget::new(id, shared_config).with_icon("cpu")?,
+            values: HashMap::new(),
+        })
+    }
 
-    loop {
-        let freqs = read_frequencies().await?;
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        let cpuinfo = self.sampler.cpuinfo().await?;
+        let freqs = &cpuinfo.frequencies;
         let freq_avg = freqs.iter().sum::<f64>() / (freqs.len() as f64);
 
         // Compute utilizations
-        let new_cputime = read_proc_stat().await?;
-        let utilization_avg = new_cputime.0.utilization(cputime.0);
+        let new_cputime = self.sampler.proc_stat().await?;
+        let utilization_avg = new_cputime.total.utilization(self.cputime.total);
+        let cores = self.cputime.cpus.len();
         let mut utilizations = Vec::new();
-        if new_cputime.1.len() != cores {
+        if new_cputime.cpus.len() != cores {
             return block_error("cpu", "new cputime length is incorrect");
         }
         for i in 0..cores {
-            utilizations.push(new_cputime.1[i].utilization(cputime.1[i]));
+            utilizations.push(new_cputime.cpus[i].utilization(self.cputime.cpus[i]));
         }
-        cputime = new_cputime;
+        self.cputime = new_cputime;
 
         // Set state
-        text.set_state(match utilization_avg {
+        self.text.set_state(match utilization_avg {
             x if x > 0.9 => State::Critical,
             x if x > 0.6 => State::Warning,
             x if x > 0.3 => State::Info,
@@ -89,9 +105,9 @@ pub async fn run(
         }
 
         // Read boot state on intel CPUs
-        let boost = match boost_status().await {
-            Some(true) => &boost_icon_on,
-            Some(false) => &boost_icon_off,
+        let boost = match self.sampler.cpu_boost().await {
+            Some(true) => &self.boost_icon_on,
+            Some(false) => &self.boost_icon_off,
             _ => "",
         };
 
@@ -114,129 +130,52 @@ pub async fn run(
             );
         }
 
-        text.set_text(format.render(&values)?);
+        self.text.set_text(self.format.render(&values)?);
+        self.values = values;
 
-        message_sender
-            .send(BlockMessage {
-                id,
-                widgets: vec![text.get_data()],
-            })
-            .await
-            .internal_error("cpu", "failed to send message")?;
+        Ok(vec![self.text.get_data()])
+    }
 
-        tokio::select! {
-            _ = tokio::time::sleep(interval) => (),
-            Some(BlockEvent::I3Bar(click)) = events_reciever.recv() => {
+    async fn handle_event(&mut self, event: BlockEvent) -> Result<bool> {
+        match event {
+            BlockEvent::I3Bar(click) => {
                 if click.button == MouseButton::Left {
-                    if let Some(ref mut format_alt) = format_alt {
-                        std::mem::swap(format_alt, &mut format);
-                    }
+                    self.toggle_format();
                 }
+                Ok(true)
             }
+            BlockEvent::ToggleFormat => {
+                self.toggle_format();
+                Ok(true)
+            }
+            _ => Ok(false),
         }
     }
-}
-
-// Read frequencies (read in MHz, store in Hz)
-async fn read_frequencies() -> Result<Vec<f64>> {
-    let mut freqs = Vec::with_capacity(32);
-
-    let file = File::open("/proc/cpuinfo")
-        .await
-        .block_error("cpu", "failed to read /proc/cpuinfo")?;
-    let mut file = BufReader::new(file);
-
-    let mut line = String::new();
-    while file
-        .read_line(&mut line)
-        .await
-        .block_error("cpu", "failed to read /proc/cpuinfo")?
-        != 0
-    {
-        if line.starts_with("cpu MHz") {
-            let slice = line
-                .trim_end()
-                .trim_start_matches(|c: char| !c.is_digit(10));
-            freqs.push(
-                f64::from_str(slice).block_error("cpu", "failed to parse /proc/cpuinfo")? * 1e6,
-            );
-        }
-        line.clear();
-    }
 
-    Ok(freqs)
-}
-
-#[derive(Debug, Clone, Copy)]
-struct CpuTime {
-    idle: u64,
-    non_idle: u64,
-}
-
-impl CpuTime {
-    fn from_str(s: &str) -> Option<Self> {
-        let mut s = s.trim().split_ascii_whitespace();
-        let user = u64::from_str(s.next()?).ok()?;
-        let nice = u64::from_str(s.next()?).ok()?;
-        let system = u64::from_str(s.next()?).ok()?;
-        let idle = u64::from_str(s.next()?).ok()?;
-        let iowait = u64::from_str(s.next()?).ok()?;
-        let irq = u64::from_str(s.next()?).ok()?;
-        let softirq = u64::from_str(s.next()?).ok()?;
-
-        Some(Self {
-            idle: idle + iowait,
-            non_idle: user + nice + system + irq + softirq,
-        })
+    fn interval(&self) -> Option<Duration> {
+        Some(self.interval)
     }
 
-    fn utilization(&self, old: Self) -> f64 {
-        let elapsed = (self.idle + self.non_idle) as f64 - (old.idle + old.non_idle) as f64;
-        ((self.non_idle - old.non_idle) as f64 / elapsed).clamp(0., 1.)
+    fn state(&self) -> Option<JsonValue> {
+        Some(JsonValue::Bool(self.showing_alt))
     }
-}
 
-async fn read_proc_stat() -> Result<(CpuTime, Vec<CpuTime>)> {
-    let mut utilizations = Vec::with_capacity(32);
-    let mut total = None;
-
-    let file = File::open("/proc/stat")
-        .await
-        .block_error("cpu", "failed to read /proc/stat")?;
-    let mut file = BufReader::new(file);
-
-    let mut line = String::new();
-    while file
-        .read_line(&mut line)
-        .await
-        .block_error("cpu", "failed to read /proc/sta")?
-        != 0
-    {
-        // Total time
-        let data = line.trim_start_matches(|c: char| !c.is_ascii_whitespace());
-        if line.starts_with("cpu ") {
-            total = Some(CpuTime::from_str(data).block_error("cpu", "failed to parse /proc/stat")?);
-        } else if line.starts_with("cpu") {
-            utilizations
-                .push(CpuTime::from_str(data).block_error("cpu", "failed to parse /proc/stat")?);
+    fn restore_state(&mut self, state: JsonValue) {
+        if state == JsonValue::Bool(true) {
+            self.toggle_format();
         }
-        line.clear();
     }
 
-    Ok((
-        total.block_error("cpu", "failed to parse /proc/stat")?,
-        utilizations,
-    ))
+    fn values(&self) -> HashMap<String, Value> {
+        self.values.clone()
+    }
 }
 
-/// Read the cpu turbo boost status from kernel sys interface
-/// or intel pstate interface
-async fn boost_status() -> Option<bool> {
-    if let Ok(boost) = read_file(Path::new(CPU_BOOST_PATH)).await {
-        Some(boost.starts_with('1'))
-    } else if let Ok(no_turbo) = read_file(Path::new(CPU_NO_TURBO_PATH)).await {
-        Some(no_turbo.starts_with('0'))
-    } else {
-        None
+impl Cpu {
+    fn toggle_format(&mut self) {
+        if let Some(ref mut format_alt) = self.format_alt {
+            std::mem::swap(format_alt, &mut self.format);
+            self.showing_alt = !self.showing_alt;
+        }
     }
 }
@@ -15,7 +15,6 @@
 //! `interval` | Update interval in seconds | No | `10`
 //! `one_shot` | Whether to run the command only once (if set to `true`, `interval` will be ignored) | No | `false`
 //! `json` | Use JSON from command output to format the block. If the JSON is not valid, the block will error out. | No | `false`
-//! `signal` | Signal value that causes an update for this block with 0 corresponding to `-SIGRTMIN+0` and the largest value being `-SIGRTMAX` | No | None
 //! `hide_when_empty` | Hides the block when the command output (or json text field) is empty | No | false
 //! `shell` | Specify the shell to use when running commands | No | `$SHELL` if set, otherwise fallback to `sh`
 //!
@@ -56,7 +55,8 @@
 //! one_shot = true
 //! ```
 //!
-//! Display the screen brightness on an intel machine and update this only when `pkill -SIGRTMIN+4 i3status-rs` is called:
+//! Display the screen brightness on an intel machine and update this only when `pkill -SIGRTMIN+4 swaystatus` is called
+//! (`signal` is an option common to all blocks):
 //!
 //! ```toml
 //! [[block]]
@@ -66,17 +66,18 @@
 //! one_shot = true
 //! ```
 
-use serde::de::Deserialize;
 use std::collections::HashMap;
 use std::env;
 use std::time::Duration;
 use tokio::process::Command;
-use tokio::sync::mpsc;
 
-use super::{BlockEvent, BlockMessage};
+use async_trait::async_trait;
+
+use super::Block;
 use crate::config::SharedConfig;
 use crate::errors::*;
-use crate::signals::Signal;
+use crate::protocol::i3bar_block::I3BarBlock;
+use crate::subprocess;
 use crate::widget::{State, Widget};
 
 #[derive(serde_derive::Deserialize, Debug, Clone)]
@@ -89,7 +90,6 @@ pub struct CustomConfig {
     hide_when_empty: bool,
     shell: Option<String>,
     one_shot: bool,
-    signal: Option<i32>,
 }
 
 impl Default for CustomConfig {
@@ -102,101 +102,104 @@ impl Default for CustomConfig {
             hide_when_empty: false,
             shell: None,
             one_shot: false,
-            signal: None,
         }
     }
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    mut events_reciever: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    let block_config = CustomConfig::deserialize(block_config).block_config_error("custom")?;
-    let CustomConfig {
-        command,
-        cycle,
-        interval,
-        json,
-        hide_when_empty,
-        shell,
-        one_shot,
-        signal,
-    } = block_config;
+pub struct Custom {
+    widget: Widget,
+    shell: String,
+    cycle: std::iter::Cycle<std::vec::IntoIter<String>>,
+    interval: Duration,
+    json: bool,
+    hide_when_empty: bool,
+    one_shot: bool,
+}
+
+#[async_trait]
+impl Block for Custom {
+    const NAME: &'static str = "custom";
 
-    let interval = Duration::from_secs(interval);
-    let mut widget = Widget::new(id, shared_config);
+    type Config = CustomConfig;
 
-    // Choose the shell in this priority:
-    // 1) `shell` config option
-    // 2) `SHELL` environment varialble
-    // 3) `"sh"`
-    let shell = shell
-        .or_else(|| env::var("SHELL").ok())
-        .unwrap_or_else(|| "sh".to_string());
+    async fn init(id: usize, block_config: CustomConfig, shared_config: SharedConfig) -> Result<Self> {
+        let CustomConfig {
+            command,
+            cycle,
+            interval,
+            json,
+            hide_when_empty,
+            shell,
+            one_shot,
+        } = block_config;
 
-    let mut cycle = cycle
-        .or_else(|| command.clone().map(|cmd| vec![cmd]))
-        .block_error("custom", "either 'command' or 'cycle' must be specified")?
-        .into_iter()
-        .cycle();
+        // Choose the shell in this priority:
+        // 1) `shell` config option
+        // 2) `SHELL` environment varialble
+        // 3) `"sh"`
+        let shell = shell
+            .or_else(|| env::var("SHELL").ok())
+            .unwrap_or_else(|| "sh".to_string());
 
-    loop {
+        let cycle = cycle
+            .or_else(|| command.clone().map(|cmd| vec![cmd]))
+            .block_error("custom", "either 'command' or 'cycle' must be specified")?
+            .into_iter()
+            .cycle();
+
+        Ok(Self {
+            widget: Widget::new(id, shared_config),
+            shell,
+            cycle,
+            interval: Duration::from_secs(interval),
+            json,
+            hide_when_empty,
+            one_shot,
+        })
+    }
+
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
         // Run command
-        let output = Command::new(&shell)
-            .args(&["-c", &cycle.next().unwrap()])
-            .output()
-            .await
-            .block_error("custom", "failed to run command")?;
+        let output = subprocess::output(
+            Command::new(&self.shell).args(&["-c", &self.cycle.next().unwrap()]),
+        )
+        .await
+        .block_error("custom", "failed to run command")?;
         let stdout = std::str::from_utf8(&output.stdout)
             .block_error("custom", "the output of command is invalid UTF-8")?
             .trim();
 
         // {"icon": "ICON", "state": "STATE", "text": "YOURTEXT", "short_text": "YOUR SHORT TEXT"}
-        let widgets = if stdout.is_empty() && hide_when_empty {
+        Ok(if stdout.is_empty() && self.hide_when_empty {
             vec![]
-        } else if json {
+        } else if self.json {
             let vals: HashMap<String, String> =
                 serde_json::from_str(stdout).block_error("custom", "invalid JSON")?;
-            widget.set_icon(vals.get("icon").map(|s| s.as_str()).unwrap_or(""))?;
-            widget.set_state(match vals.get("state").map(|s| s.as_str()).unwrap_or("") {
-                "Info" => State::Info,
-                "Good" => State::Good,
-                "Warning" => State::Warning,
-                "Critical" => State::Critical,
-                _ => State::Idle,
-            });
+            self.widget
+                .set_icon(vals.get("icon").map(|s| s.as_str()).unwrap_or(""))?;
+            self.widget
+                .set_state(match vals.get("state").map(|s| s.as_str()).unwrap_or("") {
+                    "Info" => State::Info,
+                    "Good" => State::Good,
+                    "Warning" => State::Warning,
+                    "Critical" => State::Critical,
+                    _ => State::Idle,
+                });
             let text = vals.get("text").cloned().unwrap_or_default();
             let short_text = vals.get("short_text").cloned();
-            widget.set_text((text, short_text));
-            vec![widget.get_data()]
+            self.widget.set_text((text, short_text));
+            vec![self.widget.get_data()]
         } else {
-            widget.set_full_text(stdout.to_string());
-            vec![widget.get_data()]
-        };
-
-        message_sender
-            .send(BlockMessage { id, widgets })
-            .await
-            .internal_error("custom", "failed to send message")?;
+            self.widget.set_full_text(stdout.to_string());
+            vec![self.widget.get_data()]
+        })
+    }
 
-        loop {
-            tokio::select! {
-                _ = tokio::time::sleep(interval) => {
-                    if !one_shot {
-                        break;
-                    }
-                },
-                Some(event) = events_reciever.recv() => {
-                    match (event, signal) {
-                        (BlockEvent::Signal(Signal::Custom(s)), Some(signal)) if s == signal => break,
-                        (BlockEvent::I3Bar(_), _) => break,
-                        _ => (),
-                    }
-                },
-            }
+    fn interval(&self) -> Option<Duration> {
+        if self.one_shot {
+            None
+        } else {
+            Some(self.interval)
         }
     }
 }
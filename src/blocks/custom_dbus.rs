@@ -1,7 +1,8 @@
 //! A block controled by the DBus
 //!
-//! This block runs a DBus server with a custom name specified in the configuration. It creates
-//! only one path  `/` that implements `rs.swaystatus.dbus` interface (output of `qbus <name> /`):
+//! This block runs a DBus server with a custom name specified in the configuration, which is also
+//! the name of the block. It creates only one path `/` that implements `rs.swaystatus.dbus`
+//! interface (output of `qbus <name> /`):
 //! ```text
 //! method void rs.swaystatus.dbus.SetFullText(QString full)
 //! method void rs.swaystatus.dbus.SetIcon(QString icon)
@@ -34,51 +35,83 @@ use dbus::channel::MatchingReceiver;
 use dbus::message::MatchRule;
 use dbus::MethodErr;
 use dbus_crossroads::Crossroads;
-use dbus_tokio::connection;
 
-use serde::de::Deserialize;
+use async_trait::async_trait;
 use tokio::sync::mpsc;
 
-use super::{BlockEvent, BlockMessage};
+use super::Block;
 use crate::config::SharedConfig;
+use crate::dbus_connection::{Bus, DbusConnection};
 use crate::errors::*;
+use crate::protocol::i3bar_block::I3BarBlock;
 use crate::widget::{State, Widget};
 
 #[derive(serde_derive::Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields)]
-struct CustomDBusConfig {
+pub struct CustomDBusConfig {
     name: String,
 }
 
-struct Block {
-    id: usize,
+/// The data attached to the "/" object
+struct CustomDbusData {
     text: Widget,
-    sender: mpsc::Sender<BlockMessage>,
+    sender: mpsc::Sender<I3BarBlock>,
+}
+
+pub struct CustomDbus {
+    widgets: Vec<I3BarBlock>,
+    receiver: mpsc::Receiver<I3BarBlock>,
+    dbus_conn: DbusConnection,
 }
 
 // TODO: send a signal in click?
-pub async fn run(
+#[async_trait]
+impl Block for CustomDbus {
+    const NAME: &'static str = "custom_dbus";
+
+    type Config = CustomDBusConfig;
+
+    async fn init(
+        id: usize,
+        block_config: CustomDBusConfig,
+        shared_config: SharedConfig,
+    ) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel(64);
+        let dbus_conn = setup_dbus(id, block_config.name, shared_config, sender).await?;
+        Ok(Self {
+            widgets: Vec::new(),
+            receiver,
+            dbus_conn,
+        })
+    }
+
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        Ok(self.widgets.clone())
+    }
+
+    async fn wait_for_change(&mut self) -> Result<()> {
+        tokio::select! {
+            widget = self.receiver.recv() => {
+                let widget = widget.block_error("custom_dbus", "D-Bus object was dropped")?;
+                self.widgets = vec![widget];
+            }
+            _ = self.dbus_conn.wait_lost() => {
+                return block_error("custom_dbus", "lost connection to D-Bus");
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn setup_dbus(
     id: usize,
-    block_config: toml::Value,
+    dbus_name: String,
     shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    events_reciever: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    // Drop the reciever if we don't what to recieve events
-    drop(events_reciever);
-
-    // Parse config
-    let dbus_name = CustomDBusConfig::deserialize(block_config)
-        .block_config_error("custom_dbus")?
-        .name;
-
-    // Open dbus connection
-    let (resource, dbus_conn) =
-        connection::new_session_sync().block_error("music", "failed to open DBUS connection")?;
-    tokio::spawn(async {
-        let err = resource.await;
-        panic!("Lost connection to D-Bus: {}", err);
-    });
+    sender: mpsc::Sender<I3BarBlock>,
+) -> Result<DbusConnection> {
+    // Open a dbus connection of our own rather than the shared one, since we own a name on it
+    let connection = DbusConnection::open(Bus::Session)?;
+    let dbus_conn = connection.conn();
 
     // Let's request a name on the bus, so that clients can find us.
     // TODO revisit request_name() parameters
@@ -107,16 +140,13 @@ pub async fn run(
             ("icon",),
             (),
             |mut ctx, cr, (icon,): (String,)| {
-                let block: &mut Block = cr.data_mut(ctx.path()).unwrap(); // ok_or_else(|| MethodErr::no_path(ctx.path()))?;
+                let block: &mut CustomDbusData = cr.data_mut(ctx.path()).unwrap(); // ok_or_else(|| MethodErr::no_path(ctx.path()))?;
                 let result = block
                     .text
                     .set_icon(&icon)
                     .map_err(|e| MethodErr::failed(&e.to_string()));
                 let sender = block.sender.clone();
-                let message = BlockMessage {
-                    id: block.id,
-                    widgets: vec![block.text.get_data()],
-                };
+                let message = block.text.get_data();
                 async move {
                     // TODO do not ignore error
                     let _ = sender.send(message).await;
@@ -129,13 +159,10 @@ pub async fn run(
             ("full", "short"),
             (),
             |mut ctx, cr, (full, short): (String, String)| {
-                let block: &mut Block = cr.data_mut(ctx.path()).unwrap(); // ok_or_else(|| MethodErr::no_path(ctx.path()))?;
+                let block: &mut CustomDbusData = cr.data_mut(ctx.path()).unwrap(); // ok_or_else(|| MethodErr::no_path(ctx.path()))?;
                 block.text.set_text((full, Some(short)));
                 let sender = block.sender.clone();
-                let message = BlockMessage {
-                    id: block.id,
-                    widgets: vec![block.text.get_data()],
-                };
+                let message = block.text.get_data();
                 async move {
                     let _ = sender.send(message).await;
                     ctx.reply(Ok(()))
@@ -147,13 +174,10 @@ pub async fn run(
             ("full",),
             (),
             |mut ctx, cr, (full,): (String,)| {
-                let block: &mut Block = cr.data_mut(ctx.path()).unwrap(); // ok_or_else(|| MethodErr::no_path(ctx.path()))?;
+                let block: &mut CustomDbusData = cr.data_mut(ctx.path()).unwrap(); // ok_or_else(|| MethodErr::no_path(ctx.path()))?;
                 block.text.set_text((full, None));
                 let sender = block.sender.clone();
-                let message = BlockMessage {
-                    id: block.id,
-                    widgets: vec![block.text.get_data()],
-                };
+                let message = block.text.get_data();
                 async move {
                     let _ = sender.send(message).await;
                     ctx.reply(Ok(()))
@@ -165,7 +189,7 @@ pub async fn run(
             ("state",),
             (),
             |mut ctx, cr, (state,): (String,)| {
-                let block: &mut Block = cr.data_mut(ctx.path()).unwrap(); // ok_or_else(|| MethodErr::no_path(ctx.path()))?;
+                let block: &mut CustomDbusData = cr.data_mut(ctx.path()).unwrap(); // ok_or_else(|| MethodErr::no_path(ctx.path()))?;
                 let mut succes = true;
                 match state.as_str() {
                     "idle" => block.text.set_state(State::Idle),
@@ -176,10 +200,7 @@ pub async fn run(
                     _ => succes = false,
                 }
                 let sender = block.sender.clone();
-                let message = BlockMessage {
-                    id: block.id,
-                    widgets: vec![block.text.get_data()],
-                };
+                let message = block.text.get_data();
                 async move {
                     let _ = sender.send(message).await;
                     if succes {
@@ -196,10 +217,9 @@ pub async fn run(
     crossroads.insert(
         "/",
         &[iface_token],
-        Block {
-            id,
+        CustomDbusData {
             text: Widget::new(id, shared_config),
-            sender: message_sender,
+            sender,
         },
     );
 
@@ -213,5 +233,5 @@ pub async fn run(
     );
 
     // Everything is setup
-    Ok(())
+    Ok(connection)
 }
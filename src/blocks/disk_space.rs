@@ -1,17 +1,19 @@
+use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc;
 
-use nix::sys::statvfs::statvfs;
-use serde::de::Deserialize;
+use async_trait::async_trait;
 use serde_derive::Deserialize;
 
-use super::{BlockEvent, BlockMessage};
+use super::Block;
 use crate::config::SharedConfig;
 use crate::de::deserialize_duration;
 use crate::errors::*;
 use crate::formatting::FormatTemplate;
 use crate::formatting::{prefix::Prefix, value::Value};
+use crate::protocol::i3bar_block::I3BarBlock;
+use crate::sampler::{DiskUsage, Sampler};
 use crate::widget::{State, Widget};
 
 #[derive(Copy, Clone, Debug, Deserialize)]
@@ -24,7 +26,7 @@ pub enum InfoType {
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields, default)]
-struct DiskSpaceConfig {
+pub struct DiskSpaceConfig {
     /// Path to collect information from
     path: String,
 
@@ -68,46 +70,61 @@ impl Default for DiskSpaceConfig {
     }
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    events_reciever: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    // Drop the reciever if we don't what to recieve events
-    drop(events_reciever);
-
-    let block_config =
-        DiskSpaceConfig::deserialize(block_config).block_config_error("disk_space")?;
-
-    let icon = shared_config.get_icon("disk_drive")?;
-    let icon = icon.trim();
-
-    let mut text = Widget::new(id, shared_config);
-    let format = block_config.format.or_default("{available}")?;
-
-    let unit = match block_config.unit.as_str() {
-        "TB" => Prefix::Tera,
-        "GB" => Prefix::Giga,
-        "MB" => Prefix::Mega,
-        "KB" => Prefix::Kilo,
-        "B" => Prefix::One,
-        x => return block_error("disk_space", &format!("unknown unit: '{}'", x)),
-    };
-
-    let path = Path::new(block_config.path.as_str());
+pub struct DiskSpace {
+    text: Widget,
+    format: FormatTemplate,
+    icon: String,
+    unit: Prefix,
+    block_config: DiskSpaceConfig,
+    sampler: Arc<Sampler>,
+    values: HashMap<String, Value>,
+}
 
-    let mut interval = tokio::time::interval(block_config.interval);
+#[async_trait]
+impl Block for DiskSpace {
+    const NAME: &'static str = "disk_space";
+    const PLACEHOLDERS: &'static [&'static str] =
+        &["percentage", "path", "total", "used", "available", "free", "icon"];
+    const ICONS: &'static [&'static str] = &["disk_drive"];
+
+    type Config = DiskSpaceConfig;
+
+    async fn init(
+        id: usize,
+        block_config: DiskSpaceConfig,
+        shared_config: SharedConfig,
+    ) -> Result<Self> {
+        let icon = shared_config.get_icon("disk_drive")?.trim().to_string();
+
+        let unit = match block_config.unit.as_str() {
+            "TB" => Prefix::Tera,
+            "GB" => Prefix::Giga,
+            "MB" => Prefix::Mega,
+            "KB" => Prefix::Kilo,
+            "B" => Prefix::One,
+            x => return block_error("disk_space", &format!("unknown unit: '{}'", x)),
+        };
 
-    loop {
-        let statvfs = statvfs(path).block_error("disk_space", "failed to retrieve statvfs")?;
+        Ok(Self {
+            sampler: shared_config.sampler.clone(),
+            text: Widget::new(id, shared_config),
+            format: block_config.format.clone().or_default("{available}")?,
+            icon,
+            unit,
+            block_config,
+            values: HashMap::new(),
+        })
+    }
 
-        let total = (statvfs.blocks() as u64) * (statvfs.fragment_size() as u64);
-        let used = ((statvfs.blocks() as u64) - (statvfs.blocks_free() as u64))
-            * (statvfs.fragment_size() as u64);
-        let available = (statvfs.blocks_available() as u64) * (statvfs.block_size() as u64);
-        let free = (statvfs.blocks_free() as u64) * (statvfs.block_size() as u64);
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        let block_config = &self.block_config;
+        let path = Path::new(block_config.path.as_str());
+        let DiskUsage {
+            total,
+            used,
+            available,
+            free,
+        } = *self.sampler.disk_usage(path).await?;
 
         let result = match block_config.info_type {
             InfoType::Available => available,
@@ -116,21 +133,22 @@ pub async fn run(
         } as f64;
 
         let percentage = result / (total as f64) * 100.;
-        let values = map!(
+        let values = map_to_owned!(
             "percentage" => Value::from_float(percentage).percents(),
             "path" => Value::from_string(block_config.path.clone()),
             "total" => Value::from_float(total as f64).bytes(),
             "used" => Value::from_float(used as f64).bytes(),
             "available" => Value::from_float(available as f64).bytes(),
             "free" => Value::from_float(free as f64).bytes(),
-            "icon" => Value::from_string(icon.to_string()),
+            "icon" => Value::from_string(self.icon.clone()),
         );
-        text.set_text(format.render(&values)?);
+        self.text.set_text(self.format.render(&values)?);
+        self.values = values;
 
         // Send percentage to alert check if we don't want absolute alerts
         let alert_val = if block_config.alert_absolute {
             result
-                / match unit {
+                / match self.unit {
                     Prefix::Tera => 1u64 << 40,
                     Prefix::Giga => 1u64 << 30,
                     Prefix::Mega => 1u64 << 20,
@@ -163,17 +181,16 @@ pub async fn run(
                 }
             }
         };
-        text.set_state(state);
-
-        // Send the widget
-        message_sender
-            .send(BlockMessage {
-                id,
-                widgets: vec![text.get_data()],
-            })
-            .await
-            .internal_error("disk_space", "failed to send message")?;
-
-        interval.tick().await;
+        self.text.set_state(state);
+
+        Ok(vec![self.text.get_data()])
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(self.block_config.interval)
+    }
+
+    fn values(&self) -> HashMap<String, Value> {
+        self.values.clone()
     }
 }
@@ -28,21 +28,23 @@
 //! short = "{title^20}"
 //! ```
 
-use serde::de::Deserialize;
+use std::collections::HashMap;
+
+use async_trait::async_trait;
 use serde_derive::Deserialize;
-use swayipc_async::{Connection, Event, EventType, WindowChange, WorkspaceChange};
-use tokio::sync::mpsc;
+use swayipc_async::{Connection, Event, EventStream, EventType, WindowChange, WorkspaceChange};
 use tokio_stream::StreamExt;
 
-use crate::blocks::{BlockEvent, BlockMessage};
+use crate::blocks::Block;
 use crate::config::SharedConfig;
 use crate::errors::*;
 use crate::formatting::{value::Value, FormatTemplate};
+use crate::protocol::i3bar_block::I3BarBlock;
 use crate::widget::Widget;
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields, default)]
-struct FocusedWindowConfig {
+pub struct FocusedWindowConfig {
     format: FormatTemplate,
     autohide: bool,
 }
@@ -56,89 +58,110 @@ impl Default for FocusedWindowConfig {
     }
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    events_receiver: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    drop(events_receiver);
-
-    let block_config =
-        FocusedWindowConfig::deserialize(block_config).block_config_error("focused_window")?;
-    let format = block_config.format.clone().or_default("{title^21}")?;
-    let mut widget = Widget::new(id, shared_config);
-
-    let mut title = None;
-    let mut marks = Vec::new();
-
-    let conn = Connection::new()
-        .await
-        .block_error("focused_window", "failed to open connection with swayipc")?;
-
-    let mut events = conn
-        .subscribe(&[EventType::Window, EventType::Workspace])
-        .await
-        .block_error("focused_window", "could not subscribe to window events")?;
-
-    // Main loop
-    loop {
-        let event = events
-            .next()
+pub struct FocusedWindow {
+    widget: Widget,
+    format: FormatTemplate,
+    autohide: bool,
+    title: Option<String>,
+    marks: Vec<String>,
+    events: EventStream,
+}
+
+#[async_trait]
+impl Block for FocusedWindow {
+    const NAME: &'static str = "focused_window";
+    const PLACEHOLDERS: &'static [&'static str] = &["title", "marks", "visible_marks"];
+
+    type Config = FocusedWindowConfig;
+
+    async fn init(
+        id: usize,
+        block_config: FocusedWindowConfig,
+        shared_config: SharedConfig,
+    ) -> Result<Self> {
+        let conn = Connection::new()
             .await
-            .block_error("focused_window", "swayipc channel closed")?
-            .block_error("focused_window", "bad event")?;
+            .block_error("focused_window", "failed to open connection with swayipc")?;
 
-        let updated = match event {
-            Event::Window(e) => match e.change {
-                WindowChange::Mark => {
-                    marks = e.container.marks;
-                    true
-                }
-                WindowChange::Focus => {
-                    title = e.container.name;
-                    marks = e.container.marks;
-                    true
-                }
-                WindowChange::Title => {
-                    if e.container.focused {
-                        title = e.container.name;
+        let events = conn
+            .subscribe(&[EventType::Window, EventType::Workspace])
+            .await
+            .block_error("focused_window", "could not subscribe to window events")?;
+
+        Ok(Self {
+            widget: Widget::new(id, shared_config),
+            format: block_config.format.or_default("{title^21}")?,
+            autohide: block_config.autohide,
+            title: None,
+            marks: Vec::new(),
+            events,
+        })
+    }
+
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        let mut widgets = vec![];
+        if self.title.is_some() || !self.autohide {
+            self.widget.set_text(self.format.render(&self.values())?);
+            widgets.push(self.widget.get_data());
+        }
+        Ok(widgets)
+    }
+
+    async fn wait_for_change(&mut self) -> Result<()> {
+        loop {
+            let event = self
+                .events
+                .next()
+                .await
+                .block_error("focused_window", "swayipc channel closed")?
+                .block_error("focused_window", "bad event")?;
+
+            let updated = match event {
+                Event::Window(e) => match e.change {
+                    WindowChange::Mark => {
+                        self.marks = e.container.marks;
                         true
-                    } else {
-                        false
                     }
-                }
-                WindowChange::Close => {
-                    title = None;
-                    marks.clear();
+                    WindowChange::Focus => {
+                        self.title = e.container.name;
+                        self.marks = e.container.marks;
+                        true
+                    }
+                    WindowChange::Title => {
+                        if e.container.focused {
+                            self.title = e.container.name;
+                            true
+                        } else {
+                            false
+                        }
+                    }
+                    WindowChange::Close => {
+                        self.title = None;
+                        self.marks.clear();
+                        true
+                    }
+                    _ => false,
+                },
+                Event::Workspace(e) if e.change == WorkspaceChange::Init => {
+                    self.title = None;
+                    self.marks.clear();
                     true
                 }
                 _ => false,
-            },
-            Event::Workspace(e) if e.change == WorkspaceChange::Init => {
-                title = None;
-                marks.clear();
-                true
-            }
-            _ => false,
-        };
-
-        // Render and send widget
-        if updated {
-            let mut widgets = vec![];
-            if title.is_some() || !block_config.autohide {
-                widget.set_text(format.render(&map! {
-                    "title" => Value::from_string(title.clone().unwrap_or_default()),
-                    "marks" => Value::from_string(marks.iter().map(|m| format!("[{}]",m)).collect()),
-                    "visible_marks" => Value::from_string(marks.iter().filter(|m| !m.starts_with('_')).map(|m| format!("[{}]",m)).collect()),
-                })?);
-                widgets.push(widget.get_data());
+            };
+
+            if updated {
+                return Ok(());
             }
-            message_sender
-                .send(BlockMessage { id, widgets })
-                .await
-                .internal_error("focused_window", "failed to send message")?;
+        }
+    }
+
+    fn values(&self) -> HashMap<String, Value> {
+        let marks = &self.marks;
+        map_to_owned! {
+            "title" => Value::from_string(self.title.clone().unwrap_or_default()),
+            "marks" => Value::from_string(marks.iter().map(|m| format!("[{}]",m)).collect()),
+            "visible_marks" => Value::from_string(marks.iter().filter(|m| !m.starts_with('_')).map(|m| format!("[{}]",m)).collect()),
         }
     }
 }
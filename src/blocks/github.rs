@@ -1,12 +1,14 @@
-use reqwest::header;
-use serde::de::Deserialize;
+use std::collections::HashMap;
 use std::time::Duration;
-use tokio::sync::mpsc;
 
-use super::{BlockEvent, BlockMessage};
+use async_trait::async_trait;
+use reqwest::header;
+
+use super::Block;
 use crate::config::SharedConfig;
 use crate::errors::*;
 use crate::formatting::{value::Value, FormatTemplate};
+use crate::protocol::i3bar_block::I3BarBlock;
 use crate::widget::Widget;
 
 #[derive(serde_derive::Deserialize, Debug, Clone)]
@@ -34,51 +36,68 @@ fn default_hide() -> bool {
     true
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    events_reciever: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    // Drop the reciever if we don't what to recieve events
-    drop(events_reciever);
-
-    let block_config = GithubConfig::deserialize(block_config).block_config_error("github")?;
-    let interval = Duration::from_secs(block_config.interval);
-    let format = block_config.format.or_default("{total:1}")?;
-    let mut text = Widget::new(id, shared_config).with_icon("github")?;
-
-    // Http client
-    let client = reqwest::Client::new();
-    let request = client
-        .get("https://api.github.com/notifications")
-        .header("Authorization", &format!("token {}", block_config.token))
-        .header(header::USER_AGENT, "swaystatus");
-
-    loop {
-        let total = get_total(&request).await;
-
-        text.set_text(match total {
-            Some(total) => format.render(&map! {
+pub struct Github {
+    text: Widget,
+    format: FormatTemplate,
+    interval: Duration,
+    hide: bool,
+    request: reqwest::RequestBuilder,
+    values: HashMap<String, Value>,
+}
+
+#[async_trait]
+impl Block for Github {
+    const NAME: &'static str = "github";
+    const PLACEHOLDERS: &'static [&'static str] = &["total"];
+    const ICONS: &'static [&'static str] = &["github"];
+
+    type Config = GithubConfig;
+
+    async fn init(id: usize, block_config: GithubConfig, shared_config: SharedConfig) -> Result<Self> {
+        // Http client
+        let client = reqwest::Client::new();
+        let request = client
+            .get("https://api.github.com/notifications")
+            .header("Authorization", &format!("token {}", block_config.token))
+            .header(header::USER_AGENT, "swaystatus");
+
+        Ok(Self {
+            text: Widget::new(id, shared_config).with_icon("github")?,
+            format: block_config.format.or_default("{total:1}")?,
+            interval: Duration::from_secs(block_config.interval),
+            hide: block_config.hide,
+            request,
+            values: HashMap::new(),
+        })
+    }
+
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        let total = get_total(&self.request).await;
+
+        self.values = match total {
+            Some(total) => map_to_owned! {
                 "total" => Value::from_integer(total as i64),
-            })?,
+            },
+            None => HashMap::new(),
+        };
+        self.text.set_text(match total {
+            Some(_) => self.format.render(&self.values)?,
             None => ("x".to_string(), None),
         });
 
-        message_sender
-            .send(BlockMessage {
-                id,
-                widgets: if total == Some(0) && block_config.hide {
-                    vec![]
-                } else {
-                    vec![text.get_data()]
-                },
-            })
-            .await
-            .internal_error("github", "failed to send message")?;
-
-        tokio::time::sleep(interval).await;
+        Ok(if total == Some(0) && self.hide {
+            vec![]
+        } else {
+            vec![self.text.get_data()]
+        })
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(self.interval)
+    }
+
+    fn values(&self) -> HashMap<String, Value> {
+        self.values.clone()
     }
 }
 
@@ -25,24 +25,25 @@
 //! interval = 1
 //! ```
 
-use std::path::Path;
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc;
 
-use serde::de::Deserialize;
+use async_trait::async_trait;
 
-use super::{BlockEvent, BlockMessage};
+use super::Block;
 use crate::config::SharedConfig;
 use crate::de::deserialize_duration;
 use crate::errors::*;
 use crate::formatting::value::Value;
 use crate::formatting::FormatTemplate;
-use crate::util;
+use crate::protocol::i3bar_block::I3BarBlock;
+use crate::sampler::{LoadAvg, Sampler};
 use crate::widget::{State, Widget};
 
 #[derive(serde_derive::Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields, default)]
-struct LoadConfig {
+pub struct LoadConfig {
     format: FormatTemplate,
     #[serde(deserialize_with = "deserialize_duration")]
     interval: Duration,
@@ -63,72 +64,67 @@ impl Default for LoadConfig {
     }
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    events_reciever: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    // Drop the reciever if we don't what to recieve events
-    drop(events_reciever);
+pub struct Load {
+    text: Widget,
+    format: FormatTemplate,
+    interval: Duration,
+    info: f64,
+    warning: f64,
+    critical: f64,
+    logical_cores: u32,
+    sampler: Arc<Sampler>,
+    values: HashMap<String, Value>,
+}
 
-    let block_config = LoadConfig::deserialize(block_config).block_config_error("cpu")?;
-    let mut text = Widget::new(id, shared_config).with_icon("cogs")?;
-    let format = block_config.format.or_default("{1m}")?;
-    let mut interval = tokio::time::interval(block_config.interval);
+#[async_trait]
+impl Block for Load {
+    const NAME: &'static str = "load";
+    const PLACEHOLDERS: &'static [&'static str] = &["1m", "5m", "15m"];
+    const ICONS: &'static [&'static str] = &["cogs"];
 
-    // borrowed from https://docs.rs/cpuinfo/0.1.1/src/cpuinfo/count/logical.rs.html#4-6
-    let logical_cores = util::read_file(Path::new("/proc/cpuinfo"))
-        .await
-        .block_error("load", "Your system doesn't support /proc/cpuinfo")?
-        .lines()
-        .filter(|l| l.starts_with("processor"))
-        .count() as u32;
+    type Config = LoadConfig;
 
-    let loadavg_path = Path::new("/proc/loadavg");
-    loop {
-        let loadavg = util::read_file(loadavg_path).await.block_error(
-            "load",
-            "Your system does not support reading the load average from /proc/loadavg",
-        )?;
-        let mut values = loadavg.split(' ');
-        let m1: f64 = values
-            .next()
-            .map(|x| x.parse().ok())
-            .flatten()
-            .block_error("load", "bad /proc/loadavg file")?;
-        let m5: f64 = values
-            .next()
-            .map(|x| x.parse().ok())
-            .flatten()
-            .block_error("load", "bad /proc/loadavg file")?;
-        let m15: f64 = values
-            .next()
-            .map(|x| x.parse().ok())
-            .flatten()
-            .block_error("load", "bad /proc/loadavg file")?;
+    async fn init(id: usize, block_config: LoadConfig, shared_config: SharedConfig) -> Result<Self> {
+        let logical_cores = shared_config.sampler.cpuinfo().await?.logical_cores as u32;
 
-        text.set_state(match m1 / (logical_cores as f64) {
-            x if x > block_config.critical => State::Critical,
-            x if x > block_config.warning => State::Warning,
-            x if x > block_config.info => State::Info,
+        Ok(Self {
+            sampler: shared_config.sampler.clone(),
+            text: Widget::new(id, shared_config).with_icon("cogs")?,
+            format: block_config.format.or_default("{1m}")?,
+            interval: block_config.interval,
+            info: block_config.info,
+            warning: block_config.warning,
+            critical: block_config.critical,
+            logical_cores,
+            values: HashMap::new(),
+        })
+    }
+
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        let LoadAvg { m1, m5, m15 } = *self.sampler.loadavg().await?;
+
+        self.text.set_state(match m1 / (self.logical_cores as f64) {
+            x if x > self.critical => State::Critical,
+            x if x > self.warning => State::Warning,
+            x if x > self.info => State::Info,
             _ => State::Idle,
         });
-        text.set_text(format.render(&map!(
+        let values = map_to_owned!(
             "1m" => Value::from_float(m1),
             "5m" => Value::from_float(m5),
             "15m" => Value::from_float(m15),
-        ))?);
+        );
+        self.text.set_text(self.format.render(&values)?);
+        self.values = values;
 
-        message_sender
-            .send(BlockMessage {
-                id,
-                widgets: vec![text.get_data()],
-            })
-            .await
-            .internal_error("load", "failed to send message")?;
+        Ok(vec![self.text.get_data()])
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(self.interval)
+    }
 
-        interval.tick().await;
+    fn values(&self) -> HashMap<String, Value> {
+        self.values.clone()
     }
 }
@@ -49,27 +49,25 @@
 //! critical_mem = 90
 //! ```
 
-use std::path::Path;
-use std::str::FromStr;
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::sync::mpsc;
 
-use regex::Regex;
-use serde::de::Deserialize;
+use async_trait::async_trait;
+use serde_json::Value as JsonValue;
 
-use super::{BlockEvent, BlockMessage};
+use super::{Block, BlockEvent};
 use crate::click::MouseButton;
 use crate::config::SharedConfig;
 use crate::errors::*;
 use crate::formatting::{value::Value, FormatTemplate};
-use crate::util::read_file;
+use crate::protocol::i3bar_block::I3BarBlock;
+use crate::sampler::Sampler;
 use crate::widget::{State, Widget};
 
 #[derive(serde_derive::Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields, default)]
-struct MemoryConfig {
+pub struct MemoryConfig {
     format_mem: FormatTemplate,
     format_swap: FormatTemplate,
     display_type: Memtype,
@@ -97,34 +95,68 @@ impl Default for MemoryConfig {
     }
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    mut events_reciever: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    let block_config = MemoryConfig::deserialize(block_config).block_config_error("memory")?;
+pub struct Memory {
+    text_mem: Widget,
+    text_swap: Widget,
+    format: (FormatTemplate, FormatTemplate),
+    memtype: Memtype,
+    block_config: MemoryConfig,
+    sampler: Arc<Sampler>,
+    values: HashMap<String, Value>,
+}
 
-    let format = (
-        block_config
-            .format_mem
-            .or_default("{mem_free;M}/{mem_total;M}({mem_total_used_percents})")?,
-        block_config
-            .format_swap
-            .or_default("{swap_free;M}/{swap_total;M}({swap_used_percents})")?,
-    );
+#[async_trait]
+impl Block for Memory {
+    const NAME: &'static str = "memory";
+    const PLACEHOLDERS: &'static [&'static str] = &[
+        "mem_total",
+        "mem_free",
+        "mem_free_percents",
+        "mem_total_used",
+        "mem_total_used_percents",
+        "mem_used",
+        "mem_used_percents",
+        "mem_avail",
+        "mem_avail_percents",
+        "swap_total",
+        "swap_free",
+        "swap_free_percents",
+        "swap_used",
+        "swap_used_percents",
+        "buffers",
+        "buffers_percent",
+        "cached",
+        "cached_percent",
+    ];
+    const ICONS: &'static [&'static str] = &["memory_mem", "memory_swap"];
 
-    let mut text_mem = Widget::new(id, shared_config.clone()).with_icon("memory_mem")?;
-    let mut text_swap = Widget::new(id, shared_config).with_icon("memory_swap")?;
+    type Config = MemoryConfig;
 
-    let mut memtype = block_config.display_type;
-    let clickable = block_config.clickable;
+    async fn init(id: usize, block_config: MemoryConfig, shared_config: SharedConfig) -> Result<Self> {
+        let format = (
+            block_config
+                .format_mem
+                .clone()
+                .or_default("{mem_free;M}/{mem_total;M}({mem_total_used_percents})")?,
+            block_config
+                .format_swap
+                .clone()
+                .or_default("{swap_free;M}/{swap_total;M}({swap_used_percents})")?,
+        );
 
-    let interval = Duration::from_secs(block_config.interval);
+        Ok(Self {
+            sampler: shared_config.sampler.clone(),
+            text_mem: Widget::new(id, shared_config.clone()).with_icon("memory_mem")?,
+            text_swap: Widget::new(id, shared_config).with_icon("memory_swap")?,
+            format,
+            memtype: block_config.display_type,
+            block_config,
+            values: HashMap::new(),
+        })
+    }
 
-    loop {
-        let mem_state = Memstate::new().await?;
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        let mem_state = self.sampler.meminfo().await?;
         let mem_total = mem_state.mem_total as f64 * 1024.;
         let mem_free = mem_state.mem_free as f64 * 1024.;
         let swap_total = mem_state.swap_total as f64 * 1024.;
@@ -137,7 +169,7 @@ pub async fn run(
         let mem_used = mem_total_used - (buffers + cached);
         let mem_avail = mem_total - mem_used;
 
-        let values = map!(
+        let values = map_to_owned!(
             "mem_total" => Value::from_float(mem_total).bytes(),
             "mem_free" => Value::from_float(mem_free).bytes(),
             "mem_free_percents" => Value::from_float(mem_free / mem_total * 100.).percents(),
@@ -158,15 +190,17 @@ pub async fn run(
             "cached_percent" => Value::from_float(cached / mem_total * 100.).percents(),
         );
 
-        text_mem.set_text(format.0.render(&values)?);
-        text_swap.set_text(format.1.render(&values)?);
+        self.text_mem.set_text(self.format.0.render(&values)?);
+        self.text_swap.set_text(self.format.1.render(&values)?);
+        self.values = values;
 
-        let text = match memtype {
-            Memtype::Memory => &mut text_mem,
-            Memtype::Swap => &mut text_swap,
+        let text = match self.memtype {
+            Memtype::Memory => &mut self.text_mem,
+            Memtype::Swap => &mut self.text_swap,
         };
 
-        text.set_state(match memtype {
+        let block_config = &self.block_config;
+        text.set_state(match self.memtype {
             Memtype::Memory => match mem_used / mem_total * 100. {
                 x if x > block_config.critical_mem => State::Critical,
                 x if x > block_config.warning_mem => State::Warning,
@@ -179,118 +213,46 @@ pub async fn run(
             },
         });
 
-        message_sender
-            .send(BlockMessage {
-                id,
-                widgets: vec![text.get_data()],
-            })
-            .await
-            .internal_error("memory", "failed to send message")?;
+        Ok(vec![text.get_data()])
+    }
 
-        tokio::select! {
-            _ = tokio::time::sleep(interval) =>(),
-            event = events_reciever.recv() => {
-                if let BlockEvent::I3Bar(click) = event.unwrap() {
-                    if click.button == MouseButton::Left && clickable {
-                        memtype = match memtype {
-                            Memtype::Swap => Memtype::Memory,
-                            Memtype::Memory => Memtype::Swap,
-                        };
-                    }
-                }
+    async fn handle_event(&mut self, event: BlockEvent) -> Result<bool> {
+        if let BlockEvent::I3Bar(click) = event {
+            if click.button == MouseButton::Left && self.block_config.clickable {
+                self.memtype = match self.memtype {
+                    Memtype::Swap => Memtype::Memory,
+                    Memtype::Memory => Memtype::Swap,
+                };
             }
+            return Ok(true);
         }
+        Ok(false)
     }
-}
-
-#[derive(serde_derive::Deserialize, Clone, Copy, Debug)]
-#[serde(rename_all = "lowercase")]
-pub enum Memtype {
-    Swap,
-    Memory,
-}
-
-#[derive(Clone, Copy, Debug)]
-// Not following naming convention, because of naming in /proc/meminfo
-struct Memstate {
-    mem_total: u64,
-    mem_free: u64,
-    buffers: u64,
-    cached: u64,
-    s_reclaimable: u64,
-    shmem: u64,
-    swap_total: u64,
-    swap_free: u64,
-    zfs_arc_cache: u64,
-}
-
-impl Memstate {
-    async fn new() -> Result<Self> {
-        let mut file = BufReader::new(
-            File::open("/proc/meminfo")
-                .await
-                .block_error("memory", "/proc/meminfo does not exist")?,
-        );
 
-        let mut mem_state = Memstate {
-            mem_total: 0,
-            mem_free: 0,
-            buffers: 0,
-            cached: 0,
-            s_reclaimable: 0,
-            shmem: 0,
-            swap_total: 0,
-            swap_free: 0,
-            zfs_arc_cache: 0,
-        };
-
-        let mut line = String::new();
-        while file
-            .read_line(&mut line)
-            .await
-            .block_error("memory", "failed to read /proc/meminfo")?
-            != 0
-        {
-            let mut words = line.trim().split_whitespace();
+    fn interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.block_config.interval))
+    }
 
-            let name = match words.next() {
-                Some(name) => name,
-                None => {
-                    line.clear();
-                    continue;
-                }
-            };
-            let val = words
-                .next()
-                .map(|x| u64::from_str(x).ok())
-                .flatten()
-                .block_error("memory", "failed to parse /proc/meminfo")?;
+    fn state(&self) -> Option<JsonValue> {
+        serde_json::to_value(self.memtype).ok()
+    }
 
-            match name {
-                "MemTotal:" => mem_state.mem_total = val,
-                "MemFree:" => mem_state.mem_free = val,
-                "Buffers:" => mem_state.buffers = val,
-                "Cached:" => mem_state.cached = val,
-                "SReclaimable:" => mem_state.s_reclaimable = val,
-                "Shmem:" => mem_state.shmem = val,
-                "SwapTotal:" => mem_state.swap_total = val,
-                "SwapFree:" => mem_state.swap_free = val,
-                _ => (),
+    fn restore_state(&mut self, state: JsonValue) {
+        if self.block_config.clickable {
+            if let Ok(memtype) = serde_json::from_value(state) {
+                self.memtype = memtype;
             }
-
-            line.clear();
-        }
-
-        // Read ZFS arc cache size to add to total cache size
-        if let Ok(arcstats) = read_file(Path::new("/proc/spl/kstat/zfs/arcstats")).await {
-            let size_re = Regex::new(r"size\s+\d+\s+(\d+)").unwrap(); // Valid regex is safe to unwrap.
-            let size = &size_re
-                .captures(&arcstats)
-                .block_error("memory", "failed to find zfs_arc_cache size")?[1];
-            mem_state.zfs_arc_cache =
-                u64::from_str(size).block_error("memory", "failed to parse zfs_arc_cache size")?;
         }
+    }
 
-        Ok(mem_state)
+    fn values(&self) -> HashMap<String, Value> {
+        self.values.clone()
     }
 }
+
+#[derive(serde_derive::Deserialize, serde_derive::Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Memtype {
+    Swap,
+    Memory,
+}
@@ -1,31 +1,28 @@
 use dbus::arg;
-use dbus::message::MatchRule;
 use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
 use dbus::nonblock::{Proxy, SyncConnection};
-use dbus::strings::{Interface, Member, Path};
-use dbus_tokio::connection;
+use dbus::strings::Path;
 
-use futures::StreamExt;
-use serde::de::Deserialize;
+use async_trait::async_trait;
 use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc;
 
-use super::{BlockEvent, BlockMessage};
+use super::{widget_instance, widget_name, Block, BlockEvent};
 use crate::click::MouseButton;
 use crate::config::SharedConfig;
+use crate::dbus_connection::{DbusConnection, Subscription};
 use crate::errors::*;
+use crate::protocol::i3bar_block::I3BarBlock;
 use crate::util::escape_pango_text;
 use crate::widget::{Spacing, State, Widget};
 
-const PLAY_PAUSE_BTN: usize = 1;
-const NEXT_BTN: usize = 2;
-const PREV_BTN: usize = 3;
+/// How far clicking the text seeks, in microseconds
+const SEEK_STEP_US: i64 = 10_000_000;
 
 #[derive(serde_derive::Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields, default)]
-struct MusicConfig {
+pub struct MusicConfig {
     // TODO add stuff here
     width: usize,
 
@@ -41,125 +38,170 @@ impl Default for MusicConfig {
     }
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    mut events_reciever: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    let block_config = MusicConfig::deserialize(block_config).block_config_error("music")?;
-
-    let mut text = Widget::new(id, shared_config.clone()).with_icon("music")?;
-    let mut play_pause_button = Widget::new(id, shared_config.clone())
-        .with_instance(PLAY_PAUSE_BTN)
-        .with_spacing(Spacing::Hidden);
-    let mut next_button = Widget::new(id, shared_config.clone())
-        .with_instance(NEXT_BTN)
-        .with_spacing(Spacing::Hidden)
-        .with_icon("music_next")?;
-    let mut prev_button = Widget::new(id, shared_config)
-        .with_instance(PREV_BTN)
-        .with_spacing(Spacing::Hidden)
-        .with_icon("music_prev")?;
-
-    // Connect to the D-Bus session bus (this is blocking, unfortunately).
-    let (resource, dbus_conn) =
-        connection::new_session_sync().block_error("music", "failed to open DBUS connection")?;
-    // The resource is a task that should be spawned onto a tokio compatible
-    // reactor ASAP. If the resource ever finishes, you lost connection to D-Bus.
-    tokio::spawn(async {
-        let err = resource.await;
-        panic!("Lost connection to D-Bus: {}", err);
-    });
-
-    // Add matches
-    // TODO (maybe?) listen to "owner changed" events
-    let mut dbus_rule = MatchRule::new();
-    dbus_rule.interface = Some(Interface::from_slice("org.freedesktop.DBus.Properties").unwrap());
-    dbus_rule.member = Some(Member::new("PropertiesChanged").unwrap());
-    dbus_rule.path = Some(Path::new("/org/mpris/MediaPlayer2").unwrap());
-    let (_incoming_signal, mut dbus_stream) = dbus_conn
-        .add_match(dbus_rule)
-        .await
-        .block_error("music", "failed to add match")?
-        .msg_stream();
+pub struct Music {
+    text: Widget,
+    play_pause_button: Widget,
+    next_button: Widget,
+    prev_button: Widget,
+    block_config: MusicConfig,
+    dbus_conn: DbusConnection,
+    properties_changed: Subscription,
+    name_owner_changed: Subscription,
+    player: Option<Player>,
+    refresh_player: bool,
+}
 
-    let mut player = get_any_player(dbus_conn.clone()).await?;
+#[async_trait]
+impl Block for Music {
+    const NAME: &'static str = "music";
+    const ICONS: &'static [&'static str] =
+        &["music", "music_next", "music_prev", "music_play", "music_pause"];
+    const WIDGETS: &'static [&'static str] = &["play", "next", "prev"];
+
+    type Config = MusicConfig;
+
+    async fn init(id: usize, block_config: MusicConfig, shared_config: SharedConfig) -> Result<Self> {
+        let dbus_conn = shared_config.dbus.session()?;
+        let properties_changed = dbus_conn
+            .properties_changed(Path::new("/org/mpris/MediaPlayer2").unwrap())
+            .await?;
+        let name_owner_changed = dbus_conn.name_owner_changed().await?;
+
+        let text = Widget::new(id, shared_config.clone()).with_icon("music")?;
+        let play_pause_button = Widget::new(id, shared_config.clone())
+            .with_instance(widget_instance::<Self>("play"))
+            .with_spacing(Spacing::Hidden);
+        let next_button = Widget::new(id, shared_config.clone())
+            .with_instance(widget_instance::<Self>("next"))
+            .with_spacing(Spacing::Hidden)
+            .with_icon("music_next")?;
+        let prev_button = Widget::new(id, shared_config)
+            .with_instance(widget_instance::<Self>("prev"))
+            .with_spacing(Spacing::Hidden)
+            .with_icon("music_prev")?;
+
+        Ok(Self {
+            text,
+            play_pause_button,
+            next_button,
+            prev_button,
+            block_config,
+            dbus_conn,
+            properties_changed,
+            name_owner_changed,
+            player: None,
+            refresh_player: true,
+        })
+    }
 
-    loop {
-        let widgets = match player {
-            Some(ref player) => {
-                text.set_full_text(escape_pango_text(player.display(block_config.width)));
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        if self.refresh_player {
+            self.player = get_any_player(self.dbus_conn.conn().clone()).await?;
+            self.refresh_player = false;
+        }
+
+        let widgets = match self.player {
+            Some(ref mut player) => {
+                self.text
+                    .set_full_text(escape_pango_text(player.display(self.block_config.width)));
 
                 match player.status {
                     PlaybackStatus::Playing => {
-                        text.set_state(State::Info);
-                        play_pause_button.set_state(State::Info);
-                        next_button.set_state(State::Info);
-                        prev_button.set_state(State::Info);
-                        play_pause_button.set_icon("music_pause")?;
+                        self.text.set_state(State::Info);
+                        self.play_pause_button.set_state(State::Info);
+                        self.next_button.set_state(State::Info);
+                        self.prev_button.set_state(State::Info);
+                        self.play_pause_button.set_icon("music_pause")?;
                     }
                     _ => {
-                        text.set_state(State::Idle);
-                        play_pause_button.set_state(State::Idle);
-                        next_button.set_state(State::Idle);
-                        prev_button.set_state(State::Idle);
-                        play_pause_button.set_icon("music_play")?;
+                        self.text.set_state(State::Idle);
+                        self.play_pause_button.set_state(State::Idle);
+                        self.next_button.set_state(State::Idle);
+                        self.prev_button.set_state(State::Idle);
+                        self.play_pause_button.set_icon("music_play")?;
                     }
                 }
 
-                let mut output = vec![text.get_data()];
-                for button in &block_config.buttons {
+                // Rotate the text for the next update
+                player.rotating.rotate();
+
+                let mut output = vec![self.text.get_data()];
+                for button in &self.block_config.buttons {
                     match button.as_str() {
-                        "play" => output.push(play_pause_button.get_data()),
-                        "next" => output.push(next_button.get_data()),
-                        "prev" => output.push(prev_button.get_data()),
+                        "play" => output.push(self.play_pause_button.get_data()),
+                        "next" => output.push(self.next_button.get_data()),
+                        "prev" => output.push(self.prev_button.get_data()),
                         _ => (),
                     }
                 }
                 output
             }
             None => {
-                text.set_text((String::new(), None));
-                text.set_state(State::Idle);
-                vec![text.get_data()]
+                self.text.set_text((String::new(), None));
+                self.text.set_state(State::Idle);
+                vec![self.text.get_data()]
             }
         };
 
-        message_sender
-            .send(BlockMessage { id, widgets })
-            .await
-            .internal_error("music", "failed to send message")?;
+        Ok(widgets)
+    }
 
-        if let Some(ref mut player) = player {
-            player.rotating.rotate();
+    async fn handle_event(&mut self, event: BlockEvent) -> Result<bool> {
+        if let BlockEvent::I3Bar(click) = event {
+            if click.button == MouseButton::Left {
+                if let Some(ref player) = self.player {
+                    let proxy = &player.dbus_proxy;
+                    let interface = "org.mpris.MediaPlayer2.Player";
+                    // Ignore the error
+                    let widget = widget_name(Self::WIDGETS, click.instance);
+                    let _resonce: StdResult<(), _> = match (click.instance, widget) {
+                        (_, Some("play")) => proxy.method_call(interface, "PlayPause", ()).await,
+                        (_, Some("next")) => proxy.method_call(interface, "Next", ()).await,
+                        (_, Some("prev")) => proxy.method_call(interface, "Previous", ()).await,
+                        // Seek back when the left half of the text is clicked, forward otherwise
+                        (None, _) if !click.handled => match self.text.text_position(&click) {
+                            Some(position) => {
+                                let offset = if position < 0.5 {
+                                    -SEEK_STEP_US
+                                } else {
+                                    SEEK_STEP_US
+                                };
+                                proxy.method_call(interface, "Seek", (offset,)).await
+                            }
+                            None => return Ok(true),
+                        },
+                        _ => return Ok(true),
+                    };
+                }
+            }
+            return Ok(true);
         }
+        Ok(false)
+    }
 
-        tokio::select! {
-            // Time to update rotating text
-            _ = tokio::time::sleep(Duration::from_secs(1)) => (),
-            // Wait for a DBUS event
-            _ = dbus_stream.next() => player = get_any_player(dbus_conn.clone()).await?,
-            // Wait for a click
-            Some(BlockEvent::I3Bar(click)) = events_reciever.recv() => {
-                if click.button == MouseButton::Left {
-                    if let Some(ref player) = player {
-                        let command = match click.instance {
-                            Some(PLAY_PAUSE_BTN) => "PlayPause",
-                            Some(NEXT_BTN) => "Next",
-                            Some(PREV_BTN) => "Previous",
-                            _ => continue,
-                        };
-                        // Ignore the error
-                        let _resonce: StdResult<(), _> = player
-                            .dbus_proxy
-                            .method_call("org.mpris.MediaPlayer2.Player", command, ())
-                            .await;
+    // Time to update rotating text
+    fn interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(1))
+    }
+
+    // Wait for a player to change or to appear or disappear
+    async fn wait_for_change(&mut self) -> Result<()> {
+        loop {
+            tokio::select! {
+                message = self.properties_changed.next() => {
+                    message?;
+                    break;
+                }
+                message = self.name_owner_changed.next() => {
+                    let name: String = message?.read1().unwrap_or_default();
+                    if name.starts_with("org.mpris.MediaPlayer2") {
+                        break;
                     }
                 }
             }
         }
+        self.refresh_player = true;
+        Ok(())
     }
 }
 
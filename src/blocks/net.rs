@@ -1,12 +1,15 @@
-use serde::de::Deserialize;
+use std::collections::HashMap;
 use std::time::{Duration, Instant};
-use tokio::sync::mpsc;
 
-use super::{BlockEvent, BlockMessage};
+use async_trait::async_trait;
+use serde_json::Value as JsonValue;
+
+use super::{Block, BlockEvent};
 use crate::click::MouseButton;
 use crate::config::SharedConfig;
 use crate::errors::*;
 use crate::formatting::{value::Value, FormatTemplate};
+use crate::protocol::i3bar_block::I3BarBlock;
 use crate::netlink::{default_interface, NetDevice};
 use crate::util;
 use crate::widget::Widget;
@@ -38,36 +41,69 @@ impl Default for NetConfig {
     }
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    mut events_reciever: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    let block_config = NetConfig::deserialize(block_config).block_config_error("net")?;
-    let mut format = block_config
-        .format
-        .or_default("{speed_down;K}{speed_up;k}")?;
-    let mut format_alt = block_config.format_alt;
-
-    let mut text = Widget::new(id, shared_config.clone());
-    let interval = Duration::from_secs(block_config.interval);
+pub struct Net {
+    text: Widget,
+    format: FormatTemplate,
+    format_alt: Option<FormatTemplate>,
+    /// Whether `format` and `format_alt` have been swapped
+    showing_alt: bool,
+    device: Option<String>,
+    interval: Duration,
+    net_down_icon: String,
+    net_up_icon: String,
 
     // Stats
-    let mut stats = None;
-    let mut timer = Instant::now();
-    let mut tx_hist = [0f64; 8];
-    let mut rx_hist = [0f64; 8];
+    stats: Option<(u64, u64)>,
+    timer: Instant,
+    tx_hist: [f64; 8],
+    rx_hist: [f64; 8],
+    values: HashMap<String, Value>,
+}
+
+#[async_trait]
+impl Block for Net {
+    const NAME: &'static str = "net";
+    const PLACEHOLDERS: &'static [&'static str] = &[
+        "ssid",
+        "signal_strength",
+        "frequency",
+        "speed_down",
+        "speed_up",
+        "graph_down",
+        "graph_up",
+        "device",
+    ];
+    const ICONS: &'static [&'static str] = &["net_down", "net_up"];
+
+    type Config = NetConfig;
+
+    async fn init(id: usize, block_config: NetConfig, shared_config: SharedConfig) -> Result<Self> {
+        Ok(Self {
+            format: block_config
+                .format
+                .or_default("{speed_down;K}{speed_up;k}")?,
+            format_alt: block_config.format_alt,
+            showing_alt: false,
+            device: block_config.device,
+            interval: Duration::from_secs(block_config.interval),
+            net_down_icon: shared_config.get_icon("net_down")?,
+            net_up_icon: shared_config.get_icon("net_up")?,
+            text: Widget::new(id, shared_config),
+            stats: None,
+            timer: Instant::now(),
+            tx_hist: [0f64; 8],
+            rx_hist: [0f64; 8],
+            values: HashMap::new(),
+        })
+    }
 
-    loop {
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
         let mut speed_down: f64 = 0.0;
         let mut speed_up: f64 = 0.0;
 
         // Get interface name
         let device = NetDevice::from_interface(
-            block_config
-                .device
+            self.device
                 .clone()
                 .or_else(default_interface)
                 .unwrap_or_else(|| "lo".to_string()),
@@ -75,59 +111,108 @@ pub async fn run(
         .await;
 
         // Calculate speed
-        match (stats, device.read_stats().await) {
+        match (self.stats, device.read_stats().await) {
             // No previous stats available
-            (None, new_stats) => stats = new_stats,
+            (None, new_stats) => self.stats = new_stats,
             // No new stats available
-            (Some(_), None) => stats = None,
+            (Some(_), None) => self.stats = None,
             // All stats available
             (Some(old_stats), Some(new_stats)) => {
                 let rx_bytes = new_stats.0.saturating_sub(old_stats.0);
                 let tx_bytes = new_stats.1.saturating_sub(old_stats.1);
-                let elapsed = timer.elapsed().as_secs_f64();
-                timer = Instant::now();
+                let elapsed = self.timer.elapsed().as_secs_f64();
+                self.timer = Instant::now();
                 speed_down = rx_bytes as f64 / elapsed;
                 speed_up = tx_bytes as f64 / elapsed;
-                stats = Some(new_stats);
+                self.stats = Some(new_stats);
             }
         }
-        push_to_hist(&mut rx_hist, speed_down);
-        push_to_hist(&mut tx_hist, speed_up);
+        push_to_hist(&mut self.rx_hist, speed_down);
+        push_to_hist(&mut self.tx_hist, speed_up);
 
         // Get WiFi information
-        let wifi = device.wifi_info()?;
-
-        text.set_icon(device.icon)?;
-        text.set_text(format.render(&map! {
+        let wifi = if device.wireless {
+            device.wifi_info()?
+        } else {
+            (None, None, None)
+        };
+
+        self.text.set_icon(device.icon)?;
+        let values = map_to_owned! {
             "ssid" => Value::from_string(wifi.0.unwrap_or_else(|| "N/A".to_string())),
             "signal_strength" => Value::from_integer(wifi.2.unwrap_or_default()).percents(),
             "frequency" => Value::from_float(wifi.1.unwrap_or_default()).hertz(),
-            "speed_down" => Value::from_float(speed_down).bytes().icon(shared_config.get_icon("net_down")?),
-            "speed_up" => Value::from_float(speed_up).bytes().icon(shared_config.get_icon("net_up")?),
-            "graph_down" => Value::from_string(util::format_vec_to_bar_graph(&rx_hist)),
-            "graph_up" => Value::from_string(util::format_vec_to_bar_graph(&tx_hist)),
+            "speed_down" => Value::from_float(speed_down).bytes().icon(self.net_down_icon.clone()),
+            "speed_up" => Value::from_float(speed_up).bytes().icon(self.net_up_icon.clone()),
+            "graph_down" => Value::from_string(util::format_vec_to_bar_graph(&self.rx_hist)),
+            "graph_up" => Value::from_string(util::format_vec_to_bar_graph(&self.tx_hist)),
             "device" => Value::from_string(device.interface),
-        })?);
-
-        message_sender
-            .send(BlockMessage {
-                id,
-                widgets: vec![text.get_data()],
-            })
-            .await
-            .internal_error("net", "failed to send message")?;
-
-        tokio::select! {
-            _ = tokio::time::sleep(interval) =>(),
-            Some(BlockEvent::I3Bar(click)) = events_reciever.recv() => {
+        };
+        self.text.set_text(self.format.render(&values)?);
+        self.values = values;
+
+        Ok(vec![self.text.get_data()])
+    }
+
+    async fn handle_event(&mut self, event: BlockEvent) -> Result<bool> {
+        match event {
+            BlockEvent::I3Bar(click) => {
                 if click.button == MouseButton::Left {
-                    if let Some(ref mut format_alt) = format_alt {
-                        std::mem::swap(format_alt, &mut format);
-                    }
+                    self.toggle_format();
                 }
+                Ok(true)
+            }
+            BlockEvent::ToggleFormat => {
+                self.toggle_format();
+                Ok(true)
             }
+            _ => Ok(false),
         }
     }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(self.interval)
+    }
+
+    fn state(&self) -> Option<JsonValue> {
+        serde_json::to_value(NetState {
+            showing_alt: self.showing_alt,
+            tx_hist: self.tx_hist,
+            rx_hist: self.rx_hist,
+        })
+        .ok()
+    }
+
+    fn restore_state(&mut self, state: JsonValue) {
+        if let Ok(state) = serde_json::from_value::<NetState>(state) {
+            if state.showing_alt {
+                self.toggle_format();
+            }
+            self.tx_hist = state.tx_hist;
+            self.rx_hist = state.rx_hist;
+        }
+    }
+
+    fn values(&self) -> HashMap<String, Value> {
+        self.values.clone()
+    }
+}
+
+impl Net {
+    fn toggle_format(&mut self) {
+        if let Some(ref mut format_alt) = self.format_alt {
+            std::mem::swap(format_alt, &mut self.format);
+            self.showing_alt = !self.showing_alt;
+        }
+    }
+}
+
+/// What is kept when the block is restarted
+#[derive(serde_derive::Deserialize, serde_derive::Serialize)]
+struct NetState {
+    showing_alt: bool,
+    tx_hist: [f64; 8],
+    rx_hist: [f64; 8],
 }
 
 fn push_to_hist<T>(hist: &mut [T], elem: T) {
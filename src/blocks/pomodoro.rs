@@ -36,20 +36,27 @@
 //! - Use different icons.
 //! - Use format strings.
 
-use serde::de::Deserialize;
-use std::time::{Duration, Instant};
-use tokio::sync::mpsc;
+use std::time::Duration;
+use tokio::time::Instant;
 
-use super::{BlockEvent, BlockMessage};
+use async_trait::async_trait;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use super::{Block, BlockEvent};
 use crate::click::MouseButton;
 use crate::config::SharedConfig;
 use crate::errors::*;
-use crate::subprocess::{spawn_shell, spawn_shell_sync};
+use crate::protocol::i3bar_block::I3BarBlock;
+use crate::subprocess::{spawn_shell, spawn_shell_async, GroupChild};
 use crate::widget::{State, Widget};
 
+/// The prompts shown while reading the parameters
+const PARAMS: [&str; 3] = ["Task length:", "Break length:", "Pomodoros:"];
+
 #[derive(serde_derive::Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields, default)]
-struct PomodoroConfig {
+pub struct PomodoroConfig {
     message: String,
     break_message: String,
     notify_cmd: Option<String>,
@@ -67,79 +74,155 @@ impl Default for PomodoroConfig {
     }
 }
 
-struct Block {
-    id: usize,
-    widget: Widget,
-    block_config: PomodoroConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    events_receiver: mpsc::Receiver<BlockEvent>,
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+enum Phase {
+    /// Collapsed, waiting for a left click
+    Stopped,
+    /// Reading the parameters: task length, break length and the number of pomodoros
+    Setup { step: usize, values: [u64; 3] },
+    Task {
+        pomodoro: u64,
+        #[serde(with = "unix_time")]
+        end: Instant,
+    },
+    /// The task is over, waiting for the notifier or a left click
+    TaskOver { pomodoro: u64 },
+    Break {
+        pomodoro: u64,
+        #[serde(with = "unix_time")]
+        end: Instant,
+    },
+    /// The break is over, waiting for the notifier or a left click
+    BreakOver { pomodoro: u64 },
 }
 
-impl Block {
-    async fn set_text(&mut self, text: String) -> Result<()> {
-        self.widget.set_full_text(text);
-        self.message_sender
-            .send(BlockMessage {
-                id: self.id,
-                widgets: vec![self.widget.get_data()],
-            })
-            .await
-            .internal_error("pomodoro", "failed to send message")?;
-        Ok(())
+/// What is kept when the block is restarted
+#[derive(Serialize, Deserialize)]
+struct PomodoroState {
+    phase: Phase,
+    task_len: Duration,
+    break_len: Duration,
+    pomodoros: u64,
+}
+
+/// Save an `Instant` as a UNIX timestamp, so that a timer keeps running while swaystatus is not
+mod unix_time {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use tokio::time::Instant;
+
+    pub fn serialize<S: Serializer>(instant: &Instant, serializer: S) -> Result<S::Ok, S::Error> {
+        let now = Instant::now();
+        let time = if *instant >= now {
+            SystemTime::now() + (*instant - now)
+        } else {
+            SystemTime::now() - (now - *instant)
+        };
+        let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        secs.as_secs_f64().serialize(serializer)
     }
 
-    async fn wait_for_click(&mut self, button: MouseButton) {
-        loop {
-            if let Some(BlockEvent::I3Bar(click)) = self.events_receiver.recv().await {
-                if click.button == button {
-                    break;
-                }
-            }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Instant, D::Error> {
+        // The state file may have been edited or corrupted, which must not make the block panic
+        let invalid = || D::Error::custom("invalid timestamp");
+        let secs = f64::deserialize(deserializer)?.max(0.0);
+        if !secs.is_finite() || secs > u32::MAX as f64 {
+            return Err(invalid());
+        }
+        let time = UNIX_EPOCH
+            .checked_add(Duration::from_secs_f64(secs))
+            .ok_or_else(invalid)?;
+        let now = Instant::now();
+        match time.duration_since(SystemTime::now()) {
+            Ok(left) => now.checked_add(left).ok_or_else(invalid),
+            Err(passed) => Ok(now.checked_sub(passed.duration()).unwrap_or(now)),
         }
     }
+}
+
+pub struct Pomodoro {
+    widget: Widget,
+    block_config: PomodoroConfig,
+    phase: Phase,
+    task_len: Duration,
+    break_len: Duration,
+    pomodoros: u64,
+    last_update: Instant,
+    /// The running blocking notifier
+    notifier: Option<GroupChild>,
+}
 
-    async fn read_params(&mut self) -> Result<(Duration, Duration, u64)> {
-        let task_len = self.read_u64(25, "Task length:").await?;
-        let break_len = self.read_u64(5, "Break length:").await?;
-        let pomodoros = self.read_u64(4, "Pomodoros:").await?;
-        Ok((
-            Duration::from_secs(task_len * 60),
-            Duration::from_secs(break_len * 60),
-            pomodoros,
-        ))
+impl Pomodoro {
+    /// Switch to the next phase after `TaskOver` or `BreakOver`
+    fn proceed(&mut self) {
+        self.phase = match self.phase {
+            // No break after the last pomodoro
+            Phase::TaskOver { pomodoro } if pomodoro + 1 >= self.pomodoros => Phase::Stopped,
+            Phase::TaskOver { pomodoro } => Phase::Break {
+                pomodoro,
+                end: Instant::now() + self.break_len,
+            },
+            Phase::BreakOver { pomodoro } => Phase::Task {
+                pomodoro: pomodoro + 1,
+                end: Instant::now() + self.task_len,
+            },
+            phase => phase,
+        };
     }
 
-    async fn read_u64(&mut self, mut number: u64, msg: &str) -> Result<u64> {
-        loop {
-            self.set_text(format!("{} {}", msg, number)).await?;
-            if let Some(BlockEvent::I3Bar(click)) = self.events_receiver.recv().await {
-                match click.button {
-                    MouseButton::Left => break,
-                    MouseButton::WheelUp => number += 1,
-                    MouseButton::WheelDown => number = number.saturating_sub(1),
-                    _ => (),
-                }
+    /// Run `notify_cmd` with `message`
+    fn notify(&mut self, message: &str) -> Result<()> {
+        if let Some(cmd) = &self.block_config.notify_cmd {
+            let cmd = cmd.replace("{msg}", message);
+            if self.block_config.blocking_cmd {
+                self.notifier = Some(
+                    spawn_shell_async(&cmd).block_error("pomodoro", "failed to run notify_cmd")?,
+                );
+            } else {
+                spawn_shell(&cmd).block_error("pomodoro", "failed to run notify_cmd")?;
             }
         }
-        Ok(number)
+        Ok(())
     }
+}
 
-    async fn run_pomodoro(
-        &mut self,
-        task_len: Duration,
-        break_len: Duration,
-        pomodoros: u64,
-    ) -> Result<()> {
-        for pomodoro in 0..pomodoros {
-            // Task timer
-            self.widget.set_state(State::Idle);
-            let timer = Instant::now();
-            loop {
-                let elapsed = timer.elapsed();
-                if elapsed >= task_len {
-                    break;
-                }
-                let left = task_len - elapsed;
+#[async_trait]
+impl Block for Pomodoro {
+    const NAME: &'static str = "pomodoro";
+    const ICONS: &'static [&'static str] = &["pomodoro"];
+
+    type Config = PomodoroConfig;
+
+    async fn init(
+        id: usize,
+        block_config: PomodoroConfig,
+        shared_config: SharedConfig,
+    ) -> Result<Self> {
+        Ok(Self {
+            widget: Widget::new(id, shared_config).with_icon("pomodoro")?,
+            block_config,
+            phase: Phase::Stopped,
+            task_len: Duration::from_secs(25 * 60),
+            break_len: Duration::from_secs(5 * 60),
+            pomodoros: 4,
+            last_update: Instant::now(),
+            notifier: None,
+        })
+    }
+
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        let now = Instant::now();
+        self.last_update = now;
+
+        let (state, text) = match self.phase {
+            // Collapsed block
+            Phase::Stopped => (State::Idle, String::new()),
+            Phase::Setup { step, values } => {
+                (State::Idle, format!("{} {}", PARAMS[step], values[step]))
+            }
+            Phase::Task { pomodoro, end } => {
+                let left = end.saturating_duration_since(now);
                 let text = if pomodoro == 0 {
                     format!("{} min", (left.as_secs() + 59) / 60,)
                 } else {
@@ -149,111 +232,149 @@ impl Block {
                         (left.as_secs() + 59) / 60,
                     )
                 };
-                self.set_text(text).await?;
-                tokio::select! {
-                    _ = tokio::time::sleep(Duration::from_secs(10)) => (),
-                    Some(BlockEvent::I3Bar(click)) = self.events_receiver.recv() => {
-                        if click.button == MouseButton::Middle {
-                            return Ok(());
-                        }
-                    }
-                }
+                (State::Idle, text)
             }
+            Phase::TaskOver { .. } => (State::Good, self.block_config.message.clone()),
+            Phase::Break { end, .. } => {
+                let left = end.saturating_duration_since(now);
+                (
+                    State::Good,
+                    format!("Break: {} min", (left.as_secs() + 59) / 60,),
+                )
+            }
+            Phase::BreakOver { .. } => (State::Good, self.block_config.break_message.clone()),
+        };
 
-            // Show break message
-            self.widget.set_state(State::Good);
-            self.set_text(self.block_config.message.clone()).await?;
-            if let Some(cmd) = &self.block_config.notify_cmd {
-                let cmd = cmd.replace("{msg}", &self.block_config.message);
-                if self.block_config.blocking_cmd {
-                    spawn_shell_sync(&cmd)
-                        .await
-                        .block_error("pomodoro", "failed to run notify_cmd")?;
+        self.widget.set_state(state);
+        self.widget.set_full_text(text);
+        Ok(vec![self.widget.get_data()])
+    }
+
+    async fn handle_event(&mut self, event: BlockEvent) -> Result<bool> {
+        let click = match event {
+            BlockEvent::I3Bar(click) => click,
+            _ => return Ok(false),
+        };
+
+        match (self.phase, click.button) {
+            (Phase::Stopped, MouseButton::Left) => {
+                self.phase = Phase::Setup {
+                    step: 0,
+                    values: [25, 5, 4],
+                };
+            }
+            (Phase::Setup { step, values }, MouseButton::Left) if step + 1 < PARAMS.len() => {
+                self.phase = Phase::Setup {
+                    step: step + 1,
+                    values,
+                };
+            }
+            (Phase::Setup { values, .. }, MouseButton::Left) => {
+                self.task_len = Duration::from_secs(values[0] * 60);
+                self.break_len = Duration::from_secs(values[1] * 60);
+                self.pomodoros = values[2];
+                self.phase = if self.pomodoros == 0 {
+                    Phase::Stopped
                 } else {
-                    spawn_shell(&cmd).block_error("pomodoro", "failed to run notify_cmd")?;
-                    self.wait_for_click(MouseButton::Left).await;
-                }
-            } else {
-                self.wait_for_click(MouseButton::Left).await;
+                    Phase::Task {
+                        pomodoro: 0,
+                        end: Instant::now() + self.task_len,
+                    }
+                };
             }
-
-            // No break after the last pomodoro
-            if pomodoro == pomodoros - 1 {
-                break;
+            (Phase::Setup { step, mut values }, MouseButton::WheelUp) => {
+                values[step] += 1;
+                self.phase = Phase::Setup { step, values };
+            }
+            (Phase::Setup { step, mut values }, MouseButton::WheelDown) => {
+                values[step] = values[step].saturating_sub(1);
+                self.phase = Phase::Setup { step, values };
+            }
+            (Phase::Task { .. }, MouseButton::Middle) | (Phase::Break { .. }, MouseButton::Middle) => {
+                self.phase = Phase::Stopped;
             }
+            (Phase::TaskOver { .. }, MouseButton::Left)
+            | (Phase::BreakOver { .. }, MouseButton::Left)
+                if self.notifier.is_none() =>
+            {
+                self.proceed();
+            }
+            _ => (),
+        }
+
+        Ok(true)
+    }
 
-            // Break timer
-            let timer = Instant::now();
-            loop {
-                let elapsed = timer.elapsed();
-                if elapsed >= break_len {
-                    break;
+    async fn wait_for_change(&mut self) -> Result<()> {
+        match self.phase {
+            Phase::Task { pomodoro, end } => {
+                tokio::time::sleep_until(end.min(self.last_update + Duration::from_secs(10)))
+                    .await;
+                if Instant::now() >= end {
+                    self.phase = Phase::TaskOver { pomodoro };
+                    let message = self.block_config.message.clone();
+                    self.notify(&message)?;
                 }
-                let left = break_len - elapsed;
-                self.set_text(format!("Break: {} min", (left.as_secs() + 59) / 60,))
-                    .await?;
-                tokio::select! {
-                    _ = tokio::time::sleep(Duration::from_secs(10)) => (),
-                    Some(BlockEvent::I3Bar(click)) = self.events_receiver.recv() => {
-                        if click.button == MouseButton::Middle {
-                            return Ok(());
-                        }
-                    }
+            }
+            Phase::Break { pomodoro, end } => {
+                tokio::time::sleep_until(end.min(self.last_update + Duration::from_secs(10)))
+                    .await;
+                if Instant::now() >= end {
+                    self.phase = Phase::BreakOver { pomodoro };
+                    let message = self.block_config.break_message.clone();
+                    self.notify(&message)?;
                 }
             }
-
-            // Show task message
-            self.widget.set_state(State::Good);
-            self.set_text(self.block_config.break_message.clone())
-                .await?;
-            if let Some(cmd) = &self.block_config.notify_cmd {
-                let cmd = cmd.replace("{msg}", &self.block_config.break_message);
-                if self.block_config.blocking_cmd {
-                    spawn_shell_sync(&cmd)
+            Phase::TaskOver { .. } | Phase::BreakOver { .. } if self.notifier.is_some() => {
+                if let Some(notifier) = &mut self.notifier {
+                    notifier
+                        .wait()
                         .await
                         .block_error("pomodoro", "failed to run notify_cmd")?;
-                } else {
-                    spawn_shell(&cmd).block_error("pomodoro", "failed to run notify_cmd")?;
-                    self.wait_for_click(MouseButton::Left).await;
                 }
-            } else {
-                self.wait_for_click(MouseButton::Left).await;
+                self.notifier = None;
+                self.proceed();
             }
+            _ => futures::future::pending().await,
         }
-
         Ok(())
     }
-}
-
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    events_receiver: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    let block_config = PomodoroConfig::deserialize(block_config).block_config_error("pomodoro")?;
-    let widget = Widget::new(id, shared_config).with_icon("pomodoro")?;
-    let mut block = Block {
-        id,
-        widget,
-        block_config,
-        message_sender,
-        events_receiver,
-    };
 
-    loop {
-        // Send collaped block
-        block.widget.set_state(State::Idle);
-        block.set_text(String::new()).await?;
+    fn state(&self) -> Option<JsonValue> {
+        serde_json::to_value(PomodoroState {
+            phase: self.phase,
+            task_len: self.task_len,
+            break_len: self.break_len,
+            pomodoros: self.pomodoros,
+        })
+        .ok()
+    }
 
-        // Wait for left click
-        block.wait_for_click(MouseButton::Left).await;
+    fn restore_state(&mut self, state: JsonValue) {
+        if let Ok(state) = serde_json::from_value::<PomodoroState>(state) {
+            self.phase = state.phase;
+            self.task_len = state.task_len;
+            self.break_len = state.break_len;
+            self.pomodoros = state.pomodoros;
+        }
+    }
+}
 
-        // Read params
-        let (task_len, break_len, pomodoros) = block.read_params().await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // Run!
-        block.run_pomodoro(task_len, break_len, pomodoros).await?;
+    #[test]
+    fn test_state_timestamps() {
+        let phase = |end: &str| {
+            serde_json::from_str::<Phase>(&format!(
+                "{{\"Task\": {{\"pomodoro\": 1, \"end\": {}}}}}",
+                end
+            ))
+        };
+        assert!(matches!(phase("0"), Ok(Phase::Task { pomodoro: 1, .. })));
+        assert!(phase("-5").is_ok());
+        assert!(phase("1e300").is_err());
+        assert!(phase("18446744073709551616").is_err());
     }
 }
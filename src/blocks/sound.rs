@@ -1,17 +1,19 @@
-use serde::de::Deserialize;
 use std::cmp::{max, min};
 use std::collections::HashMap;
 use std::process::Stdio;
 use tokio::io::AsyncReadExt;
-use tokio::process::Command;
-use tokio::sync::mpsc;
+use tokio::process::{ChildStdout, Command};
 
-use super::{BlockEvent, BlockMessage};
+use async_trait::async_trait;
+
+use super::{Block, BlockEvent};
 use crate::click::MouseButton;
 use crate::config::SharedConfig;
 use crate::errors::*;
 use crate::formatting::value::Value;
 use crate::formatting::FormatTemplate;
+use crate::protocol::i3bar_block::I3BarBlock;
+use crate::subprocess::GroupChild;
 use crate::widget::{Spacing, State, Widget};
 
 const FILTER: &[char] = &['[', ']', '%'];
@@ -46,20 +48,25 @@ impl Default for SoundConfig {
     }
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    mut events_reciever: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    let block_config = SoundConfig::deserialize(block_config).block_config_error("sound")?;
-    let format = block_config.format.or_default("{volume}")?;
-    let mut text = Widget::new(id, shared_config);
-
-    let device_kind = block_config.device_kind;
-    let icon = |volume: u32| -> String {
-        let prefix = match device_kind {
+pub struct Sound {
+    text: Widget,
+    format: FormatTemplate,
+    device: AlsaSoundDevice,
+    device_kind: DeviceKind,
+    step_width: i32,
+    max_vol: Option<u32>,
+    show_volume_when_muted: bool,
+    mappings: Option<HashMap<String, String>>,
+    /// Kept so that `alsactl monitor` is killed when the block stops
+    _monitor_process: GroupChild,
+    monitor: ChildStdout,
+    buffer: [u8; 1024],
+    values: HashMap<String, Value>,
+}
+
+impl Sound {
+    fn icon(&self, volume: u32) -> String {
+        let prefix = match self.device_kind {
             DeviceKind::Source => "microphone",
             DeviceKind::Sink => "volume",
         };
@@ -72,79 +79,133 @@ pub async fn run(
         };
 
         format!("{}_{}", prefix, suffix)
-    };
-
-    let step_width = block_config.step_width.clamp(0, 50) as i32;
-
-    let mut device = AlsaSoundDevice::new(
-        block_config.name.unwrap_or_else(|| "Master".into()),
-        block_config.device.unwrap_or_else(|| "default".into()),
-        block_config.natural_mapping,
-    )
-    .await?;
-
-    let mut monitor = Command::new("stdbuf")
-        .args(&["-oL", "alsactl", "monitor"])
-        .stdout(Stdio::piped())
-        .spawn()
-        .block_error("sound", "Failed to start alsactl monitor")?
-        .stdout
-        .block_error("sound", "Failed to pipe alsactl monitor output")?;
-    let mut buffer = [0; 1024]; // Should be more than enough.
-
-    loop {
-        device.get_info().await?;
-        let volume = device.volume();
-        let mut output_name = device.output_name();
-
-        if let Some(m) = &block_config.mappings {
+    }
+}
+
+#[async_trait]
+impl Block for Sound {
+    const NAME: &'static str = "sound";
+    const PLACEHOLDERS: &'static [&'static str] = &["volume", "output_name"];
+    const ICONS: &'static [&'static str] = &[
+        "microphone_muted",
+        "microphone_empty",
+        "microphone_half",
+        "microphone_full",
+        "volume_muted",
+        "volume_empty",
+        "volume_half",
+        "volume_full",
+    ];
+
+    type Config = SoundConfig;
+
+    async fn init(id: usize, block_config: SoundConfig, shared_config: SharedConfig) -> Result<Self> {
+        let device = AlsaSoundDevice::new(
+            block_config.name.unwrap_or_else(|| "Master".into()),
+            block_config.device.unwrap_or_else(|| "default".into()),
+            block_config.natural_mapping,
+        )
+        .await?;
+
+        let mut monitor_process = GroupChild::spawn(
+            Command::new("stdbuf")
+                .args(&["-oL", "alsactl", "monitor"])
+                .stdout(Stdio::piped()),
+        )
+        .block_error("sound", "Failed to start alsactl monitor")?;
+        let monitor = monitor_process
+            .stdout()
+            .block_error("sound", "Failed to pipe alsactl monitor output")?;
+
+        Ok(Self {
+            text: Widget::new(id, shared_config),
+            format: block_config.format.or_default("{volume}")?,
+            device,
+            device_kind: block_config.device_kind,
+            step_width: block_config.step_width.clamp(0, 50) as i32,
+            max_vol: block_config.max_vol,
+            show_volume_when_muted: block_config.show_volume_when_muted,
+            mappings: block_config.mappings,
+            _monitor_process: monitor_process,
+            monitor,
+            buffer: [0; 1024], // Should be more than enough.
+            values: HashMap::new(),
+        })
+    }
+
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        self.device.get_info().await?;
+        let volume = self.device.volume();
+        let mut output_name = self.device.output_name();
+
+        if let Some(m) = &self.mappings {
             if let Some(mapped) = m.get(&output_name) {
                 output_name = mapped.to_string();
             }
         }
 
-        text.set_text(format.render(&map! {
+        let values = map_to_owned! {
             "volume" => Value::from_integer(volume as i64).percents(),
             "output_name" => Value::from_string(output_name),
-        })?);
-
-        if device.muted() {
-            text.set_icon(&icon(0))?;
-            text.set_state(State::Warning);
-            if !block_config.show_volume_when_muted {
-                text.set_text((String::new(), None));
+        };
+        self.text.set_text(self.format.render(&values)?);
+        self.values = values;
+
+        if self.device.muted() {
+            let icon = self.icon(0);
+            self.text.set_icon(&icon)?;
+            self.text.set_state(State::Warning);
+            if !self.show_volume_when_muted {
+                self.text.set_text((String::new(), None));
             }
         } else {
-            text.set_icon(&icon(volume))?;
-            text.set_spacing(Spacing::Normal);
-            text.set_state(State::Idle);
+            let icon = self.icon(volume);
+            self.text.set_icon(&icon)?;
+            self.text.set_spacing(Spacing::Normal);
+            self.text.set_state(State::Idle);
         }
 
-        message_sender
-            .send(BlockMessage {
-                id,
-                widgets: vec![text.get_data()],
-            })
-            .await
-            .internal_error("sound", "failed to send message")?;
-
-        tokio::select! {
-            _ = monitor.read(&mut buffer) => (),
-            Some(BlockEvent::I3Bar(click)) = events_reciever.recv() => {
-                match click.button {
-                    MouseButton::Right => {
-                        device.toggle().await?;
-                    }
-                    MouseButton::WheelUp => {
-                        device.set_volume(step_width, block_config.max_vol).await?;
-                    }
-                    MouseButton::WheelDown => {
-                        device.set_volume(-step_width, block_config.max_vol).await?;
+        Ok(vec![self.text.get_data()])
+    }
+
+    async fn handle_event(&mut self, event: BlockEvent) -> Result<bool> {
+        if let BlockEvent::I3Bar(click) = event {
+            match click.button {
+                MouseButton::Right => {
+                    self.device.toggle().await?;
+                }
+                MouseButton::WheelUp => {
+                    self.device.set_volume(self.step_width, self.max_vol).await?;
+                }
+                MouseButton::WheelDown => {
+                    self.device
+                        .set_volume(-self.step_width, self.max_vol)
+                        .await?;
+                }
+                // Set the volume to where the text was clicked, unless the click is bound in the
+                // config
+                MouseButton::Left if !click.handled => {
+                    if let Some(position) = self.text.text_position(&click) {
+                        let volume = (position * 100.0).round() as i32;
+                        self.device
+                            .set_volume(volume - self.device.volume() as i32, self.max_vol)
+                            .await?;
                     }
-                    _ => ()
                 }
+                _ => (),
             }
+            return Ok(true);
         }
+        Ok(false)
+    }
+
+    async fn wait_for_change(&mut self) -> Result<()> {
+        let _ = self.monitor.read(&mut self.buffer).await;
+        Ok(())
+    }
+
+    fn values(&self) -> HashMap<String, Value> {
+        self.values.clone()
     }
 }
 
@@ -26,21 +26,24 @@
 //! format = "{ping}{speed_down:4*B}{speed_up:4*B}"
 //! ```
 
-use serde::de::Deserialize;
+use std::collections::HashMap;
 use std::time::Duration;
 use tokio::process::Command;
-use tokio::sync::mpsc;
 
-use super::{BlockEvent, BlockMessage};
+use async_trait::async_trait;
+
+use super::Block;
 use crate::config::SharedConfig;
 use crate::de::deserialize_duration;
 use crate::errors::*;
 use crate::formatting::{value::Value, FormatTemplate};
+use crate::protocol::i3bar_block::I3BarBlock;
+use crate::subprocess;
 use crate::widget::Widget;
 
 #[derive(serde_derive::Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields, default)]
-struct SpeedtestConfig {
+pub struct SpeedtestConfig {
     format: FormatTemplate,
     #[serde(deserialize_with = "deserialize_duration")]
     interval: Duration,
@@ -55,32 +58,44 @@ impl Default for SpeedtestConfig {
     }
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    events_reciever: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    // Drop the reciever if we don't what to recieve events
-    drop(events_reciever);
+pub struct Speedtest {
+    text: Widget,
+    format: FormatTemplate,
+    interval: Duration,
+    icon_ping: String,
+    icon_down: String,
+    icon_up: String,
+    values: HashMap<String, Value>,
+}
+
+#[async_trait]
+impl Block for Speedtest {
+    const NAME: &'static str = "speedtest";
+    const PLACEHOLDERS: &'static [&'static str] = &["ping", "speed_down", "speed_up"];
+    const ICONS: &'static [&'static str] = &["ping", "net_down", "net_up"];
 
-    let icon_ping = shared_config.get_icon("ping")?;
-    let icon_down = shared_config.get_icon("net_down")?;
-    let icon_up = shared_config.get_icon("net_up")?;
-    let block_config =
-        SpeedtestConfig::deserialize(block_config).block_config_error("speedtest")?;
-    let format = block_config
-        .format
-        .or_default("{ping}{speed_down}{speed_up}")?;
-    let mut text = Widget::new(id, shared_config);
+    type Config = SpeedtestConfig;
 
-    let mut command = Command::new("speedtest-cli");
-    command.arg("--json");
+    async fn init(
+        id: usize,
+        block_config: SpeedtestConfig,
+        shared_config: SharedConfig,
+    ) -> Result<Self> {
+        Ok(Self {
+            icon_ping: shared_config.get_icon("ping")?,
+            icon_down: shared_config.get_icon("net_down")?,
+            icon_up: shared_config.get_icon("net_up")?,
+            format: block_config
+                .format
+                .or_default("{ping}{speed_down}{speed_up}")?,
+            interval: block_config.interval,
+            text: Widget::new(id, shared_config),
+            values: HashMap::new(),
+        })
+    }
 
-    loop {
-        let output = command
-            .output()
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        let output = subprocess::output(Command::new("speedtest-cli").arg("--json"))
             .await
             .block_error("speedtest", "failed to run 'speedtest-cli'")?
             .stdout;
@@ -89,21 +104,23 @@ pub async fn run(
         let output: SpeedtestCliOutput = serde_json::from_str(&output)
             .block_error("speedtest", "'speedtest-cli' produced wrong JSON")?;
 
-        text.set_text(format.render(&map! {
-            "ping" => Value::from_float(output.ping * 1e-3).seconds().icon(icon_ping.clone()),
-            "speed_down" => Value::from_float(output.download).bits().icon(icon_down.clone()),
-            "speed_up" => Value::from_float(output.upload).bits().icon(icon_up.clone()),
-        })?);
+        let values = map_to_owned! {
+            "ping" => Value::from_float(output.ping * 1e-3).seconds().icon(self.icon_ping.clone()),
+            "speed_down" => Value::from_float(output.download).bits().icon(self.icon_down.clone()),
+            "speed_up" => Value::from_float(output.upload).bits().icon(self.icon_up.clone()),
+        };
+        self.text.set_text(self.format.render(&values)?);
+        self.values = values;
 
-        message_sender
-            .send(BlockMessage {
-                id,
-                widgets: vec![text.get_data()],
-            })
-            .await
-            .internal_error("speedtest", "failed to send message")?;
+        Ok(vec![self.text.get_data()])
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(self.interval)
+    }
 
-        tokio::time::sleep(block_config.interval).await;
+    fn values(&self) -> HashMap<String, Value> {
+        self.values.clone()
     }
 }
 
@@ -1,14 +1,14 @@
 use futures::stream::StreamExt;
-use serde::de::Deserialize;
 use std::collections::HashMap;
-use tokio::sync::mpsc;
 
-use swayipc_async::{Connection, Event, EventType};
+use async_trait::async_trait;
+use swayipc_async::{Connection, Event, EventStream, EventType};
 
-use super::{BlockEvent, BlockMessage};
+use super::Block;
 use crate::config::SharedConfig;
 use crate::errors::*;
 use crate::formatting::{value::Value, FormatTemplate};
+use crate::protocol::i3bar_block::I3BarBlock;
 use crate::widget::Widget;
 
 #[derive(serde_derive::Deserialize, Debug, Clone)]
@@ -20,64 +20,63 @@ pub struct SwayKbdConfig {
     pub mappings: Option<HashMap<String, String>>,
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    events_reciever: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    // Drop the reciever if we don't what to recieve events
-    drop(events_reciever);
+pub struct SwayKbd {
+    text: Widget,
+    format: FormatTemplate,
+    mappings: Option<HashMap<String, String>>,
+    layout: String,
+    events: EventStream,
+}
 
-    let block_config = SwayKbdConfig::deserialize(block_config).block_config_error("sway_kbd")?;
-    let format = block_config.format.or_default("{layout}")?;
-    let mut text = Widget::new(id, shared_config);
+#[async_trait]
+impl Block for SwayKbd {
+    const NAME: &'static str = "sway_kbd";
+    const PLACEHOLDERS: &'static [&'static str] = &["layout"];
 
-    // New connection
-    let mut connection = Connection::new()
-        .await
-        .block_error("sway_kbd", "failed to open swayipc connection")?;
+    type Config = SwayKbdConfig;
 
-    // Get current layout
-    let mut layout = connection
-        .get_inputs()
-        .await
-        .block_error("sway_kbd", "failed to get current input")?
-        .iter()
-        .find(|i| i.input_type == "keyboard")
-        .map(|i| i.xkb_active_layout_name.clone())
-        .flatten()
-        .block_error("sway_kbd", "failed to get current input")?;
+    async fn init(id: usize, block_config: SwayKbdConfig, shared_config: SharedConfig) -> Result<Self> {
+        // New connection
+        let mut connection = Connection::new()
+            .await
+            .block_error("sway_kbd", "failed to open swayipc connection")?;
 
-    // Subscribe to events
-    let mut events = connection
-        .subscribe(&[EventType::Input])
-        .await
-        .block_error("sway_kbd", "failed to subscribe to events")?;
+        // Get current layout
+        let layout = connection
+            .get_inputs()
+            .await
+            .block_error("sway_kbd", "failed to get current input")?
+            .iter()
+            .find(|i| i.input_type == "keyboard")
+            .map(|i| i.xkb_active_layout_name.clone())
+            .flatten()
+            .block_error("sway_kbd", "failed to get current input")?;
 
-    loop {
-        let layout_mapped = if let Some(ref mappings) = block_config.mappings {
-            mappings.get(&layout).unwrap_or(&layout).to_string()
-        } else {
-            layout.clone()
-        };
+        // Subscribe to events
+        let events = connection
+            .subscribe(&[EventType::Input])
+            .await
+            .block_error("sway_kbd", "failed to subscribe to events")?;
 
-        text.set_text(format.render(&map! {
-            "layout" => Value::from_string(layout_mapped),
-        })?);
+        Ok(Self {
+            text: Widget::new(id, shared_config),
+            format: block_config.format.or_default("{layout}")?,
+            mappings: block_config.mappings,
+            layout,
+            events,
+        })
+    }
 
-        message_sender
-            .send(BlockMessage {
-                id,
-                widgets: vec![text.get_data()],
-            })
-            .await
-            .internal_error("sway_kbd", "failed to send message")?;
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        self.text.set_text(self.format.render(&self.values())?);
 
-        // Wait for new event
+        Ok(vec![self.text.get_data()])
+    }
+
+    async fn wait_for_change(&mut self) -> Result<()> {
         loop {
-            let event = events
+            let event = self
+                .events
                 .next()
                 .await
                 .block_error("sway_kbd", "swayipc channel closed")?
@@ -85,12 +84,24 @@ pub async fn run(
             if let Event::Input(event) = event {
                 if let Some(new_layout) = event.input.xkb_active_layout_name {
                     // Update only if layout has changed
-                    if new_layout != layout {
-                        layout = new_layout;
-                        break;
+                    if new_layout != self.layout {
+                        self.layout = new_layout;
+                        return Ok(());
                     }
                 }
             }
         }
     }
+
+    fn values(&self) -> HashMap<String, Value> {
+        let layout_mapped = if let Some(ref mappings) = self.mappings {
+            mappings.get(&self.layout).unwrap_or(&self.layout).to_string()
+        } else {
+            self.layout.clone()
+        };
+
+        map_to_owned! {
+            "layout" => Value::from_string(layout_mapped),
+        }
+    }
 }
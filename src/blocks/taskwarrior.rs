@@ -39,22 +39,26 @@
 //! filter = "project:some-project +PENDING"
 //! ```
 
-use serde::de::Deserialize;
+use std::collections::HashMap;
 use std::time::Duration;
 use tokio::process::Command;
-use tokio::sync::mpsc;
 
-use super::{BlockEvent, BlockMessage};
+use async_trait::async_trait;
+use serde_json::Value as JsonValue;
+
+use super::{Block, BlockEvent};
 use crate::click::MouseButton;
 use crate::config::SharedConfig;
 use crate::de::deserialize_duration;
 use crate::errors::*;
 use crate::formatting::{value::Value, FormatTemplate};
+use crate::protocol::i3bar_block::I3BarBlock;
+use crate::subprocess;
 use crate::widget::{State, Widget};
 
 #[derive(serde_derive::Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields, default)]
-struct TaskwarriorConfig {
+pub struct TaskwarriorConfig {
     #[serde(deserialize_with = "deserialize_duration")]
     interval: Duration,
     warning_threshold: u32,
@@ -84,77 +88,119 @@ impl Default for TaskwarriorConfig {
     }
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    mut events_reciever: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    let block_config =
-        TaskwarriorConfig::deserialize(block_config).block_config_error("taskwarrior")?;
-    let format = block_config.format.or_default("{count}")?;
-    let format_singular = block_config.format_singular.or_default("{count}")?;
-    let format_everything_done = block_config.format_everything_done.or_default("{count}")?;
-    let mut widget = Widget::new(id, shared_config).with_icon("tasks")?;
-
-    let mut filters = block_config.filters.iter().cycle();
-    let mut filter = filters
-        .next()
-        .block_error("taskwarrior", "failed to get next filter")?;
-
-    loop {
+pub struct Taskwarrior {
+    widget: Widget,
+    format: FormatTemplate,
+    format_singular: FormatTemplate,
+    format_everything_done: FormatTemplate,
+    filter_index: usize,
+    block_config: TaskwarriorConfig,
+    values: HashMap<String, Value>,
+}
+
+#[async_trait]
+impl Block for Taskwarrior {
+    const NAME: &'static str = "taskwarrior";
+    const PLACEHOLDERS: &'static [&'static str] = &["count", "filter_name"];
+    const ICONS: &'static [&'static str] = &["tasks"];
+
+    type Config = TaskwarriorConfig;
+
+    async fn init(
+        id: usize,
+        block_config: TaskwarriorConfig,
+        shared_config: SharedConfig,
+    ) -> Result<Self> {
+        if block_config.filters.is_empty() {
+            return block_error("taskwarrior", "failed to get next filter");
+        }
+
+        Ok(Self {
+            widget: Widget::new(id, shared_config).with_icon("tasks")?,
+            format: block_config.format.clone().or_default("{count}")?,
+            format_singular: block_config.format_singular.clone().or_default("{count}")?,
+            format_everything_done: block_config
+                .format_everything_done
+                .clone()
+                .or_default("{count}")?,
+            filter_index: 0,
+            block_config,
+            values: HashMap::new(),
+        })
+    }
+
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        let filter = &self.block_config.filters[self.filter_index];
         let number_of_tasks = get_number_of_tasks(&filter.filter).await?;
-        let values = map!(
+        let values = map_to_owned!(
             "count" => Value::from_integer(number_of_tasks as i64),
             "filter_name" => Value::from_string(filter.name.clone()),
         );
-        widget.set_text(match number_of_tasks {
-            0 => format_everything_done.render(&values)?,
-            1 => format_singular.render(&values)?,
-            _ => format.render(&values)?,
-        });
-        widget.set_state(if number_of_tasks >= block_config.critical_threshold {
-            State::Critical
-        } else if number_of_tasks >= block_config.warning_threshold {
-            State::Warning
-        } else {
-            State::Idle
+        self.widget.set_text(match number_of_tasks {
+            0 => self.format_everything_done.render(&values)?,
+            1 => self.format_singular.render(&values)?,
+            _ => self.format.render(&values)?,
         });
+        self.values = values;
+        self.widget
+            .set_state(if number_of_tasks >= self.block_config.critical_threshold {
+                State::Critical
+            } else if number_of_tasks >= self.block_config.warning_threshold {
+                State::Warning
+            } else {
+                State::Idle
+            });
 
-        let widgets = if number_of_tasks == 0 && block_config.hide_when_zero {
+        Ok(if number_of_tasks == 0 && self.block_config.hide_when_zero {
             vec![]
         } else {
-            vec![widget.get_data()]
-        };
-
-        message_sender
-            .send(BlockMessage { id, widgets })
-            .await
-            .internal_error("taskwarrior", "failed to send message")?;
-
-        tokio::select! {
-            _ = tokio::time::sleep(block_config.interval) =>(),
-            Some(BlockEvent::I3Bar(click)) = events_reciever.recv() => {
-                if click.button == MouseButton::Right {
-                    filter = filters.next().block_error("taskwarrior", "failed to get next filter")?;
-                }
+            vec![self.widget.get_data()]
+        })
+    }
+
+    async fn handle_event(&mut self, event: BlockEvent) -> Result<bool> {
+        if let BlockEvent::I3Bar(click) = event {
+            if click.button == MouseButton::Right {
+                self.filter_index = (self.filter_index + 1) % self.block_config.filters.len();
             }
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(self.block_config.interval)
+    }
+
+    /// The name of the selected filter, so that editing the list of filters doesn't select another
+    fn state(&self) -> Option<JsonValue> {
+        let filter = &self.block_config.filters[self.filter_index];
+        Some(JsonValue::String(filter.name.clone()))
+    }
+
+    fn restore_state(&mut self, state: JsonValue) {
+        let filters = &self.block_config.filters;
+        if let Some(index) = filters.iter().position(|f| Some(&*f.name) == state.as_str()) {
+            self.filter_index = index;
         }
     }
+
+    fn values(&self) -> HashMap<String, Value> {
+        self.values.clone()
+    }
 }
 
 async fn get_number_of_tasks(filter: &str) -> Result<u32> {
     String::from_utf8(
-        Command::new("sh")
-            .args(&["-c", &format!("task rc.gc=off {} count", filter)])
-            .output()
-            .await
-            .block_error(
-                "taskwarrior",
-                "failed to run taskwarrior for getting the number of tasks",
-            )?
-            .stdout,
+        subprocess::output(
+            Command::new("sh").args(&["-c", &format!("task rc.gc=off {} count", filter)]),
+        )
+        .await
+        .block_error(
+            "taskwarrior",
+            "failed to run taskwarrior for getting the number of tasks",
+        )?
+        .stdout,
     )
     .block_error(
         "taskwarrior",
@@ -167,7 +213,7 @@ async fn get_number_of_tasks(filter: &str) -> Result<u32> {
 
 #[derive(serde_derive::Deserialize, Debug, Default, Clone)]
 #[serde(deny_unknown_fields)]
-struct Filter {
+pub struct Filter {
     pub name: String,
     pub filter: String,
 }
@@ -26,7 +26,7 @@
 //! Placeholder  | Value                                | Type    | Unit
 //! -------------|--------------------------------------|---------|--------
 //! `{min}`      | Minimum temperature among all inputs | Integer | Degrees
-//! `{average}`  | Average temperature among all inputs | Integer | Degrees
+//! `{average}`  | Average temperature among all inputs (also available as `{avg}`) | Integer | Degrees
 //! `{max}`      | Maximum temperature among all inputs | Integer | Degrees
 //!
 //! # Example
@@ -38,22 +38,25 @@
 //! format = "{min} min, {max} max, {average} avg"
 //! ```
 
-use serde::de::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::fs::{read_dir, read_to_string};
-use tokio::sync::mpsc;
 
-use super::{BlockEvent, BlockMessage};
+use async_trait::async_trait;
+
+use super::{Block, BlockEvent};
 use crate::click::MouseButton;
 use crate::config::SharedConfig;
 use crate::de::deserialize_duration;
 use crate::errors::*;
 use crate::formatting::{value::Value, FormatTemplate};
+use crate::protocol::i3bar_block::I3BarBlock;
+use crate::sampler::Sampler;
 use crate::widget::{State, Widget};
 
 #[derive(serde_derive::Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields, default)]
-struct TemperatureConfig {
+pub struct TemperatureConfig {
     format: FormatTemplate,
     #[serde(deserialize_with = "deserialize_duration")]
     interval: Duration,
@@ -80,40 +83,67 @@ impl Default for TemperatureConfig {
     }
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    mut events_reciever: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    let block_config =
-        TemperatureConfig::deserialize(block_config).block_config_error("temperature")?;
-    let format = block_config.format.or_default("{average} avg, {max} max")?;
-    let mut text = Widget::new(id, shared_config).with_icon("thermometer")?;
-    let mut collapsed = block_config.collapsed;
-
-    loop {
+pub struct Temperature {
+    text: Widget,
+    format: FormatTemplate,
+    collapsed: bool,
+    block_config: TemperatureConfig,
+    sampler: Arc<Sampler>,
+    values: HashMap<String, Value>,
+}
+
+#[async_trait]
+impl Block for Temperature {
+    const NAME: &'static str = "temperature";
+    const PLACEHOLDERS: &'static [&'static str] = &["average", "avg", "min", "max"];
+    const ICONS: &'static [&'static str] = &["thermometer"];
+
+    type Config = TemperatureConfig;
+
+    async fn init(
+        id: usize,
+        block_config: TemperatureConfig,
+        shared_config: SharedConfig,
+    ) -> Result<Self> {
+        Ok(Self {
+            sampler: shared_config.sampler.clone(),
+            text: Widget::new(id, shared_config).with_icon("thermometer")?,
+            format: block_config
+                .format
+                .clone()
+                .or_default("{average} avg, {max} max")?,
+            collapsed: block_config.collapsed,
+            block_config,
+            values: HashMap::new(),
+        })
+    }
+
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
         // Get chip info
-        let temp = ChipInfo::new(&block_config.chip).await?.temp;
+        let temp = self.sampler.temperatures(&self.block_config.chip).await?;
         let min_temp = temp.iter().min().cloned().unwrap_or(0);
         let max_temp = temp.iter().max().cloned().unwrap_or(0);
         let avg_temp = (temp.iter().sum::<i32>() as f64) / (temp.len() as f64);
 
         // Render!
-        let values = map! {
-            "avg" => Value::from_integer(avg_temp.round() as i64).degrees(),
+        let average = Value::from_integer(avg_temp.round() as i64).degrees();
+        let values = map_to_owned! {
+            // `avg` is the name used by older configs
+            "avg" => average.clone(),
+            "average" => average,
             "min" => Value::from_integer(min_temp as i64).degrees(),
             "max" => Value::from_integer(max_temp as i64).degrees(),
         };
-        text.set_text(if collapsed {
+        self.text.set_text(if self.collapsed {
             (String::new(), None)
         } else {
-            format.render(&values)?
+            self.format.render(&values)?
         });
+        self.values = values;
 
         // Set state
-        text.set_state(match max_temp {
+        let block_config = &self.block_config;
+        self.text.set_state(match max_temp {
             x if x <= block_config.good => State::Good,
             x if x <= block_config.idle => State::Idle,
             x if x <= block_config.info => State::Info,
@@ -121,68 +151,24 @@ pub async fn run(
             _ => State::Critical,
         });
 
-        message_sender
-            .send(BlockMessage {
-                id,
-                widgets: vec![text.get_data()],
-            })
-            .await
-            .internal_error("temperature", "failed to send message")?;
-
-        tokio::select! {
-            _ = tokio::time::sleep(block_config.interval) => (),
-            Some(BlockEvent::I3Bar(click)) = events_reciever.recv() => {
-                if click.button == MouseButton::Left {
-                    collapsed = !collapsed;
-                }
+        Ok(vec![self.text.get_data()])
+    }
+
+    async fn handle_event(&mut self, event: BlockEvent) -> Result<bool> {
+        if let BlockEvent::I3Bar(click) = event {
+            if click.button == MouseButton::Left {
+                self.collapsed = !self.collapsed;
             }
+            return Ok(true);
         }
+        Ok(false)
     }
-}
 
-#[derive(Debug, Clone)]
-struct ChipInfo {
-    temp: Vec<i32>,
-}
+    fn interval(&self) -> Option<Duration> {
+        Some(self.block_config.interval)
+    }
 
-impl ChipInfo {
-    async fn new(name: &str) -> Result<Self> {
-        let mut sysfs_dir = read_dir("/sys/class/hwmon")
-            .await
-            .block_error("temperature", "failed to read /sys/class/hwmon direcory")?;
-        while let Some(dir) = sysfs_dir
-            .next_entry()
-            .await
-            .block_error("temperature", "failed to read /sys/class/hwmon direcory")?
-        {
-            if read_to_string(dir.path().join("name"))
-                .await
-                .map(|t| t.trim() == name)
-                .unwrap_or(false)
-            {
-                let mut chip_dir = read_dir(dir.path())
-                    .await
-                    .block_error("temperature", "failed to read chip's sysfs direcory")?;
-                let mut temp = Vec::new();
-                while let Some(entry) = chip_dir
-                    .next_entry()
-                    .await
-                    .block_error("temperature", "failed to read chip's sysfs direcory")?
-                {
-                    let entry_str = entry.file_name().to_str().unwrap().to_string();
-                    if entry_str.starts_with("temp") && entry_str.ends_with("_input") {
-                        let val: i32 = read_to_string(entry.path())
-                            .await
-                            .block_error("temperature", "failed to read chip's temperature")?
-                            .trim()
-                            .parse()
-                            .block_error("temperature", "temperature is not an integer")?;
-                        temp.push(val / 1000);
-                    }
-                }
-                return Ok(Self { temp });
-            }
-        }
-        block_error("temperature", &format!("chip '{}' not found", name))
+    fn values(&self) -> HashMap<String, Value> {
+        self.values.clone()
     }
 }
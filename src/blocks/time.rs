@@ -22,25 +22,26 @@
 //! short = "%R"
 //! ```
 
-use serde::de::Deserialize;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::time::Duration;
-use tokio::sync::mpsc;
+
+use async_trait::async_trait;
 
 use chrono::offset::{Local, Utc};
 use chrono::Locale;
 use chrono_tz::Tz;
 
-use super::{BlockEvent, BlockMessage};
+use super::Block;
 use crate::config::SharedConfig;
 use crate::errors::*;
 use crate::formatting::FormatTemplate;
+use crate::protocol::i3bar_block::I3BarBlock;
 use crate::widget::Widget;
 
 #[derive(serde_derive::Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields, default)]
-struct TimeConfig {
+pub struct TimeConfig {
     format: FormatTemplate,
     interval: u64,
     timezone: Option<Tz>,
@@ -58,53 +59,63 @@ impl Default for TimeConfig {
     }
 }
 
-pub async fn run(
-    id: usize,
-    block_config: toml::Value,
-    shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    events_reciever: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    // Drop the reciever if we don't what to recieve events
-    drop(events_reciever);
-
-    let block_config = TimeConfig::deserialize(block_config).block_config_error("time")?;
-    let mut interval = tokio::time::interval(Duration::from_secs(block_config.interval));
-    let mut text = Widget::new(id, shared_config).with_icon("time")?;
-    // `FormatTemplate` doesn't do much stuff here - we just want to get the original "full" and
-    // "short" formats, so we "render" it without providing any placeholders.
-    let (format, format_short) = block_config
-        .format
-        .or_default("")?
-        .render(&HashMap::<&str, _>::new())?;
-    let format = format.as_str();
-    let format_short = format_short.as_deref();
-
-    let timezone = block_config.timezone;
-    let locale = match block_config.locale.as_deref() {
-        Some(locale) => Some(
-            locale
-                .try_into()
-                .ok()
-                .block_error("time", "invalid locale")?,
-        ),
-        None => None,
-    };
-
-    loop {
-        let full_time = get_time(format, timezone, locale);
-        let short_time = format_short.map(|f| get_time(f, timezone, locale));
-        text.set_text((full_time, short_time));
-
-        message_sender
-            .send(BlockMessage {
-                id,
-                widgets: vec![text.get_data()],
-            })
-            .await
-            .internal_error("time", "failed to send message")?;
-
-        interval.tick().await;
+pub struct Time {
+    text: Widget,
+    format: String,
+    format_short: Option<String>,
+    interval: Duration,
+    timezone: Option<Tz>,
+    locale: Option<Locale>,
+}
+
+#[async_trait]
+impl Block for Time {
+    const NAME: &'static str = "time";
+    const ICONS: &'static [&'static str] = &["time"];
+
+    type Config = TimeConfig;
+
+    async fn init(id: usize, block_config: TimeConfig, shared_config: SharedConfig) -> Result<Self> {
+        // `FormatTemplate` doesn't do much stuff here - we just want to get the original "full" and
+        // "short" formats, so we "render" it without providing any placeholders.
+        let (format, format_short) = block_config
+            .format
+            .or_default("")?
+            .render(&HashMap::<&str, _>::new())?;
+
+        let locale = match block_config.locale.as_deref() {
+            Some(locale) => Some(
+                locale
+                    .try_into()
+                    .ok()
+                    .block_error("time", "invalid locale")?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            text: Widget::new(id, shared_config).with_icon("time")?,
+            format,
+            format_short,
+            interval: Duration::from_secs(block_config.interval),
+            timezone: block_config.timezone,
+            locale,
+        })
+    }
+
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        let full_time = get_time(&self.format, self.timezone, self.locale);
+        let short_time = self
+            .format_short
+            .as_deref()
+            .map(|f| get_time(f, self.timezone, self.locale));
+        self.text.set_text((full_time, short_time));
+
+        Ok(vec![self.text.get_data()])
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(self.interval)
     }
 }
 
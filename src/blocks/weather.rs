@@ -1,15 +1,16 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
-use serde::de::Deserialize;
+use async_trait::async_trait;
 use serde_derive::Deserialize;
-use tokio::sync::mpsc;
 
-use crate::blocks::{BlockEvent, BlockMessage};
+use crate::blocks::Block;
 use crate::config::SharedConfig;
 use crate::de::deserialize_duration;
 use crate::errors::{OptionExt, Result, ResultExt};
 use crate::formatting::value::Value;
 use crate::formatting::FormatTemplate;
+use crate::protocol::i3bar_block::I3BarBlock;
 use crate::widget::Widget;
 
 const IP_API_URL: &str = "https://ipapi.co/json";
@@ -46,7 +47,7 @@ struct ApiWeather {
 
 #[derive(Clone, Debug, Deserialize)]
 #[serde(tag = "name", rename_all = "lowercase")]
-enum WeatherService {
+pub enum WeatherService {
     OpenWeatherMap {
         api_key: Option<String>,
         city_id: Option<String>,
@@ -129,7 +130,7 @@ impl Default for WeatherService {
 
 #[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
-enum OpenWeatherMapUnits {
+pub enum OpenWeatherMapUnits {
     Metric,
     Imperial,
 }
@@ -146,13 +147,15 @@ async fn find_ip_location() -> Result<Option<String>> {
         city: Option<String>,
     }
 
-    let res: ApiResponse = dbg!(reqwest::get(IP_API_URL).await)
+    let res: ApiResponse = reqwest::get(IP_API_URL)
+        .await
         .block_error("weather", "failed during request for current location")?
         .json()
         .await
         .block_error("weather", "failed while parsing location API result")?;
 
-    Ok(dbg!(res.city))
+    log::debug!("Location from {}: {:?}", IP_API_URL, res.city);
+    Ok(res.city)
 }
 
 // Compute the Australian Apparent Temperature (AT),
@@ -205,7 +208,7 @@ fn convert_wind_direction(direction_opt: Option<f64>) -> String {
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields, default)]
-struct WeatherConfig {
+pub struct WeatherConfig {
     #[serde(deserialize_with = "deserialize_duration")]
     interval: Duration,
     format: FormatTemplate,
@@ -224,21 +227,53 @@ impl Default for WeatherConfig {
     }
 }
 
-pub async fn run(
+pub struct Weather {
     id: usize,
-    block_config: toml::Value,
     shared_config: SharedConfig,
-    message_sender: mpsc::Sender<BlockMessage>,
-    events_receiver: mpsc::Receiver<BlockEvent>,
-) -> Result<()> {
-    std::mem::drop(events_receiver);
-    let block_config = WeatherConfig::deserialize(block_config).block_config_error("weather")?;
-    let format = block_config
-        .format
-        .clone()
-        .or_default("{weather} {temp}\u{00b0}")?;
-
-    let update = || async {
+    format: FormatTemplate,
+    block_config: WeatherConfig,
+    values: HashMap<String, Value>,
+}
+
+#[async_trait]
+impl Block for Weather {
+    const NAME: &'static str = "weather";
+    const PLACEHOLDERS: &'static [&'static str] = &[
+        "weather",
+        "temp",
+        "humidity",
+        "apparent",
+        "wind",
+        "wind_kmh",
+        "direction",
+        "location",
+    ];
+    const ICONS: &'static [&'static str] = &[
+        "weather_sun",
+        "weather_rain",
+        "weather_clouds",
+        "weather_thunder",
+        "weather_snow",
+        "weather_default",
+    ];
+
+    type Config = WeatherConfig;
+
+    async fn init(id: usize, block_config: WeatherConfig, shared_config: SharedConfig) -> Result<Self> {
+        Ok(Self {
+            id,
+            shared_config,
+            format: block_config
+                .format
+                .clone()
+                .or_default("{weather} {temp}\u{00b0}")?,
+            block_config,
+            values: HashMap::new(),
+        })
+    }
+
+    async fn update(&mut self) -> Result<Vec<I3BarBlock>> {
+        let block_config = &self.block_config;
         let data = block_config.service.get(block_config.autolocate).await?;
 
         let apparent_temp = australian_apparent_temp(
@@ -254,7 +289,7 @@ pub async fn run(
                 OpenWeatherMapUnits::Imperial => 0.447 * data.wind.speed,
             };
 
-        let keys = map! {
+        let keys = map_to_owned! {
             "weather" => Value::from_string(data.weather[0].main.to_string()),
             "temp" => Value::from_float(data.main.temp),
             "humidity" => Value::from_float(data.main.humidity),
@@ -274,24 +309,20 @@ pub async fn run(
             _ => "weather_default",
         };
 
-        let widget = Widget::new(id, shared_config.clone())
-            .with_text(format.render(&keys)?)
+        let widget = Widget::new(self.id, self.shared_config.clone())
+            .with_text(self.format.render(&keys)?)
             .with_icon(icon)?
             .get_data();
+        self.values = keys;
 
-        message_sender
-            .send(BlockMessage {
-                id,
-                widgets: vec![widget],
-            })
-            .await
-            .internal_error("backlight", "failed to send message")?;
+        Ok(vec![widget])
+    }
 
-        Ok(())
-    };
+    fn interval(&self) -> Option<Duration> {
+        Some(self.block_config.interval)
+    }
 
-    loop {
-        update().await?;
-        tokio::time::sleep(block_config.interval).await;
+    fn values(&self) -> HashMap<String, Value> {
+        self.values.clone()
     }
 }
@@ -0,0 +1,237 @@
+//! Validate the config file without starting the bar
+
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+use toml::value::{Table, Value};
+
+use crate::blocks::{find_block, BLOCKS};
+use crate::config::{Config, SharedConfig};
+use crate::util::did_you_mean;
+
+/// A mistake in the config
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// The option the problem is related to
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn new(key: Option<&str>, message: impl Into<String>) -> Self {
+        Self {
+            key: key.map(str::to_string),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "{}: {}", key, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Check the config file and print all problems found in it. Returns `false` if there are any.
+pub fn check_config_file(path: &Path) -> bool {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) => {
+            println!("{}: {}", path.display(), error);
+            return false;
+        }
+    };
+
+    let problems = check_config(&text);
+    for (line, message) in &problems {
+        match line {
+            Some(line) => println!("{}:{}: {}", path.display(), line, message),
+            None => println!("{}: {}", path.display(), message),
+        }
+    }
+    problems.is_empty()
+}
+
+/// Find all problems in the config. Returns the (1-based) line of each problem, if known, and
+/// a description.
+pub fn check_config(text: &str) -> Vec<(Option<usize>, String)> {
+    let mut config: Table = match toml::from_str(text) {
+        Ok(config) => config,
+        Err(error) => {
+            return vec![(
+                error.line_col().map(|(line, _)| line + 1),
+                error.to_string(),
+            )];
+        }
+    };
+
+    let mut problems = Vec::new();
+
+    // Check the global options. The blocks are checked separately, so that all problems are found.
+    let raw_blocks = config
+        .insert("block".to_string(), Value::Array(Vec::new()))
+        .unwrap_or_else(|| Value::Array(Vec::new()));
+    let shared_config = match Value::Table(config).try_into::<Config>() {
+        Ok(config) => SharedConfig::new(&config),
+        Err(error) => {
+            problems.push((None, error.to_string()));
+            SharedConfig::default()
+        }
+    };
+
+    let raw_blocks = match raw_blocks {
+        Value::Array(raw_blocks) => raw_blocks,
+        _ => {
+            problems.push((
+                line_of(text, 0, text.len(), "block"),
+                "'block' must be an array of tables".to_string(),
+            ));
+            Vec::new()
+        }
+    };
+
+    let headers = block_headers(text);
+    let mut names = HashSet::new();
+    for (index, raw_block) in raw_blocks.into_iter().enumerate() {
+        // The text of this block, if it is declared with `[[block]]`
+        let span = headers.get(index).map(|&start| {
+            let end = headers.get(index + 1).copied().unwrap_or(text.len());
+            (start, end)
+        });
+        let line = span.map(|(start, _)| line_number(text, start));
+        let key_line = |key: &str| span.and_then(|(start, end)| line_of(text, start, end, key));
+
+        let mut table = match raw_block {
+            Value::Table(table) => table,
+            _ => {
+                problems.push((line, "block must be a table".to_string()));
+                continue;
+            }
+        };
+        if let Some(name) = table.get("name").and_then(Value::as_str) {
+            if !names.insert(name.to_string()) {
+                problems.push((key_line("name"), format!("duplicate block name '{}'", name)));
+            }
+        }
+        let block = match table.remove("block") {
+            Some(Value::String(name)) => match find_block(&name) {
+                Some(block) => block,
+                None => {
+                    let candidates = BLOCKS.iter().map(|block| block.name);
+                    problems.push((
+                        key_line("block"),
+                        format!(
+                            "unknown block '{}'{}",
+                            name,
+                            did_you_mean(&name, candidates)
+                        ),
+                    ));
+                    continue;
+                }
+            },
+            Some(_) => {
+                problems.push((key_line("block"), "block name must be a string".to_string()));
+                continue;
+            }
+            None => {
+                problems.push((line, "missing field 'block'".to_string()));
+                continue;
+            }
+        };
+
+        for problem in (block.check)(Value::Table(table), &shared_config) {
+            let line = problem.key.as_deref().and_then(key_line).or(line);
+            problems.push((line, format!("block '{}': {}", block.name, problem)));
+        }
+    }
+
+    problems
+}
+
+/// Byte offsets of all `[[block]]` headers
+fn block_headers(text: &str) -> Vec<usize> {
+    let mut offset = 0;
+    let mut headers = Vec::new();
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed.starts_with("[[") && trimmed.trim_matches(&['[', ']'][..]).trim() == "block" {
+            headers.push(offset);
+        }
+        offset += line.len();
+    }
+    headers
+}
+
+/// The line on which `key` is set, searching between the byte offsets `start` and `end`
+fn line_of(text: &str, start: usize, end: usize, key: &str) -> Option<usize> {
+    let mut offset = start;
+    for line in text[start..end].split_inclusive('\n') {
+        if let Some((k, _)) = line.split_once('=') {
+            if k.trim().trim_matches('"') == key {
+                return Some(line_number(text, offset));
+            }
+        }
+        offset += line.len();
+    }
+    None
+}
+
+fn line_number(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_config() {
+        let config = r#"
+icons = "none"
+
+[[block]]
+block = "load"
+format = "{1m} {2m}"
+
+[[block]]
+block = "cpuu"
+
+[[block]]
+block = "temperature"
+collapsed = false
+format = "{average}"
+"#;
+        // The "none" icon set lacks most icons
+        let check = |config: &str| -> Vec<_> {
+            check_config(config)
+                .into_iter()
+                .filter(|(_, message)| !message.contains("icon '"))
+                .collect()
+        };
+
+        assert_eq!(
+            check(config),
+            &[
+                (
+                    Some(6),
+                    "block 'load': format: unknown placeholder '2m'".to_string()
+                ),
+                (
+                    Some(9),
+                    "unknown block 'cpuu', did you mean 'cpu'?".to_string()
+                ),
+            ]
+        );
+
+        assert_eq!(check_config("[[block]\n").len(), 1);
+        assert_eq!(
+            check("[[block]]\nblock = \"time\"\nname = \"a\"\n[[block]]\nblock = \"time\"\nname = \"a\"\n"),
+            &[(Some(6), "duplicate block name 'a'".to_string())]
+        );
+        assert_eq!(check("[[block]]\nblock = \"time\"\n"), &[]);
+    }
+}
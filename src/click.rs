@@ -1,7 +1,13 @@
 use serde::de::{self, Deserialize, Deserializer, Visitor};
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt;
 
-use crate::subprocess::{spawn_shell, spawn_shell_sync};
+use crate::formatting::placeholder::Placeholder;
+use crate::formatting::value::Value;
+use crate::formatting::FormatTemplate;
+use crate::protocol::i3bar_event::I3BarEvent;
+use crate::subprocess::{shell, spawn, GroupChild};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MouseButton {
@@ -13,23 +19,100 @@ pub enum MouseButton {
     Forward,
     Back,
     Unknown,
-    /// Experemental
     DoubleLeft,
+    DoubleMiddle,
+    DoubleRight,
+    DoubleForward,
+    DoubleBack,
+    TripleLeft,
+    TripleMiddle,
+    TripleRight,
+    TripleForward,
+    TripleBack,
+}
+
+// Deriving `Default` for an enum needs Rust 1.62
+#[allow(clippy::derivable_impls)]
+impl Default for MouseButton {
+    fn default() -> Self {
+        Self::Unknown
+    }
+}
+
+/// The buttons which can be double and triple clicked. Scrolling several times is not a gesture.
+const MULTI_CLICKS: &[[MouseButton; 3]] = {
+    use MouseButton::*;
+    &[
+        [Left, DoubleLeft, TripleLeft],
+        [Middle, DoubleMiddle, TripleMiddle],
+        [Right, DoubleRight, TripleRight],
+        [Forward, DoubleForward, TripleForward],
+        [Back, DoubleBack, TripleBack],
+    ]
+};
+
+impl MouseButton {
+    /// The name of the button, as used in the config
+    pub fn name(self) -> &'static str {
+        use MouseButton::*;
+        match self {
+            Left => "left",
+            Middle => "middle",
+            Right => "right",
+            WheelUp => "up",
+            WheelDown => "down",
+            Forward => "forward",
+            Back => "back",
+            Unknown => "unknown",
+            DoubleLeft => "double_left",
+            DoubleMiddle => "double_middle",
+            DoubleRight => "double_right",
+            DoubleForward => "double_forward",
+            DoubleBack => "double_back",
+            TripleLeft => "triple_left",
+            TripleMiddle => "triple_middle",
+            TripleRight => "triple_right",
+            TripleForward => "triple_forward",
+            TripleBack => "triple_back",
+        }
+    }
+
+    /// The gesture of clicking this button `count` times in a row, if there is one
+    pub fn repeated(self, count: usize) -> Option<MouseButton> {
+        MULTI_CLICKS
+            .iter()
+            .find(|clicks| clicks[0] == self)
+            .and_then(|clicks| clicks.get(count.checked_sub(1)?))
+            .copied()
+    }
 }
 
 #[derive(serde_derive::Deserialize, Debug, Clone, Default)]
 pub struct ClickHandler(Vec<ClickConfigEntry>);
 
 impl ClickHandler {
-    // Returns true if the block needs to be updated
-    pub async fn handle(&self, button: MouseButton) -> bool {
-        match self.0.iter().find(|e| e.button == button) {
+    /// Run the command handling `event` on `widget` of the block called `block`, with the
+    /// placeholders of the command replaced by `values`, which are also in its environment.
+    /// Returns true if the block needs to be updated.
+    pub async fn handle(
+        &self,
+        event: &I3BarEvent,
+        widget: Option<&str>,
+        block: &str,
+        values: &HashMap<String, Value>,
+    ) -> bool {
+        match self.entry(event, widget) {
             Some(entry) => {
                 if let Some(cmd) = &entry.cmd {
+                    let mut command = shell(&render_cmd(cmd, values));
+                    command.envs(click_env(event, widget, block));
+                    command.envs(value_env(values));
                     if entry.sync {
-                        let _ = spawn_shell_sync(cmd).await;
+                        if let Ok(mut child) = GroupChild::spawn(&mut command) {
+                            let _ = child.wait().await;
+                        }
                     } else {
-                        let _ = spawn_shell(cmd);
+                        let _ = spawn(&mut command);
                     }
                 }
                 entry.update
@@ -37,6 +120,222 @@ impl ClickHandler {
             None => true,
         }
     }
+
+    /// Whether an entry handles `event` on `widget`
+    pub fn handles(&self, event: &I3BarEvent, widget: Option<&str>) -> bool {
+        self.entry(event, widget).is_some()
+    }
+
+    /// Whether a click with `button` is handled, whatever the modifiers
+    pub fn binds(&self, button: MouseButton) -> bool {
+        self.0.iter().any(|e| e.button == button)
+    }
+
+    /// The built-in action to run on `event`, if any. Actions are run by the bar, since most of
+    /// them act on the bar rather than on the block.
+    pub fn action(&self, event: &I3BarEvent, widget: Option<&str>) -> Option<&ClickAction> {
+        self.entry(event, widget).and_then(|e| e.action.as_ref())
+    }
+
+    /// The names of the values the commands use, i.e. the `<name>` of the `SWAYSTATUS_VALUE_<name>`
+    /// variables they mention
+    pub fn used_values(&self) -> impl Iterator<Item = &str> {
+        self.0
+            .iter()
+            .filter_map(|e| e.cmd.as_deref())
+            .flat_map(|cmd| cmd.split(VALUE_PREFIX).skip(1))
+            .map(|rest| {
+                let end = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                &rest[..end]
+            })
+            .filter(|name| !name.is_empty())
+    }
+
+    /// The names of the placeholders in the commands. The parts of commands which are not valid
+    /// format strings have none.
+    pub fn used_placeholders(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .filter_map(|e| e.cmd.as_deref())
+            .flat_map(cmd_pieces)
+            .filter(|&(_, is_format)| is_format)
+            .filter_map(|(piece, _)| FormatTemplate::new(Some(piece), None).ok())
+            .flat_map(|format| {
+                format
+                    .placeholders()
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// The names of the widgets the entries are bound to
+    pub fn widgets(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().filter_map(|e| e.widget.as_deref())
+    }
+
+    /// The entry handling `event` on `widget`, the name of the clicked widget if the block gives
+    /// it one. Entries for the widget take precedence over the entries for the whole block, and
+    /// entries with `modifiers` over the entries without, which handle the clicks with any
+    /// modifiers.
+    fn entry(&self, event: &I3BarEvent, widget: Option<&str>) -> Option<&ClickConfigEntry> {
+        let entries = |for_widget: bool| {
+            self.0.iter().filter(move |e| {
+                e.button == event.button
+                    && match &e.widget {
+                        Some(name) => for_widget && Some(name.as_str()) == widget,
+                        None => !for_widget,
+                    }
+            })
+        };
+        [true, false].iter().find_map(|&for_widget| {
+            entries(for_widget)
+                .find(|e| match &e.modifiers {
+                    Some(modifiers) => same_modifiers(modifiers, &event.modifiers),
+                    None => false,
+                })
+                .or_else(|| entries(for_widget).find(|e| e.modifiers.is_none()))
+        })
+    }
+}
+
+/// Replace the placeholders of a click command with the values last shown by the block, quoted so
+/// that the shell takes each of them as plain text. The `${...}` parameter expansions of the shell
+/// are left alone. Commands which are not valid format strings or use unknown placeholders, such as
+/// `awk '{print $1}'`, are run as they are written.
+fn render_cmd(cmd: &str, values: &HashMap<String, Value>) -> String {
+    let mut rendered = String::new();
+    for (piece, is_format) in cmd_pieces(cmd) {
+        if !is_format {
+            rendered.push_str(piece);
+            continue;
+        }
+        let piece = FormatTemplate::new(Some(piece), None).and_then(|format| {
+            format.render_escaped(values, |before, value| {
+                shell_quote(&format!("{}{}", rendered, before), value)
+            })
+        });
+        match piece {
+            Ok(piece) => rendered.push_str(&piece),
+            Err(_) => return cmd.to_string(),
+        }
+    }
+    rendered
+}
+
+/// Split a command into the `${...}` parameter expansions of the shell and the text around them,
+/// which is a format string. Returns the pieces, and whether each of them is a format string.
+fn cmd_pieces(cmd: &str) -> Vec<(&str, bool)> {
+    let mut pieces = Vec::new();
+    let mut rest = cmd;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .map_or(rest.len(), |end| start + end + 1);
+        pieces.push((&rest[..start], true));
+        pieces.push((&rest[start..end], false));
+        rest = &rest[end..];
+    }
+    pieces.push((rest, true));
+    pieces
+}
+
+/// Quote a value pasted into a shell command after `before`. The placeholder may be unquoted or
+/// inside single or double quotes, e.g. `notify-send '{title}'`.
+fn shell_quote(before: &str, value: String) -> String {
+    match open_quote(before) {
+        None => format!("'{}'", value.replace('\'', r"'\''")),
+        Some('\'') => value.replace('\'', r"'\''"),
+        Some(_) => {
+            let mut quoted = String::with_capacity(value.len());
+            for c in value.chars() {
+                if matches!(c, '"' | '\\' | '$' | '`') {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            quoted
+        }
+    }
+}
+
+/// The quote left open at the end of a piece of shell command, if any
+fn open_quote(cmd: &str) -> Option<char> {
+    let mut quote = None;
+    let mut escaped = false;
+    for c in cmd.chars() {
+        match quote {
+            _ if escaped => escaped = false,
+            Some('\'') => {
+                if c == '\'' {
+                    quote = None;
+                }
+            }
+            _ if c == '\\' => escaped = true,
+            Some(_) => {
+                if c == '"' {
+                    quote = None;
+                }
+            }
+            None => {
+                if c == '\'' || c == '"' {
+                    quote = Some(c);
+                }
+            }
+        }
+    }
+    quote
+}
+
+/// The prefix of the environment variables holding the values last shown by the block
+const VALUE_PREFIX: &str = "SWAYSTATUS_VALUE_";
+
+/// The values last shown by a block, as environment variables of its click commands. They are
+/// formatted as in a format string, without padding.
+fn value_env(values: &HashMap<String, Value>) -> Vec<(String, String)> {
+    values
+        .iter()
+        .filter_map(|(name, value)| {
+            let placeholder: Placeholder = format!("{}:1", name).parse().ok()?;
+            let text = value.format(&placeholder).ok()?;
+            Some((format!("{}{}", VALUE_PREFIX, name), text))
+        })
+        .collect()
+}
+
+/// The environment variables describing a click to its command
+fn click_env(event: &I3BarEvent, widget: Option<&str>, block: &str) -> Vec<(&'static str, String)> {
+    vec![
+        ("SWAYSTATUS_BLOCK", block.to_string()),
+        ("SWAYSTATUS_BUTTON", event.button.name().to_string()),
+        (
+            "SWAYSTATUS_INSTANCE",
+            event.instance.map(|i| i.to_string()).unwrap_or_default(),
+        ),
+        ("SWAYSTATUS_WIDGET", widget.unwrap_or_default().to_string()),
+        ("SWAYSTATUS_MODIFIERS", event.modifiers.join(",")),
+        ("SWAYSTATUS_X", event.x.to_string()),
+        ("SWAYSTATUS_Y", event.y.to_string()),
+        ("SWAYSTATUS_RELATIVE_X", event.relative_x.to_string()),
+        ("SWAYSTATUS_RELATIVE_Y", event.relative_y.to_string()),
+        ("SWAYSTATUS_WIDTH", event.width.to_string()),
+        ("SWAYSTATUS_HEIGHT", event.height.to_string()),
+    ]
+}
+
+/// Whether the modifiers of a click are the expected ones. Caps Lock and Num Lock (`Mod2`) are
+/// ignored, since they stay on.
+fn same_modifiers(expected: &[String], held: &[String]) -> bool {
+    let held: Vec<&String> = held
+        .iter()
+        .filter(|m| !m.eq_ignore_ascii_case("Lock") && !m.eq_ignore_ascii_case("Mod2"))
+        .collect();
+    held.len() == expected.len()
+        && expected
+            .iter()
+            .all(|e| held.iter().any(|m| m.eq_ignore_ascii_case(e)))
 }
 
 #[derive(serde_derive::Deserialize, Debug, Clone)]
@@ -44,9 +343,18 @@ impl ClickHandler {
 pub struct ClickConfigEntry {
     /// Which button to handle
     button: MouseButton,
+    /// Which modifier keys must be held, e.g. `["Shift"]` (default is any)
+    #[serde(default)]
+    modifiers: Option<Vec<String>>,
+    /// Which of the block's widgets to handle, e.g. `"next"` (default is the whole block)
+    #[serde(default)]
+    widget: Option<String>,
     /// Which command to run
     #[serde(default)]
     cmd: Option<String>,
+    /// Which built-in action to run
+    #[serde(default)]
+    action: Option<ClickAction>,
     /// Whether to wait for command to exit or not (default is `false`)
     #[serde(default)]
     sync: bool,
@@ -59,6 +367,43 @@ fn return_true() -> bool {
     true
 }
 
+/// The built-in actions a click can run
+#[derive(serde_derive::Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String")]
+pub enum ClickAction {
+    /// Swap the block's `format` and `format_alt`
+    ToggleFormat,
+    /// Update the block
+    Refresh,
+    /// Hide the block, until it is shown again through the control socket
+    Hide,
+    /// Switch to the next of the `themes`
+    NextTheme,
+    /// Copy the text of the block to the Wayland clipboard
+    Copy,
+    /// Send the click to the block with this name
+    SignalBlock(String),
+}
+
+impl TryFrom<String> for ClickAction {
+    type Error = String;
+
+    fn try_from(action: String) -> Result<Self, Self::Error> {
+        use ClickAction::*;
+        Ok(match action.as_str() {
+            "toggle_format" => ToggleFormat,
+            "refresh" => Refresh,
+            "hide" => Hide,
+            "next_theme" => NextTheme,
+            "copy" => Copy,
+            _ => match action.strip_prefix("signal_block:") {
+                Some(name) if !name.is_empty() => SignalBlock(name.to_string()),
+                _ => return Err(format!("unknown click action '{}'", action)),
+            },
+        })
+    }
+}
+
 impl<'de> Deserialize<'de> for MouseButton {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -89,9 +434,13 @@ impl<'de> Deserialize<'de> for MouseButton {
                     "down" => WheelDown,
                     "forward" => Forward,
                     "back" => Back,
-                    // Experemental
-                    "double_left" => DoubleLeft,
-                    _ => Unknown,
+                    // Double and triple clicks
+                    _ => MULTI_CLICKS
+                        .iter()
+                        .flatten()
+                        .copied()
+                        .find(|button| button.name() == name)
+                        .unwrap_or(Unknown),
                 })
             }
 
@@ -125,3 +474,200 @@ impl<'de> Deserialize<'de> for MouseButton {
         deserializer.deserialize_any(MouseButtonVisitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modifiers() {
+        let handler: ClickHandler = toml::from_str::<toml::Value>(
+            r#"
+            click = [
+                { button = "left", cmd = "plain" },
+                { button = "left", modifiers = ["Shift"], cmd = "shift" },
+                { button = "left", modifiers = ["control", "shift"], cmd = "control shift" },
+                { button = "right", modifiers = ["Mod4"], cmd = "super" },
+            ]
+            "#,
+        )
+        .unwrap()["click"]
+            .clone()
+            .try_into()
+            .unwrap();
+        let cmd = |button, modifiers: &[&str]| {
+            let event = I3BarEvent {
+                button,
+                modifiers: modifiers.iter().map(|m| m.to_string()).collect(),
+                ..Default::default()
+            };
+            handler.entry(&event, None).and_then(|e| e.cmd.as_deref())
+        };
+
+        assert_eq!(cmd(MouseButton::Left, &[]), Some("plain"));
+        assert_eq!(cmd(MouseButton::Left, &["Shift"]), Some("shift"));
+        assert_eq!(cmd(MouseButton::Left, &["Shift", "Mod2"]), Some("shift"));
+        assert_eq!(
+            cmd(MouseButton::Left, &["Shift", "Control"]),
+            Some("control shift")
+        );
+        // Entries without modifiers handle the other combinations
+        assert_eq!(cmd(MouseButton::Left, &["Mod1"]), Some("plain"));
+        assert_eq!(cmd(MouseButton::Right, &["Mod4"]), Some("super"));
+        assert_eq!(cmd(MouseButton::Right, &[]), None);
+    }
+
+    #[test]
+    fn test_widgets() {
+        let handler: ClickHandler = toml::from_str::<toml::Value>(
+            r#"
+            click = [
+                { button = "left", cmd = "block" },
+                { button = "left", widget = "next", cmd = "next" },
+                { button = "left", widget = "next", modifiers = ["Shift"], cmd = "shift next" },
+                { button = "right", widget = "prev", cmd = "prev" },
+            ]
+            "#,
+        )
+        .unwrap()["click"]
+            .clone()
+            .try_into()
+            .unwrap();
+        let cmd = |button, widget, modifiers: &[&str]| {
+            let event = I3BarEvent {
+                button,
+                modifiers: modifiers.iter().map(|m| m.to_string()).collect(),
+                ..Default::default()
+            };
+            handler.entry(&event, widget).and_then(|e| e.cmd.as_deref())
+        };
+
+        assert_eq!(cmd(MouseButton::Left, Some("next"), &[]), Some("next"));
+        assert_eq!(
+            cmd(MouseButton::Left, Some("next"), &["Shift"]),
+            Some("shift next")
+        );
+        // The entries for the whole block handle the other widgets
+        assert_eq!(cmd(MouseButton::Left, Some("play"), &[]), Some("block"));
+        assert_eq!(cmd(MouseButton::Left, None, &["Shift"]), Some("block"));
+        assert_eq!(cmd(MouseButton::Right, Some("prev"), &[]), Some("prev"));
+        assert_eq!(cmd(MouseButton::Right, None, &[]), None);
+        assert_eq!(
+            handler.widgets().collect::<Vec<_>>(),
+            ["next", "next", "prev"]
+        );
+    }
+
+    #[test]
+    fn test_values() {
+        let values = map_to_owned! {
+            "title" => Value::from_string("'; rm -rf ~ #".to_string()),
+            "total" => Value::from_integer(3),
+        };
+        let mut env = value_env(&values);
+        env.sort();
+        assert_eq!(
+            env,
+            [
+                (
+                    "SWAYSTATUS_VALUE_title".to_string(),
+                    "'; rm -rf ~ #".to_string()
+                ),
+                ("SWAYSTATUS_VALUE_total".to_string(), "3".to_string()),
+            ]
+        );
+
+        let handler: ClickHandler = toml::from_str::<toml::Value>(
+            r#"
+            [[click]]
+            button = "left"
+            cmd = "notify-send \"$SWAYSTATUS_VALUE_title\" ${SWAYSTATUS_VALUE_total}"
+            [[click]]
+            button = "right"
+            cmd = "echo {title} $SWAYSTATUS_VALUE_"
+            "#,
+        )
+        .unwrap()["click"]
+            .clone()
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            handler.used_values().collect::<Vec<_>>(),
+            ["title", "total"]
+        );
+        assert_eq!(handler.used_placeholders(), ["title"]);
+    }
+
+    #[test]
+    fn test_render_cmd() {
+        let values = map_to_owned! {
+            "ssid" => Value::from_string("home".to_string()),
+            "title" => Value::from_string("it's \"$(reboot)\" `id`".to_string()),
+            "total" => Value::from_integer(3),
+        };
+        assert_eq!(
+            render_cmd("nm-connection-editor --edit {ssid}", &values),
+            "nm-connection-editor --edit 'home'"
+        );
+        // Numbers are padded like in formats, unless a minimum width is given
+        assert_eq!(render_cmd("echo {total}", &values), "echo ' 3'");
+        assert_eq!(render_cmd("echo {total:1}", &values), "echo '3'");
+        // Values are quoted for where they are pasted, so the shell never interprets them
+        assert_eq!(
+            render_cmd("notify-send {title}", &values),
+            r#"notify-send 'it'\''s "$(reboot)" `id`'"#
+        );
+        assert_eq!(
+            render_cmd("notify-send '{title}'", &values),
+            r#"notify-send 'it'\''s "$(reboot)" `id`'"#
+        );
+        assert_eq!(
+            render_cmd(r#"notify-send "Title: {title}" '"' {ssid}"#, &values),
+            r#"notify-send "Title: it's \"\$(reboot)\" \`id\`" '"' 'home'"#
+        );
+        // Shell syntax and unknown placeholders are left alone
+        assert_eq!(
+            render_cmd("echo ${HOME}/{ssid}", &values),
+            "echo ${HOME}/'home'"
+        );
+        assert_eq!(
+            render_cmd("echo {ssid} {nope}", &values),
+            "echo {ssid} {nope}"
+        );
+        assert_eq!(render_cmd("awk '{print $1}'", &values), "awk '{print $1}'");
+    }
+
+    #[test]
+    fn test_actions() {
+        let action = |action: &str| ClickAction::try_from(action.to_string());
+        assert_eq!(action("toggle_format"), Ok(ClickAction::ToggleFormat));
+        assert_eq!(
+            action("signal_block:clock"),
+            Ok(ClickAction::SignalBlock("clock".to_string()))
+        );
+        assert!(action("signal_block:").is_err());
+        assert!(action("reboot").is_err());
+
+        let entry: Result<ClickConfigEntry, _> =
+            toml::from_str("button = \"left\"\naction = \"next_theme\"");
+        assert_eq!(entry.unwrap().action, Some(ClickAction::NextTheme));
+    }
+
+    #[test]
+    fn test_gestures() {
+        let button = |name: &str| -> MouseButton {
+            toml::Value::String(name.to_string()).try_into().unwrap()
+        };
+        assert_eq!(button("double_left"), MouseButton::DoubleLeft);
+        assert_eq!(button("triple_back"), MouseButton::TripleBack);
+        assert_eq!(button("double_up"), MouseButton::Unknown);
+
+        assert_eq!(MouseButton::Right.repeated(1), Some(MouseButton::Right));
+        assert_eq!(
+            MouseButton::Right.repeated(3),
+            Some(MouseButton::TripleRight)
+        );
+        assert_eq!(MouseButton::Right.repeated(4), None);
+        assert_eq!(MouseButton::WheelUp.repeated(2), None);
+    }
+}
@@ -1,26 +1,51 @@
-use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use serde::de::{Deserialize, Deserializer};
 use serde_derive::Deserialize;
 use toml::value;
 
-use crate::blocks::BlockType;
+use crate::blocks::{find_block, BLOCKS};
+use crate::dbus_connection::DbusConnections;
+use crate::de::deserialize_duration;
 use crate::icons::Icons;
+use crate::sampler::Sampler;
+use crate::scheduler::{Scheduler, SchedulerConfig};
 use crate::themes::Theme;
+use crate::util;
 
 #[derive(Debug, Clone)]
 pub struct SharedConfig {
-    pub theme: Cow<'static, Theme>,
-    pub icons: Cow<'static, Icons>,
-    pub icons_format: Cow<'static, String>,
+    pub theme: Arc<Theme>,
+    pub icons: Arc<Icons>,
+    pub icons_format: Arc<String>,
+    pub scheduler: Arc<Scheduler>,
+    pub dbus: Arc<DbusConnections>,
+    pub sampler: Arc<Sampler>,
+}
+
+/// The D-Bus connections and the sampler are not part of the configuration, so they are not
+/// compared
+impl PartialEq for SharedConfig {
+    fn eq(&self, other: &Self) -> bool {
+        self.theme == other.theme
+            && self.icons == other.icons
+            && self.icons_format == other.icons_format
+            && self.scheduler == other.scheduler
+    }
 }
 
 impl SharedConfig {
-    pub fn new(config: &'static Config) -> Self {
+    pub fn new(config: &Config) -> Self {
         Self {
-            theme: Cow::Borrowed(&config.theme),
-            icons: Cow::Borrowed(&config.icons),
-            icons_format: Cow::Borrowed(&config.icons_format),
+            theme: Arc::new(config.theme.clone()),
+            icons: Arc::new(config.icons.clone()),
+            icons_format: Arc::new(config.icons_format.clone()),
+            scheduler: Arc::new(Scheduler::new(config.scheduler.clone())),
+            dbus: Arc::new(DbusConnections::default()),
+            sampler: Arc::new(Sampler::default()),
         }
     }
 
@@ -39,9 +64,12 @@ impl SharedConfig {
 impl Default for SharedConfig {
     fn default() -> Self {
         Self {
-            theme: Cow::Owned(Theme::default()),
-            icons: Cow::Owned(Icons::default()),
-            icons_format: Cow::Owned(" {icon} ".to_string()),
+            theme: Arc::new(Theme::default()),
+            icons: Arc::new(Icons::default()),
+            icons_format: Arc::new(" {icon} ".to_string()),
+            scheduler: Arc::new(Scheduler::default()),
+            dbus: Arc::new(DbusConnections::default()),
+            sampler: Arc::new(Sampler::default()),
         }
     }
 }
@@ -54,6 +82,10 @@ pub struct Config {
     #[serde(default)]
     pub theme: Theme,
 
+    /// The themes the `next_theme` click action cycles through
+    #[serde(default)]
+    pub themes: Vec<String>,
+
     #[serde(default = "Config::default_icons_format")]
     pub icons_format: String,
 
@@ -61,30 +93,107 @@ pub struct Config {
     #[serde(default)]
     pub invert_scrolling: bool,
 
+    /// How long to wait for other blocks to update before redrawing the bar
+    #[serde(
+        default = "Config::default_redraw_delay",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub redraw_delay: Duration,
+
+    /// The maximum number of times per second the bar is redrawn
+    #[serde(default = "Config::default_max_fps")]
+    pub max_fps: u32,
+
+    /// The maximum delay between the clicks of a double or triple click
+    #[serde(
+        default = "Config::default_double_click_delay",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub double_click_delay: Duration,
+
+    /// When interval-driven blocks are updated
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+
     #[serde(rename = "block", deserialize_with = "deserialize_blocks")]
-    pub blocks: Vec<(BlockType, value::Value)>,
+    pub blocks: Vec<(String, value::Value)>,
 }
 
 impl Config {
     fn default_icons_format() -> String {
         " {icon} ".to_string()
     }
+
+    fn default_redraw_delay() -> Duration {
+        Duration::from_millis(20)
+    }
+
+    fn default_double_click_delay() -> Duration {
+        Duration::from_millis(150)
+    }
+
+    fn default_max_fps() -> u32 {
+        20
+    }
 }
 
-fn deserialize_blocks<'de, D>(deserializer: D) -> Result<Vec<(BlockType, value::Value)>, D::Error>
+fn deserialize_blocks<'de, D>(deserializer: D) -> Result<Vec<(String, value::Value)>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let mut blocks: Vec<(BlockType, value::Value)> = Vec::new();
+    let mut blocks: Vec<(String, value::Value)> = Vec::new();
+    let mut names = HashSet::new();
     let raw_blocks: Vec<value::Table> = Deserialize::deserialize(deserializer)?;
     for mut entry in raw_blocks {
+        if let Some(name) = entry.get("name").and_then(value::Value::as_str) {
+            if !names.insert(name.to_string()) {
+                return Err(serde::de::Error::custom(format!(
+                    "duplicate block name '{}'",
+                    name
+                )));
+            }
+        }
         if let Some(name) = entry.remove("block") {
             let name_str = name.to_string();
-            let block = BlockType::deserialize(name)
-                .map_err(|_| serde::de::Error::custom(format!("unknown block {}", name_str)))?;
-            blocks.push((block, value::Value::Table(entry)));
+            let block = name.as_str().and_then(find_block).ok_or_else(|| {
+                let candidates = BLOCKS.iter().map(|block| block.name);
+                serde::de::Error::custom(format!(
+                    "unknown block {}{}",
+                    name_str,
+                    util::did_you_mean(name.as_str().unwrap_or_default(), candidates)
+                ))
+            })?;
+            blocks.push((block.name.to_string(), value::Value::Table(entry)));
         }
     }
 
     Ok(blocks)
 }
+
+/// Get the files the configuration depends on: the config file itself and the theme and icon set
+/// files it references.
+pub fn config_files(config_path: &Path) -> Vec<PathBuf> {
+    let mut files = vec![config_path.to_path_buf()];
+
+    let config: value::Table = match util::deserialize_file(config_path) {
+        Ok(config) => config,
+        Err(_) => return files,
+    };
+
+    for (key, subdir) in &[("theme", "themes"), ("icons", "icons")] {
+        let name = match config.get(*key) {
+            Some(value::Value::String(name)) => Some(name.as_str()),
+            Some(value::Value::Table(table)) => table
+                .get("name")
+                .or_else(|| table.get("file"))
+                .and_then(value::Value::as_str),
+            _ => None,
+        };
+        if let Some(file) = name.and_then(|name| util::find_file(name, Some(subdir), Some("toml")))
+        {
+            files.push(file);
+        }
+    }
+
+    files
+}
@@ -0,0 +1,77 @@
+//! Watch the config file and the files it references for changes
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use futures::stream::StreamExt;
+use inotify::{EventStream, Inotify, WatchDescriptor, WatchMask};
+
+use crate::config::config_files;
+use crate::errors::*;
+
+pub struct ConfigWatcher {
+    files: Vec<PathBuf>,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    events: EventStream<[u8; 1024]>,
+}
+
+impl ConfigWatcher {
+    /// Watch `config_path` and the theme and icon set files it references.
+    ///
+    /// The parent directories are watched rather than the files themselves, so that editors
+    /// which save by replacing the file are handled too.
+    pub fn new(config_path: &Path) -> Result<Self> {
+        let mut files = Vec::new();
+        for file in config_files(config_path) {
+            // Follow symlinks, so that editing the target of a symlinked config is noticed
+            if let Ok(canonical) = file.canonicalize() {
+                if canonical != file {
+                    files.push(canonical);
+                }
+            }
+            files.push(file);
+        }
+
+        let mut inotify =
+            Inotify::init().internal_error("config watcher", "failed to start inotify")?;
+        let mut dirs = HashMap::new();
+        for dir in files.iter().filter_map(|file| file.parent()) {
+            if dirs.values().any(|d| d == dir) {
+                continue;
+            }
+            let wd = inotify
+                .add_watch(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)
+                .internal_error("config watcher", "failed to watch config directory")?;
+            dirs.insert(wd, dir.to_path_buf());
+        }
+
+        let events = inotify
+            .event_stream([0; 1024])
+            .internal_error("config watcher", "failed to create event stream")?;
+
+        Ok(Self {
+            files,
+            dirs,
+            events,
+        })
+    }
+
+    /// Resolve when one of the watched files has been written. Cancel-safe.
+    pub async fn changed(&mut self) -> Result<()> {
+        loop {
+            let event = self
+                .events
+                .next()
+                .await
+                .internal_error("config watcher", "inotify stream ended")?
+                .internal_error("config watcher", "failed to read inotify event")?;
+            let path = match (self.dirs.get(&event.wd), event.name) {
+                (Some(dir), Some(name)) => dir.join(name),
+                _ => continue,
+            };
+            if self.files.contains(&path) {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! D-Bus connections shared by all blocks
+//!
+//! The session and system bus connections are opened the first time a block asks for them. When
+//! a connection is lost (e.g. because the bus was restarted), the [`Subscription`]s made on it
+//! fail, so the blocks using it are restarted by the runtime, and the next block to ask for a
+//! connection gets a new one.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use dbus::message::MatchRule;
+use dbus::nonblock::{MsgMatch, Proxy, SyncConnection};
+use dbus::strings::{BusName, Path};
+use dbus::Message;
+use dbus_tokio::connection;
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::StreamExt;
+use tokio::sync::watch;
+
+use crate::errors::*;
+
+/// How long to wait for the reply to a method call
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy)]
+pub enum Bus {
+    Session,
+    System,
+}
+
+/// The connections to the session and system buses
+#[derive(Default)]
+pub struct DbusConnections {
+    session: Mutex<Option<DbusConnection>>,
+    system: Mutex<Option<DbusConnection>>,
+}
+
+impl DbusConnections {
+    pub fn session(&self) -> Result<DbusConnection> {
+        Self::get(&self.session, Bus::Session)
+    }
+
+    pub fn system(&self) -> Result<DbusConnection> {
+        Self::get(&self.system, Bus::System)
+    }
+
+    fn get(slot: &Mutex<Option<DbusConnection>>, bus: Bus) -> Result<DbusConnection> {
+        let mut slot = slot.lock().unwrap();
+        match &*slot {
+            Some(connection) if !connection.is_lost() => Ok(connection.clone()),
+            _ => {
+                let connection = DbusConnection::open(bus)?;
+                *slot = Some(connection.clone());
+                Ok(connection)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DbusConnections {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("DbusConnections")
+    }
+}
+
+/// A connection to a bus
+#[derive(Clone)]
+pub struct DbusConnection {
+    conn: Arc<SyncConnection>,
+    /// Becomes `true` when the connection is lost
+    lost: watch::Receiver<bool>,
+}
+
+impl DbusConnection {
+    /// Open a new connection. Blocks should use the shared connections of [`DbusConnections`],
+    /// unless they own a bus name.
+    pub fn open(bus: Bus) -> Result<Self> {
+        let (resource, conn) = match bus {
+            Bus::Session => connection::new_session_sync(),
+            Bus::System => connection::new_system_sync(),
+        }
+        .internal_error("D-Bus", "failed to open D-Bus connection")?;
+
+        let (lost_tx, lost) = watch::channel(false);
+        tokio::spawn(async move {
+            let error = resource.await;
+            log::warn!("Lost connection to the D-Bus {:?} bus: {}", bus, error);
+            let _ = lost_tx.send(true);
+        });
+
+        Ok(Self { conn, lost })
+    }
+
+    pub fn conn(&self) -> &Arc<SyncConnection> {
+        &self.conn
+    }
+
+    pub fn is_lost(&self) -> bool {
+        *self.lost.borrow()
+    }
+
+    /// A proxy for calling the methods of an object
+    pub fn proxy<'a>(
+        &self,
+        destination: impl Into<BusName<'a>>,
+        path: impl Into<Path<'a>>,
+    ) -> Proxy<'a, Arc<SyncConnection>> {
+        Proxy::new(destination, path, TIMEOUT, self.conn.clone())
+    }
+
+    /// Receive the messages matching `rule`
+    pub async fn subscribe(&self, rule: MatchRule<'static>) -> Result<Subscription> {
+        let (msg_match, stream) = self
+            .conn
+            .add_match(rule)
+            .await
+            .internal_error("D-Bus", "failed to add match rule")?
+            .msg_stream();
+        Ok(Subscription {
+            _msg_match: msg_match,
+            stream,
+            lost: self.lost.clone(),
+        })
+    }
+
+    /// Receive the `PropertiesChanged` signals of the object at `path`
+    pub async fn properties_changed(&self, path: Path<'static>) -> Result<Subscription> {
+        let mut rule =
+            MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged");
+        rule.path = Some(path);
+        self.subscribe(rule).await
+    }
+
+    /// Receive the `NameOwnerChanged` signals, sent when a name appears on or leaves the bus
+    pub async fn name_owner_changed(&self) -> Result<Subscription> {
+        let mut rule = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged");
+        rule.sender = Some(BusName::from("org.freedesktop.DBus"));
+        self.subscribe(rule).await
+    }
+
+    /// Resolve when the connection is lost. Cancel-safe.
+    pub async fn wait_lost(&self) {
+        wait_lost(self.lost.clone()).await
+    }
+}
+
+/// The messages matching a rule
+pub struct Subscription {
+    _msg_match: MsgMatch,
+    stream: UnboundedReceiver<Message>,
+    lost: watch::Receiver<bool>,
+}
+
+impl Subscription {
+    /// Wait for the next message. Fails when the connection is lost. Cancel-safe.
+    pub async fn next(&mut self) -> Result<Message> {
+        tokio::select! {
+            Some(message) = self.stream.next() => Ok(message),
+            _ = wait_lost(self.lost.clone()) => internal_error("D-Bus", "lost connection to D-Bus"),
+        }
+    }
+}
+
+async fn wait_lost(mut lost: watch::Receiver<bool>) {
+    while !*lost.borrow() {
+        if lost.changed().await.is_err() {
+            // The connection task is gone without saying so, which means it is lost
+            return;
+        }
+    }
+}
@@ -40,3 +40,38 @@ where
 
     deserializer.deserialize_any(DurationWrapper)
 }
+
+/// Get the names of the fields of a struct that derives `Deserialize`.
+///
+/// Returns an empty slice if `T` is not a struct.
+pub fn struct_fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    struct StructFieldsDeserializer<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de, 'a> Deserializer<'de> for StructFieldsDeserializer<'a> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: de::Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("done"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(StructFieldsDeserializer(&mut fields));
+    fields
+}
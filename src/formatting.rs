@@ -12,6 +12,7 @@ use serde::de::{MapAccess, Visitor};
 use serde::{de, Deserialize, Deserializer};
 
 use crate::errors::*;
+use crate::util::did_you_mean;
 use placeholder::Placeholder;
 use value::Value;
 
@@ -57,6 +58,18 @@ impl FormatTemplate {
         Self::format_contains(&self.full, var) || Self::format_contains(&self.short, var)
     }
 
+    /// The names of all placeholders used in the format strings
+    pub fn placeholders(&self) -> impl Iterator<Item = &str> {
+        self.full
+            .iter()
+            .chain(self.short.iter())
+            .flatten()
+            .filter_map(|token| match token {
+                Token::Var(placeholder) => Some(placeholder.name.as_str()),
+                Token::Text(_) => None,
+            })
+    }
+
     fn format_contains(format: &Option<Vec<Token>>, var: &str) -> bool {
         if let Some(tokens) = format {
             for token in tokens {
@@ -134,20 +147,47 @@ impl FormatTemplate {
         Ok((full, short))
     }
 
+    /// Render the full format, passing each formatted value through `escape` together with the
+    /// text rendered before it, e.g. to quote the values pasted into a shell command
+    pub fn render_escaped(
+        &self,
+        vars: &HashMap<impl FormatMapKey, Value>,
+        escape: impl FnMut(&str, String) -> String,
+    ) -> Result<String> {
+        match &self.full {
+            Some(tokens) => Self::render_tokens_with(tokens, vars, escape),
+            None => Ok(String::new()),
+        }
+    }
+
     fn render_tokens(tokens: &[Token], vars: &HashMap<impl FormatMapKey, Value>) -> Result<String> {
+        Self::render_tokens_with(tokens, vars, |_, value| value)
+    }
+
+    fn render_tokens_with(
+        tokens: &[Token],
+        vars: &HashMap<impl FormatMapKey, Value>,
+        mut escape: impl FnMut(&str, String) -> String,
+    ) -> Result<String> {
         let mut rendered = String::new();
         for token in tokens {
             match token {
                 Token::Text(text) => rendered.push_str(text),
-                Token::Var(var) => rendered.push_str(
-                    &vars
+                Token::Var(var) => {
+                    let value = vars
                         .get(&var.name)
                         .internal_error(
                             "util",
-                            &format!("Unknown placeholder in format string: '{}'", var.name),
+                            &format!(
+                                "Unknown placeholder in format string: '{}'{}",
+                                var.name,
+                                did_you_mean(&var.name, vars.keys().map(Borrow::borrow))
+                            ),
                         )?
-                        .format(var)?,
-                ),
+                        .format(var)?;
+                    let value = escape(&rendered, value);
+                    rendered.push_str(&value);
+                }
             }
         }
         Ok(rendered)
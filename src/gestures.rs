@@ -0,0 +1,159 @@
+//! Recognize double and triple clicks
+//!
+//! Bars only report single clicks, so repeated clicks on the same widget with the same button are
+//! turned into a `double_*` or `triple_*` click here. Waiting for the next click delays the first
+//! one, so clicks are only held back if the clicked block binds a double or triple click of that
+//! button. If the gesture is not completed in time, the clicks are delivered one by one. Long
+//! presses can't be recognized, since bars don't report when a button is released.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::click::MouseButton;
+use crate::protocol::i3bar_event::I3BarEvent;
+
+#[derive(Debug, Default)]
+pub struct Gestures {
+    /// The maximum delay between two clicks of a gesture
+    delay: Duration,
+    pending: Option<Pending>,
+}
+
+/// Clicks held back because they may become a gesture
+#[derive(Debug)]
+struct Pending {
+    event: I3BarEvent,
+    count: usize,
+    /// Which gestures the block binds: double click, triple click
+    bound: (bool, bool),
+    deadline: Instant,
+}
+
+impl Gestures {
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+
+    /// Handle a click. `binds` tells whether the clicked block binds a button or gesture. Returns
+    /// the clicks to deliver now.
+    pub fn click(
+        &mut self,
+        event: I3BarEvent,
+        binds: impl Fn(MouseButton) -> bool,
+    ) -> Vec<I3BarEvent> {
+        let mut events = Vec::new();
+        let now = Instant::now();
+
+        if let Some(mut pending) = self.pending.take() {
+            if pending.event.same_click(&event) && now <= pending.deadline {
+                pending.count += 1;
+                let max = if pending.bound.1 { 3 } else { 2 };
+                if pending.count >= max {
+                    return pending.deliver();
+                }
+                pending.deadline = now + self.delay;
+                self.pending = Some(pending);
+                return events;
+            }
+            // Another click interrupts the gesture
+            events = pending.deliver();
+        }
+
+        let gesture = |count| event.button.repeated(count).filter(|b| binds(*b)).is_some();
+        let bound = (gesture(2), gesture(3));
+        if bound.0 || bound.1 {
+            self.pending = Some(Pending {
+                event,
+                count: 1,
+                bound,
+                deadline: now + self.delay,
+            });
+        } else {
+            events.push(event);
+        }
+        events
+    }
+
+    /// When the held back clicks are due, if there are any
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|pending| pending.deadline)
+    }
+
+    /// Deliver the held back clicks, since no other click came in time
+    pub fn timeout(&mut self) -> Vec<I3BarEvent> {
+        self.pending
+            .take()
+            .map(Pending::deliver)
+            .unwrap_or_default()
+    }
+}
+
+impl Pending {
+    /// The gesture made by the clicks, or the clicks themselves if it is not bound
+    fn deliver(self) -> Vec<I3BarEvent> {
+        let bound = match self.count {
+            2 => self.bound.0,
+            3 => self.bound.1,
+            _ => false,
+        };
+        match self.event.button.repeated(self.count) {
+            Some(button) if bound => vec![I3BarEvent {
+                button,
+                ..self.event
+            }],
+            _ => vec![self.event; self.count],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn click(id: usize, button: MouseButton) -> I3BarEvent {
+        I3BarEvent {
+            id,
+            button,
+            ..Default::default()
+        }
+    }
+
+    fn buttons(events: Vec<I3BarEvent>) -> Vec<MouseButton> {
+        events.iter().map(|event| event.button).collect()
+    }
+
+    #[test]
+    fn test_gestures() {
+        use MouseButton::*;
+
+        let mut gestures = Gestures::default();
+        gestures.set_delay(Duration::from_millis(200));
+        let binds = |button| matches!(button, DoubleLeft | TripleRight);
+
+        // Not bound, so not delayed
+        assert_eq!(buttons(gestures.click(click(0, Middle), binds)), [Middle]);
+        assert_eq!(gestures.deadline(), None);
+
+        assert!(gestures.click(click(0, Left), binds).is_empty());
+        assert!(gestures.deadline().is_some());
+        assert_eq!(buttons(gestures.click(click(0, Left), binds)), [DoubleLeft]);
+
+        // Interrupted by a click elsewhere
+        assert!(gestures.click(click(0, Left), binds).is_empty());
+        assert_eq!(buttons(gestures.click(click(1, Left), binds)), [Left]);
+        assert_eq!(buttons(gestures.timeout()), [Left]);
+
+        // A double right click is not bound, so it is two clicks
+        assert!(gestures.click(click(0, Right), binds).is_empty());
+        assert!(gestures.click(click(0, Right), binds).is_empty());
+        assert_eq!(buttons(gestures.timeout()), [Right, Right]);
+        assert!(gestures.click(click(0, Right), binds).is_empty());
+        assert!(gestures.click(click(0, Right), binds).is_empty());
+        assert_eq!(
+            buttons(gestures.click(click(0, Right), binds)),
+            [TripleRight]
+        );
+        assert_eq!(gestures.deadline(), None);
+    }
+}
@@ -6,7 +6,7 @@ use serde_derive::Deserialize;
 
 use crate::util;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Icons(pub HashMap<String, String>);
 
 impl Default for Icons {
@@ -0,0 +1,301 @@
+//! A JSON control socket for querying and driving a running bar
+//!
+//! The socket is created at `$XDG_RUNTIME_DIR/swaystatus-<pid>.sock`. Each request is a JSON
+//! object on its own line, and is answered with either `{"result": ...}` or `{"error": "..."}`
+//! on a line.
+//!
+//! Blocks are referred to either by their position on the bar (starting at 0) or by their `name`.
+//!
+//! Request | Description
+//! --------|------------
+//! `{"command": "list"}` | List the blocks
+//! `{"command": "get", "block": 0}` | Get the widgets currently rendered by the block
+//! `{"command": "update", "block": 0}` | Update the block now
+//! `{"command": "click", "block": 0, "button": "left", "instance": 1}` | Click the block. `instance` is optional.
+//! `{"command": "signal", "signal": 3, "block": 0}` | Send the custom signal `SIGRTMIN+3`. `block` is optional, by default all blocks get the signal.
+//! `{"command": "hide", "block": 0}` | Hide the block
+//! `{"command": "show", "block": 0}` | Show the block
+//! `{"command": "toggle", "block": 0}` | Hide the block if it is shown, show it otherwise
+//! `{"command": "set_theme", "theme": "solarized-dark"}` | Switch to another theme until the config is reloaded
+
+use std::env;
+use std::io::{BufRead, BufReader as StdBufReader, Write};
+use std::os::unix::net::UnixStream as StdUnixStream;
+use std::path::{Path, PathBuf};
+
+use serde_derive::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::click::MouseButton;
+use crate::errors::*;
+
+/// A reference to a block
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum BlockRef {
+    /// The position of the block on the bar
+    Index(usize),
+    /// The `name` of the block
+    Name(String),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "command", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Request {
+    List,
+    Get {
+        block: BlockRef,
+    },
+    Update {
+        block: BlockRef,
+    },
+    Click {
+        block: BlockRef,
+        button: MouseButton,
+        #[serde(default)]
+        instance: Option<usize>,
+    },
+    Signal {
+        signal: i32,
+        #[serde(default)]
+        block: Option<BlockRef>,
+    },
+    Hide {
+        block: BlockRef,
+    },
+    Show {
+        block: BlockRef,
+    },
+    Toggle {
+        block: BlockRef,
+    },
+    SetTheme {
+        theme: String,
+    },
+}
+
+/// The result of a request, or a description of why it failed
+pub type Response = StdResult<JsonValue, String>;
+
+/// A request waiting to be handled by the main loop
+pub struct IpcRequest {
+    pub request: Request,
+    pub reply: oneshot::Sender<Response>,
+}
+
+/// The path of the control socket of the process with the given pid
+fn socket_path(pid: u32) -> Option<PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(|dir| PathBuf::from(dir).join(format!("swaystatus-{}.sock", pid)))
+}
+
+/// Remove the control socket of this process, if any
+pub fn remove_socket() {
+    if let Some(path) = socket_path(std::process::id()) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Serve the control socket, passing the requests to `sender`
+pub async fn listen(sender: mpsc::Sender<IpcRequest>) -> Result<()> {
+    let path =
+        socket_path(std::process::id()).internal_error("ipc", "XDG_RUNTIME_DIR is not set")?;
+    // The socket may be left over from before an in-place restart
+    let _ = std::fs::remove_file(&path);
+    let listener =
+        UnixListener::bind(&path).internal_error("ipc", "failed to create the control socket")?;
+
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .internal_error("ipc", "failed to accept a connection")?;
+        tokio::spawn(handle_connection(stream, sender.clone()));
+    }
+}
+
+async fn handle_connection(stream: UnixStream, sender: mpsc::Sender<IpcRequest>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => {
+                let (reply, reply_rx) = oneshot::channel();
+                if sender.send(IpcRequest { request, reply }).await.is_err() {
+                    break;
+                }
+                reply_rx
+                    .await
+                    .unwrap_or_else(|_| Err("the request was dropped".to_string()))
+            }
+            Err(error) => Err(format!("invalid request: {}", error)),
+        };
+        let response = match response {
+            Ok(result) => json!({ "result": result }),
+            Err(error) => json!({ "error": error }),
+        };
+        if writer
+            .write_all(format!("{}\n", response).as_bytes())
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// Find the control socket of the running instance
+fn find_socket() -> Result<PathBuf> {
+    let dir = env::var_os("XDG_RUNTIME_DIR").internal_error("msg", "XDG_RUNTIME_DIR is not set")?;
+    let entries = std::fs::read_dir(dir).internal_error("msg", "failed to read XDG_RUNTIME_DIR")?;
+
+    // Skip the sockets of the processes which are no longer running
+    let mut sockets: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let pid = name.strip_prefix("swaystatus-")?.strip_suffix(".sock")?;
+            Path::new("/proc").join(pid).exists().then(|| entry.path())
+        })
+        .collect();
+
+    match sockets.len() {
+        0 => internal_error("msg", "no running swaystatus found"),
+        1 => Ok(sockets.remove(0)),
+        _ => internal_error(
+            "msg",
+            "several instances of swaystatus are running, select one with --socket",
+        ),
+    }
+}
+
+/// Build a request from the arguments of `swaystatus msg`
+pub fn parse_command(args: &[&str]) -> Result<JsonValue> {
+    fn block(arg: Option<&&str>) -> Result<JsonValue> {
+        let arg = arg.internal_error("msg", "missing block")?;
+        Ok(match arg.parse::<usize>() {
+            Ok(index) => json!(index),
+            Err(_) => json!(arg),
+        })
+    }
+
+    let (command, args) = args
+        .split_first()
+        .internal_error("msg", "missing command")?;
+    Ok(match *command {
+        "list" => json!({ "command": "list" }),
+        "get" | "update" | "hide" | "show" | "toggle" => json!({
+            "command": command,
+            "block": block(args.first())?,
+        }),
+        "click" => {
+            let button = args.get(1).internal_error("msg", "missing button")?;
+            let button = match button.parse::<u64>() {
+                Ok(number) => json!(number),
+                Err(_) => json!(button),
+            };
+            let mut request = json!({
+                "command": "click",
+                "block": block(args.first())?,
+                "button": button,
+            });
+            if let Some(instance) = args.get(2) {
+                request["instance"] = json!(instance
+                    .parse::<usize>()
+                    .internal_error("msg", "invalid instance")?);
+            }
+            request
+        }
+        "theme" => json!({
+            "command": "set_theme",
+            "theme": args.first().internal_error("msg", "missing theme")?,
+        }),
+        "signal" => {
+            let signal = args
+                .first()
+                .internal_error("msg", "missing signal")?
+                .parse::<i32>()
+                .internal_error("msg", "invalid signal")?;
+            let mut request = json!({ "command": "signal", "signal": signal });
+            if args.get(1).is_some() {
+                request["block"] = block(args.get(1))?;
+            }
+            request
+        }
+        other => return internal_error("msg", &format!("unknown command '{}'", other)),
+    })
+}
+
+/// Send a request to a running bar and wait for the response
+pub fn send_request(socket: Option<&Path>, request: &JsonValue) -> Result<Response> {
+    let socket = match socket {
+        Some(socket) => socket.to_path_buf(),
+        None => find_socket()?,
+    };
+    let mut stream =
+        StdUnixStream::connect(&socket).internal_error("msg", "failed to connect to swaystatus")?;
+    stream
+        .write_all(format!("{}\n", request).as_bytes())
+        .internal_error("msg", "failed to send the request")?;
+
+    let mut response = String::new();
+    StdBufReader::new(stream)
+        .read_line(&mut response)
+        .internal_error("msg", "failed to read the response")?;
+    let mut response: JsonValue =
+        serde_json::from_str(&response).internal_error("msg", "invalid response")?;
+
+    Ok(match response.get_mut("error") {
+        Some(error) => Err(error.as_str().unwrap_or_default().to_string()),
+        None => Ok(response["result"].take()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        let request = parse_command(&["click", "cpu", "left", "2"]).unwrap();
+        assert_eq!(
+            request,
+            json!({ "command": "click", "block": "cpu", "button": "left", "instance": 2 })
+        );
+        assert!(matches!(
+            serde_json::from_value(request).unwrap(),
+            Request::Click {
+                block: BlockRef::Name(_),
+                button: MouseButton::Left,
+                instance: Some(2),
+            }
+        ));
+
+        let request = parse_command(&["signal", "3"]).unwrap();
+        assert!(matches!(
+            serde_json::from_value(request).unwrap(),
+            Request::Signal {
+                signal: 3,
+                block: None
+            }
+        ));
+
+        let request = parse_command(&["hide", "1"]).unwrap();
+        assert!(matches!(
+            serde_json::from_value(request).unwrap(),
+            Request::Hide {
+                block: BlockRef::Index(1)
+            }
+        ));
+
+        assert!(parse_command(&["update"]).is_err());
+        assert!(parse_command(&["frobnicate"]).is_err());
+    }
+}
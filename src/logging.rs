@@ -0,0 +1,102 @@
+//! Diagnostics
+//!
+//! Swaystatus writes the status line to stdout, so everything else goes through the `log` macros
+//! to stderr (which the bar usually forwards to its own log) or to the file given with
+//! `--log-file`. Warnings and errors are logged by default, `-v` adds informational messages and
+//! `-vv` debug messages, such as every command spawned by a block or a click.
+//!
+//! Messages about a block use the `block::<name>` target, where `<name>` is the `name` given to
+//! the block in the config, or its type and its position among the blocks of that type (e.g.
+//! `block::cpu:0`).
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+struct Logger {
+    level: LevelFilter,
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut output = self.output.lock().unwrap();
+        // There is nowhere to report a failure to log
+        let _ = writeln!(
+            output,
+            "{} {:5} {}: {}",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {
+        let _ = self.output.lock().unwrap().flush();
+    }
+}
+
+/// The most detailed level logged with `verbosity` `-v` flags
+fn level_filter(verbosity: u64) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Log to stderr, or to the end of `log_file`. Must be called once, before anything is logged.
+pub fn init(verbosity: u64, log_file: Option<&Path>) -> Result<(), String> {
+    let output: Box<dyn Write + Send> = match log_file {
+        Some(path) => Box::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|error| format!("{}: {}", path.display(), error))?,
+        ),
+        None => Box::new(io::stderr()),
+    };
+    let level = level_filter(verbosity);
+    log::set_boxed_logger(Box::new(Logger {
+        level,
+        output: Mutex::new(output),
+    }))
+    .map_err(|error| error.to_string())?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// The target of the messages about a block, see the module documentation
+pub fn block_target(block: &str, name: Option<&str>, nth: usize) -> String {
+    match name {
+        Some(name) => format!("block::{}", name),
+        None => format!("block::{}:{}", block, nth),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targets_and_levels() {
+        assert_eq!(block_target("cpu", None, 1), "block::cpu:1");
+        assert_eq!(block_target("cpu", Some("work"), 1), "block::work");
+        assert_eq!(level_filter(0), LevelFilter::Warn);
+        assert_eq!(level_filter(2), LevelFilter::Debug);
+        assert_eq!(level_filter(5), LevelFilter::Trace);
+    }
+}
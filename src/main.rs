@@ -1,32 +1,65 @@
 #[macro_use]
 mod util;
+mod bar_dbus;
 mod blocks;
+mod check;
 mod click;
 mod config;
+mod config_watcher;
+mod dbus_connection;
 mod de;
 mod errors;
 mod formatting;
+mod gestures;
 mod icons;
+mod ipc;
+mod logging;
 mod netlink;
 mod protocol;
+mod sampler;
+mod scheduler;
 mod signals;
+mod state;
 mod subprocess;
 mod themes;
 mod widget;
 
-use clap::{app_from_crate, crate_authors, crate_description, crate_name, crate_version, Arg};
+use clap::{
+    app_from_crate, crate_authors, crate_description, crate_name, crate_version, Arg, SubCommand,
+};
 
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::{abortable, AbortHandle, BoxFuture};
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt;
-use tokio::sync::mpsc;
+use serde_json::Value as JsonValue;
+use tokio::io::{AsyncBufRead, BufReader};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinError;
+use tokio::time::Instant;
+use toml::value::Value;
 
-use crate::blocks::{run_block, BlockEvent};
+use crate::blocks::{
+    find_block, has_format_alt, widget_name, BlockEvent, BlockIdentity, BlockMessage, BLOCKS,
+};
+use crate::click::{ClickAction, ClickHandler, MouseButton};
 use crate::config::Config;
 use crate::config::SharedConfig;
+use crate::config_watcher::ConfigWatcher;
 use crate::errors::*;
+use crate::gestures::Gestures;
+use crate::ipc::{BlockRef, IpcRequest, Request};
 use crate::protocol::i3bar_block::I3BarBlock;
-use crate::protocol::i3bar_event::process_events;
+use crate::protocol::i3bar_event::{process_events, I3BarEvent};
 use crate::signals::{process_signals, Signal};
+use crate::state::StateStore;
+use crate::themes::Theme;
 use crate::util::deserialize_file;
 use crate::widget::{State, Widget};
 
@@ -52,6 +85,20 @@ fn main() {
                 .long("never-pause")
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("verbose")
+                .help("Log more details (-v, -vv)")
+                .short("v")
+                .long("verbose")
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("log-file")
+                .value_name("FILE")
+                .help("Write the log to this file instead of stderr")
+                .long("log-file")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("no-init")
                 .help("Do not send an init sequence")
@@ -59,8 +106,103 @@ fn main() {
                 .takes_value(false)
                 .hidden(true),
         )
+        .arg(
+            Arg::with_name("fs-root")
+                .help("Read /sys and /proc from this directory, for testing")
+                .long("fs-root")
+                .takes_value(true)
+                .hidden(true),
+        )
+        .arg(
+            Arg::with_name("list-blocks")
+                .help("List all available blocks with their options and placeholders and exit")
+                .long("list-blocks")
+                .takes_value(false),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Check the config file for mistakes without starting the bar")
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG_FILE")
+                        .help("The toml config file to check")
+                        .required(false)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("msg")
+                .about("Send a command to the running bar through its control socket")
+                .arg(
+                    Arg::with_name("socket")
+                        .help("The control socket to use (found automatically by default)")
+                        .long("socket")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .value_name("COMMAND")
+                        .help(
+                            "list | get BLOCK | update BLOCK | click BLOCK BUTTON [INSTANCE] | \
+                             signal N [BLOCK] | hide BLOCK | show BLOCK | toggle BLOCK | theme THEME",
+                        )
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
         .get_matches();
 
+    if let Some(root) = args.value_of("fs-root") {
+        // Set before any thread is started, and inherited by a restarted swaystatus
+        std::env::set_var(util::FS_ROOT_VAR, root);
+    }
+
+    if args.is_present("list-blocks") {
+        list_blocks();
+        return;
+    }
+
+    if let Some(args) = args.subcommand_matches("check") {
+        let config = args.value_of("config").unwrap_or("config.toml");
+        let ok = match util::find_file(config, None, Some("toml")) {
+            Some(config_path) => check::check_config_file(&config_path),
+            None => {
+                println!("{}: configuration file not found", config);
+                false
+            }
+        };
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
+    if let Some(args) = args.subcommand_matches("msg") {
+        let command: Vec<&str> = args.values_of("command").unwrap().collect();
+        let response = ipc::parse_command(&command).and_then(|request| {
+            ipc::send_request(args.value_of("socket").map(Path::new), &request)
+        });
+        match response {
+            Ok(Ok(result)) => {
+                if !result.is_null() {
+                    println!("{}", serde_json::to_string_pretty(&result).unwrap());
+                }
+            }
+            Ok(Err(error)) => {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let log_file = args.value_of("log-file").map(Path::new);
+    if let Err(error) = logging::init(args.occurrences_of("verbose"), log_file) {
+        eprintln!("Failed to set up logging: {}", error);
+        std::process::exit(EXIT_ERROR);
+    }
+
     // Build the runtime and run the program
     tokio::runtime::Builder::new_multi_thread()
         .worker_threads(2)
@@ -72,13 +214,13 @@ fn main() {
             if let Err(error) = run(
                 args.value_of("config"),
                 args.is_present("no-init"),
-                args.is_present("never_pause"),
+                args.is_present("never-pause"),
             )
             .await
             {
                 if args.is_present("exit-on-error") {
-                    eprintln!("{:?}", error);
-                    std::process::exit(1);
+                    log::error!("{}", error);
+                    std::process::exit(EXIT_ERROR);
                 }
 
                 // Create widget with error message
@@ -88,20 +230,37 @@ fn main() {
 
                 // Print errors
                 println!("[{}],", error_widget.get_data().render());
-                eprintln!("\n\n{}\n\n", error);
-                dbg!(error);
+                log::error!("{}", error);
+                log::debug!("{:?}", error);
 
-                // Wait for USR2 signal to restart
-                signal_hook::iterator::Signals::new(&[signal_hook::consts::SIGUSR2])
-                    .unwrap()
-                    .forever()
-                    .next()
-                    .unwrap();
-                restart();
+                // Wait for USR2 signal to restart, or for a signal to exit
+                let signal = signal_hook::iterator::Signals::new(&[
+                    signal_hook::consts::SIGUSR2,
+                    signal_hook::consts::SIGTERM,
+                    signal_hook::consts::SIGINT,
+                ])
+                .unwrap()
+                .forever()
+                .next()
+                .unwrap();
+                if signal == signal_hook::consts::SIGUSR2 {
+                    restart();
+                }
             }
+            // Don't wait for the thread blocked on reading stdin
+            std::process::exit(EXIT_OK);
         });
 }
 
+/// How long to wait after the config file was written before reloading it, since editors may
+/// write it in several steps
+const RELOAD_DELAY: Duration = Duration::from_millis(50);
+
+/// The exit status after the bar has exited, or after SIGTERM or SIGINT
+const EXIT_OK: i32 = 0;
+/// The exit status when a block fails and `--exit-on-error` is given
+const EXIT_ERROR: i32 = 1;
+
 async fn run(config: Option<&str>, noinit: bool, never_pause: bool) -> Result<()> {
     if !noinit {
         // Now we can start to run the i3bar protocol
@@ -114,62 +273,801 @@ async fn run(config: Option<&str>, noinit: bool, never_pause: bool) -> Result<()
         .internal_error("run()", "configuration file not found")?;
 
     let config: Config = deserialize_file(&config_path)?;
-    let block_list = config.blocks.clone();
-    let config: &'static Config = Box::leak(Box::new(config));
-    let shared_config = SharedConfig::new(config);
+
+    // Listen to signals
+    let (signals_sender, signals) = mpsc::channel(64);
+    tokio::spawn(process_signals(signals_sender));
+
+    let io = BarIo {
+        input: Box::new(BufReader::new(tokio::io::stdin())),
+        signals,
+        output: Box::new(std::io::stdout()),
+        headless: false,
+    };
+    run_bar(config, Some(&config_path), io).await
+}
+
+/// What the bar is connected to: i3bar on stdin and stdout and the signals sent to swaystatus, or
+/// in-memory channels in tests
+struct BarIo {
+    /// The click events written by i3bar. The bar exits when it is closed.
+    input: Box<dyn AsyncBufRead + Unpin + Send>,
+    signals: mpsc::Receiver<Signal>,
+    /// Where the lines of the i3bar protocol are written
+    output: Box<dyn Write + Send>,
+    /// Run without the control socket, the D-Bus interface and the state file, and don't restart
+    /// on `SIGUSR2`, so that tests don't interfere with each other or with a running swaystatus
+    headless: bool,
+}
+
+/// Run the blocks of `config` until the bar exits or swaystatus is asked to terminate. The config
+/// is reloaded when `config_path` changes.
+async fn run_bar(config: Config, config_path: Option<&Path>, io: BarIo) -> Result<()> {
+    let BarIo {
+        input,
+        signals: mut signals_receiver,
+        output,
+        headless,
+    } = io;
 
     // Initialize the blocks
-    let mut blocks_events: Vec<mpsc::Sender<BlockEvent>> = Vec::new();
-    let mut blocks_tasks = FuturesUnordered::new();
     let (message_sender, mut message_receiver) = mpsc::channel(64);
+    let state = if headless {
+        StateStore::default()
+    } else {
+        StateStore::load()
+    };
+    let mut bar = Bar::new(message_sender, output, state);
+    bar.apply_config(config)?;
 
-    for (block_type, block_config) in block_list {
-        let (events_sender, events_reciever) = mpsc::channel(64);
-        blocks_events.push(events_sender);
+    // Reload the config when it changes. Failing to watch it is not fatal.
+    let mut config_watcher = config_path.and_then(|path| ConfigWatcher::new(path).ok());
+    let mut reload_at = None;
+
+    // Listen to clicks
+    let (events_sender, mut events_receiver) = mpsc::channel(64);
+    tokio::spawn(process_events(input, events_sender));
+
+    let (ipc_sender, mut ipc_receiver) = mpsc::channel(64);
+    let bar_dbus = if headless {
+        None
+    } else {
+        // Serve the control socket. The bar works without it, so errors are only reported.
+        let listener_sender = ipc_sender.clone();
+        tokio::spawn(async move {
+            if let Err(error) = ipc::listen(listener_sender).await {
+                log::warn!("{}", error);
+            }
+        });
+
+        // Serve the D-Bus interface. Like the control socket, it is optional.
+        match bar_dbus::BarDbus::new(ipc_sender).await {
+            Ok(bar_dbus) => Some(bar_dbus),
+            Err(error) => {
+                log::warn!("{}", error);
+                None
+            }
+        }
+    };
+
+    // Main loop. It ends when the bar exits or swaystatus is asked to terminate or to restart.
+    let mut restart_requested = false;
+    let result: Result<()> = async {
+        loop {
+            tokio::select! {
+                // Handle blocks' errors
+                Some((id, block_result)) = bar.tasks.next() => {
+                    let block_result = block_result.internal_error("error handler", "failed to read block exit status")?;
+                    // Stopped blocks are no longer on the bar, so their result does not matter
+                    if bar.blocks.contains_key(&id) {
+                        block_result?;
+                    }
+                },
+                // Recieve widgets from blocks
+                Some(message) = message_receiver.recv() => {
+                    if bar.blocks.contains_key(&message.id) {
+                        bar.rendered.insert(message.id, message.widgets);
+                        bar.request_redraw();
+                    }
+                }
+                // Handle clicks
+                event = events_receiver.recv() => {
+                    // Stdin is closed, so the bar has exited
+                    let mut event = match event {
+                        Some(event) => event,
+                        None => break,
+                    };
+                    if bar.invert_scrolling {
+                        event.button = match event.button {
+                            MouseButton::WheelUp => MouseButton::WheelDown,
+                            MouseButton::WheelDown => MouseButton::WheelUp,
+                            other => other,
+                        };
+                    }
+                    let events = match bar.blocks.get(&event.id) {
+                        Some(block) => bar.gestures.click(event, |button| block.click.binds(button)),
+                        None => Vec::new(),
+                    };
+                    for event in events {
+                        bar.send_click(event, bar_dbus.as_ref());
+                    }
+                }
+                // Deliver the clicks which did not become a double or triple click
+                _ = tokio::time::sleep_until(bar.gestures.deadline().unwrap_or_else(Instant::now)), if bar.gestures.deadline().is_some() => {
+                    for event in bar.gestures.timeout() {
+                        bar.send_click(event, bar_dbus.as_ref());
+                    }
+                }
+                // Handle signals
+                Some(signal) = signals_receiver.recv() => match signal {
+                    Signal::Usr2 if !headless => {
+                        restart_requested = true;
+                        break;
+                    }
+                    Signal::Terminate => break,
+                    Signal::Stop => bar.set_paused(true),
+                    Signal::Cont => {
+                        bar.set_paused(false);
+                        bar.request_redraw();
+                    }
+                    signal => {
+                        for block in bar.blocks.values() {
+                            block.send(BlockEvent::Signal(signal));
+                        }
+                    }
+                },
+                // Handle requests from the control socket
+                Some(IpcRequest { request, reply }) = ipc_receiver.recv() => {
+                    let response = bar.handle_request(request);
+                    bar.request_redraw();
+                    let _ = reply.send(response);
+                }
+                // Redraw the bar
+                _ = tokio::time::sleep_until(bar.redraw_at.unwrap_or_else(Instant::now)), if bar.redraw_at.is_some() => {
+                    bar.redraw();
+                }
+                // Reload the config once it has stopped changing
+                result = config_changed(&mut config_watcher) => match result {
+                    Ok(()) => reload_at = Some(Instant::now() + RELOAD_DELAY),
+                    Err(error) => {
+                        log::warn!("{}. The config won't be reloaded anymore.", error);
+                        config_watcher = None;
+                    }
+                },
+                _ = tokio::time::sleep_until(reload_at.unwrap_or_else(Instant::now)), if reload_at.is_some() => {
+                    reload_at = None;
+                    // There is only a watcher if there is a config file
+                    if let Some(config_path) = config_path {
+                        match deserialize_file::<Config>(config_path) {
+                            Ok(config) => {
+                                bar.reload_error = None;
+                                bar.apply_config(config)?;
+                                // The config may now reference different theme and icon files
+                                config_watcher = ConfigWatcher::new(config_path).ok();
+                            }
+                            Err(error) => bar.reload_error = Some(error.to_string()),
+                        }
+                        bar.request_redraw();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    // Clean up even if a block failed, since the error is then shown until we are restarted
+    bar.shutdown().await;
+    if !headless {
+        bar.save_state();
+        if let Some(bar_dbus) = bar_dbus {
+            bar_dbus.close().await;
+        }
+        ipc::remove_socket();
+    }
+    if restart_requested {
+        restart();
+    }
+    result
+}
+
+/// The config of a block with its `format` and `format_alt` swapped
+fn swap_formats(config: &Value) -> Value {
+    let mut config = config.clone();
+    if let Some(table) = config.as_table_mut() {
+        let format = table.remove("format");
+        let format_alt = table.remove("format_alt");
+        if let Some(format_alt) = format_alt {
+            table.insert("format".to_string(), format_alt);
+        }
+        if let Some(format) = format {
+            table.insert("format_alt".to_string(), format);
+        }
+    }
+    config
+}
+
+/// Count the blocks of type `block`, returning how many were counted before
+fn nth_of_type(counts: &mut HashMap<String, usize>, block: &str) -> usize {
+    let count = counts.entry(block.to_string()).or_insert(0);
+    *count += 1;
+    *count - 1
+}
+
+async fn config_changed(watcher: &mut Option<ConfigWatcher>) -> Result<()> {
+    match watcher {
+        Some(watcher) => watcher.changed().await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// A running block
+struct RunningBlock {
+    /// The type of the block
+    block: String,
+    /// The name given to the block in the config
+    name: Option<String>,
+    config: Value,
+    /// The number of blocks of the same type before it
+    nth: usize,
+    /// Whether the block runs with its `format` and `format_alt` swapped
+    format_toggled: bool,
+    /// The click handlers of the block, to know which gestures to recognize and which actions to
+    /// run
+    click: ClickHandler,
+    /// The names of the block's widgets, for the click handlers
+    widgets: &'static [&'static str],
+    events: mpsc::Sender<BlockEvent>,
+    abort: AbortHandle,
+}
+
+impl RunningBlock {
+    fn log_target(&self) -> String {
+        logging::block_target(&self.block, self.name.as_deref(), self.nth)
+    }
+
+    /// Send an event to the block without waiting, since a block which doesn't keep up would
+    /// freeze the bar. The event is dropped if the block is busy, or if it has stopped.
+    fn send(&self, event: BlockEvent) {
+        if let Err(TrySendError::Full(event)) = self.events.try_send(event) {
+            log::warn!(target: &self.log_target(), "The block is busy, dropped {:?}", event);
+        }
+    }
+}
+
+type BlockTask = BoxFuture<'static, (usize, std::result::Result<Result<()>, JoinError>)>;
+
+/// The blocks on the bar, in the order given by the config.
+///
+/// Blocks get an id which never changes while they are running, so that a reloaded config can
+/// keep the blocks that did not change (and their state) running.
+struct Bar {
+    shared_config: SharedConfig,
+    /// The themes the `next_theme` click action cycles through
+    themes: Vec<String>,
+    /// The index of the theme `next_theme` switches to
+    next_theme: usize,
+    invert_scrolling: bool,
+    gestures: Gestures,
+    redraw_delay: Duration,
+    /// The minimum time between two frames
+    frame_interval: Duration,
+    /// When the bar should be redrawn next
+    redraw_at: Option<Instant>,
+    last_frame: Option<Instant>,
+    /// The last line printed, so that identical frames are skipped
+    last_line: String,
+    order: Vec<usize>,
+    blocks: HashMap<usize, RunningBlock>,
+    rendered: HashMap<usize, Vec<I3BarBlock>>,
+    hidden: HashSet<usize>,
+    /// Whether the bar is hidden, in which case nothing is printed and the blocks don't update on
+    /// intervals. The blocks watch it rather than being sent events, so that a busy block can't
+    /// hold up the main loop.
+    paused: watch::Sender<bool>,
+    /// Cloned for every new block, and keeps `paused` from being closed
+    paused_receiver: watch::Receiver<bool>,
+    tasks: FuturesUnordered<BlockTask>,
+    next_id: usize,
+    message_sender: mpsc::Sender<BlockMessage>,
+    output: Box<dyn Write + Send>,
+    /// The state of the blocks, kept across restarts
+    state: StateStore,
+    /// Why the last attempt to reload the config failed
+    reload_error: Option<String>,
+}
+
+impl Bar {
+    fn new(
+        message_sender: mpsc::Sender<BlockMessage>,
+        output: Box<dyn Write + Send>,
+        state: StateStore,
+    ) -> Self {
+        let (paused, paused_receiver) = watch::channel(false);
+        Self {
+            shared_config: SharedConfig::default(),
+            themes: Vec::new(),
+            next_theme: 0,
+            invert_scrolling: false,
+            gestures: Gestures::default(),
+            redraw_delay: Duration::default(),
+            frame_interval: Duration::default(),
+            redraw_at: None,
+            last_frame: None,
+            last_line: String::new(),
+            order: Vec::new(),
+            blocks: HashMap::new(),
+            rendered: HashMap::new(),
+            hidden: HashSet::new(),
+            paused,
+            paused_receiver,
+            tasks: FuturesUnordered::new(),
+            next_id: 0,
+            message_sender,
+            output,
+            state,
+            reload_error: None,
+        }
+    }
+
+    /// Start the blocks of `config`. Blocks which are already running with the same
+    /// configuration are kept, the others are stopped.
+    fn apply_config(&mut self, config: Config) -> Result<()> {
+        // Restarted blocks stay hidden if they can be recognized by their name
+        let hidden_names: HashSet<String> = self
+            .hidden
+            .iter()
+            .filter_map(|id| self.blocks[id].name.clone())
+            .collect();
 
-        blocks_tasks.push(tokio::spawn(run_block(
-            blocks_tasks.len(),
-            block_type,
+        let mut shared_config = SharedConfig::new(&config);
+        // Keep the D-Bus connections open and the samples shared with the remaining blocks
+        shared_config.dbus = self.shared_config.dbus.clone();
+        shared_config.sampler = self.shared_config.sampler.clone();
+        if shared_config != self.shared_config {
+            // Every block renders with the theme and icons, so restart all of them
+            self.shared_config = shared_config;
+            for id in std::mem::take(&mut self.order) {
+                self.stop_block(id);
+            }
+        }
+        // The config's theme is back in use
+        self.themes = config.themes;
+        self.next_theme = 0;
+        self.invert_scrolling = config.invert_scrolling;
+        self.gestures.set_delay(config.double_click_delay);
+        self.redraw_delay = config.redraw_delay;
+        self.frame_interval = Duration::from_secs(1) / config.max_fps.max(1);
+
+        let mut old_order = std::mem::take(&mut self.order);
+        let mut nth = HashMap::new();
+        for (name, block_config) in config.blocks {
+            let nth = nth_of_type(&mut nth, &name);
+            let unchanged = old_order.iter().position(|id| {
+                let block = &self.blocks[id];
+                block.block == name && block.config == block_config
+            });
+            let id = match unchanged {
+                Some(index) => old_order.remove(index),
+                None => self.spawn_block(name, block_config, nth, false)?,
+            };
+            self.order.push(id);
+        }
+        for id in old_order {
+            self.stop_block(id);
+        }
+        for id in &self.order {
+            if let Some(name) = &self.blocks[id].name {
+                if hidden_names.contains(name) {
+                    self.hidden.insert(*id);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Restart all blocks, e.g. to apply a new theme
+    fn restart_all(&mut self) -> Result<()> {
+        for index in 0..self.order.len() {
+            let format_toggled = self.blocks[&self.order[index]].format_toggled;
+            self.restart_block(index, format_toggled)?;
+        }
+        Ok(())
+    }
+
+    /// Restart the block at `index` in the order, with its formats swapped or not. It stays
+    /// hidden if it was.
+    fn restart_block(&mut self, index: usize, format_toggled: bool) -> Result<()> {
+        let id = self.order[index];
+        let block = &self.blocks[&id];
+        let (block_name, config, nth) = (block.block.clone(), block.config.clone(), block.nth);
+        let hidden = self.hidden.contains(&id);
+        self.stop_block(id);
+        let id = self.spawn_block(block_name, config, nth, format_toggled)?;
+        if hidden {
+            self.hidden.insert(id);
+        }
+        self.order[index] = id;
+        Ok(())
+    }
+
+    /// Start a block. `nth` is the number of blocks of the same type before it. If
+    /// `format_toggled` is set, the block runs with its `format` and `format_alt` swapped.
+    fn spawn_block(
+        &mut self,
+        block_name: String,
+        config: Value,
+        nth: usize,
+        format_toggled: bool,
+    ) -> Result<usize> {
+        let block = find_block(&block_name).internal_error("run()", "unknown block")?;
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let name = block.block_name(&config);
+        let block_state = self.state.block(&block_name, name, nth);
+        let (events_sender, events_reciever) = mpsc::channel(64);
+        let block_config = if format_toggled {
+            swap_formats(&config)
+        } else {
+            config.clone()
+        };
+        let identity = BlockIdentity {
+            id,
+            log_target: logging::block_target(&block_name, name, nth),
+        };
+        let (task, abort) = abortable((block.run)(
+            identity,
             block_config,
-            shared_config.clone(),
-            message_sender.clone(),
+            self.shared_config.clone(),
+            block_state,
+            self.message_sender.clone(),
             events_reciever,
-        )));
-    }
+            self.paused_receiver.clone(),
+        ));
+        let handle = tokio::spawn(task);
+        self.tasks.push(Box::pin(async move {
+            let result = handle.await.map(|result| result.unwrap_or(Ok(())));
+            (id, result)
+        }));
 
-    // Listen to signals and clicks
-    let (signals_sender, mut signals_receiver) = mpsc::channel(64);
-    let (events_sender, mut events_receiver) = mpsc::channel(64);
-    tokio::spawn(process_signals(signals_sender));
-    tokio::spawn(process_events(events_sender, config.invert_scrolling));
-
-    // Main loop
-    let mut rendered = vec![Vec::<I3BarBlock>::new(); blocks_events.len()];
-    loop {
-        tokio::select! {
-            // Handle blocks' errors
-            Some(block_result) = blocks_tasks.next() => {
-                block_result.internal_error("error handler", "failed to read block exit status")??;
+        self.blocks.insert(
+            id,
+            RunningBlock {
+                block: block_name,
+                name: block.block_name(&config).map(str::to_string),
+                click: config
+                    .get("click")
+                    .and_then(|click| click.clone().try_into().ok())
+                    .unwrap_or_default(),
+                widgets: block.widgets,
+                config,
+                nth,
+                format_toggled,
+                events: events_sender,
+                abort,
             },
-            // Recieve widgets from blocks
-            Some(message) = message_receiver.recv() => {
-                *rendered.get_mut(message.id).internal_error("handle block's message", "failed to get block")? = message.widgets;
-                protocol::print_blocks(&rendered, &shared_config)?;
-            }
-            // Handle clicks
-            Some(event) = events_receiver.recv() => {
-                let blocks_event = blocks_events.get(event.id).unwrap();
-                blocks_event.send(BlockEvent::I3Bar(event)).await.unwrap();
-            }
-            // Handle signals
-            Some(signal) = signals_receiver.recv() => match signal {
-                Signal::Usr2 => restart(),
-                signal => {
-                    for block in &blocks_events {
-                        block.send(BlockEvent::Signal(signal)).await.unwrap();
+        );
+        Ok(id)
+    }
+
+    /// Stop all blocks and wait for them to be dropped, so that the processes they started are
+    /// killed
+    async fn shutdown(&mut self) {
+        for id in std::mem::take(&mut self.order) {
+            self.stop_block(id);
+        }
+        // Don't wait forever for a block stuck in a blocking call
+        let _ = tokio::time::timeout(
+            Duration::from_secs(1),
+            self.tasks.by_ref().for_each(|_| async {}),
+        )
+        .await;
+    }
+
+    fn save_state(&self) {
+        if let Err(error) = self.state.save() {
+            log::warn!("Failed to save the state of the blocks: {}", error);
+        }
+    }
+
+    fn stop_block(&mut self, id: usize) {
+        if let Some(block) = self.blocks.remove(&id) {
+            block.abort.abort();
+        }
+        self.rendered.remove(&id);
+        self.hidden.remove(&id);
+    }
+
+    /// Find the id of a block
+    fn find(&self, block: &BlockRef) -> StdResult<usize, String> {
+        match block {
+            BlockRef::Index(index) => self.order.get(*index).copied(),
+            BlockRef::Name(name) => self
+                .order
+                .iter()
+                .copied()
+                .find(|id| self.blocks[id].name.as_ref() == Some(name)),
+        }
+        .ok_or_else(|| format!("no such block: {:?}", block))
+    }
+
+    /// Send an event to a block without waiting
+    fn send_event(&self, id: usize, event: BlockEvent) -> StdResult<(), String> {
+        self.blocks[&id]
+            .events
+            .try_send(event)
+            .map_err(|error| match error {
+                TrySendError::Full(_) => "the block is busy".to_string(),
+                TrySendError::Closed(_) => "the block has stopped".to_string(),
+            })
+    }
+
+    /// A block as named in replies to the control socket: its position, and its name or type
+    fn describe(&self, id: usize) -> String {
+        let block = &self.blocks[&id];
+        let index = self.order.iter().position(|other| *other == id);
+        format!(
+            "block {} ({})",
+            index.unwrap_or_default(),
+            block.name.as_deref().unwrap_or(&block.block)
+        )
+    }
+
+    /// Handle a request from the control socket
+    fn handle_request(&mut self, request: Request) -> ipc::Response {
+        match request {
+            Request::List => Ok(self
+                .order
+                .iter()
+                .enumerate()
+                .map(|(index, id)| {
+                    let block = &self.blocks[id];
+                    serde_json::json!({
+                        "index": index,
+                        "block": block.block,
+                        "name": block.name,
+                        "hidden": self.hidden.contains(id),
+                    })
+                })
+                .collect()),
+            Request::Get { block } => {
+                let id = self.find(&block)?;
+                Ok(self
+                    .rendered
+                    .get(&id)
+                    .into_iter()
+                    .flatten()
+                    .map(|widget| {
+                        serde_json::from_str::<JsonValue>(&widget.render()).unwrap_or_default()
+                    })
+                    .collect())
+            }
+            Request::Update { block } => {
+                let id = self.find(&block)?;
+                self.send_event(id, BlockEvent::Update)?;
+                Ok(JsonValue::Null)
+            }
+            Request::Click {
+                block,
+                button,
+                instance,
+            } => {
+                let id = self.find(&block)?;
+                self.send_event(
+                    id,
+                    BlockEvent::I3Bar(I3BarEvent {
+                        id,
+                        instance,
+                        button,
+                        ..Default::default()
+                    }),
+                )?;
+                Ok(JsonValue::Null)
+            }
+            Request::Signal { signal, block } => {
+                let ids = match block {
+                    Some(block) => vec![self.find(&block)?],
+                    None => self.order.clone(),
+                };
+                // A busy block doesn't keep the others from being signalled
+                let failed: Vec<String> = ids
+                    .into_iter()
+                    .filter_map(|id| {
+                        self.send_event(id, BlockEvent::Signal(Signal::Custom(signal)))
+                            .err()
+                            .map(|error| format!("{}: {}", self.describe(id), error))
+                    })
+                    .collect();
+                if failed.is_empty() {
+                    Ok(JsonValue::Null)
+                } else {
+                    Err(format!("not signalled: {}", failed.join(", ")))
+                }
+            }
+            Request::Hide { block } => {
+                let id = self.find(&block)?;
+                self.hidden.insert(id);
+                Ok(JsonValue::Null)
+            }
+            Request::Show { block } => {
+                let id = self.find(&block)?;
+                self.hidden.remove(&id);
+                Ok(JsonValue::Null)
+            }
+            Request::Toggle { block } => {
+                let id = self.find(&block)?;
+                if !self.hidden.remove(&id) {
+                    self.hidden.insert(id);
+                }
+                Ok(JsonValue::Null)
+            }
+            Request::SetTheme { theme } => {
+                self.set_theme(&theme)?;
+                Ok(JsonValue::Null)
+            }
+        }
+    }
+
+    /// Use the theme file `theme` until the config is reloaded
+    fn set_theme(&mut self, theme: &str) -> StdResult<(), String> {
+        let theme = Theme::from_file(theme).map_err(|error| error.to_string())?;
+        self.shared_config.theme = Arc::new(theme);
+        self.restart_all().map_err(|error| error.to_string())
+    }
+
+    /// Send a click to its block, or run the action it is bound to instead. The block may be
+    /// restarting, in which case the click is dropped.
+    fn send_click(&mut self, event: I3BarEvent, bar_dbus: Option<&bar_dbus::BarDbus>) {
+        if let Some(block) = self.blocks.get(&event.id) {
+            if let Some(bar_dbus) = bar_dbus {
+                let name = block.name.as_deref().unwrap_or(&block.block);
+                bar_dbus.block_clicked(name, event.instance, event.button);
+            }
+            let widget = widget_name(block.widgets, event.instance);
+            match block.click.action(&event, widget).cloned() {
+                Some(action) => {
+                    let target = block.log_target();
+                    log::debug!(target: &target, "Running click action {:?}", action);
+                    if let Err(error) = self.run_action(event, &action) {
+                        log::warn!(target: &target, "Click action {:?} failed: {}", action, error);
                     }
                 }
+                None => block.send(BlockEvent::I3Bar(event)),
+            }
+        }
+    }
+
+    /// Run the built-in action a click on a block is bound to
+    fn run_action(&mut self, event: I3BarEvent, action: &ClickAction) -> StdResult<(), String> {
+        let id = event.id;
+        match action {
+            ClickAction::ToggleFormat => {
+                let block = &self.blocks[&id];
+                if block.config.get("format_alt").is_none() {
+                    return Err("the block has no format_alt".to_string());
+                }
+                // These blocks keep track of the format they show, across restarts too, so
+                // swapping the formats in their config as well would undo the toggle
+                let entry = find_block(&block.block).ok_or("unknown block")?;
+                if has_format_alt((entry.config_fields)()) {
+                    return self.send_event(id, BlockEvent::ToggleFormat);
+                }
+                let format_toggled = !block.format_toggled;
+                let index = self
+                    .order
+                    .iter()
+                    .position(|other| *other == id)
+                    .ok_or("the block is not on the bar")?;
+                self.restart_block(index, format_toggled)
+                    .map_err(|error| error.to_string())?;
+            }
+            ClickAction::Refresh => self.send_event(id, BlockEvent::Update)?,
+            ClickAction::Hide => {
+                self.hidden.insert(id);
+                self.request_redraw();
+            }
+            ClickAction::NextTheme => {
+                if self.themes.is_empty() {
+                    return Err("no themes are configured".to_string());
+                }
+                let theme = self.themes[self.next_theme % self.themes.len()].clone();
+                self.next_theme = (self.next_theme + 1) % self.themes.len();
+                self.set_theme(&theme)?;
+            }
+            ClickAction::Copy => {
+                let text = self
+                    .rendered
+                    .get(&id)
+                    .into_iter()
+                    .flatten()
+                    .map(|widget| widget.full_text.trim())
+                    .filter(|text| !text.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                subprocess::spawn_program("wl-copy", &["--", &text])
+                    .map_err(|error| format!("failed to run wl-copy: {}", error))?;
+            }
+            ClickAction::SignalBlock(name) => {
+                let target = self.find(&BlockRef::Name(name.clone()))?;
+                // The click is handled by the target as its own, but doesn't run its actions, so
+                // that blocks can't send clicks to each other forever
+                self.send_event(
+                    target,
+                    BlockEvent::I3Bar(I3BarEvent {
+                        id: target,
+                        ..event
+                    }),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        // There is always a receiver, so this can't fail
+        let _ = self.paused.send(paused);
+    }
+
+    /// Redraw the bar soon. Blocks which update in the meantime are drawn in the same frame.
+    fn request_redraw(&mut self) {
+        if self.redraw_at.is_none() {
+            let mut redraw_at = Instant::now() + self.redraw_delay;
+            if let Some(last_frame) = self.last_frame {
+                redraw_at = redraw_at.max(last_frame + self.frame_interval);
             }
+            self.redraw_at = Some(redraw_at);
+        }
+    }
+
+    fn redraw(&mut self) {
+        self.redraw_at = None;
+        // The bar may not read our output while it is hidden
+        if *self.paused_receiver.borrow() {
+            return;
+        }
+
+        let mut rendered: Vec<Vec<I3BarBlock>> = self
+            .order
+            .iter()
+            .filter(|id| !self.hidden.contains(id))
+            .map(|id| self.rendered.get(id).cloned().unwrap_or_default())
+            .collect();
+        if let Some(error) = &self.reload_error {
+            let widget = Widget::new(usize::MAX, self.shared_config.clone())
+                .with_state(State::Critical)
+                .with_full_text(format!("Failed to reload config: {}", error));
+            rendered.push(vec![widget.get_data()]);
+        }
+        let line = protocol::render_blocks(&rendered, &self.shared_config);
+        if line != self.last_line {
+            // Write errors mean the bar has exited, which is noticed on stdin
+            let _ = writeln!(self.output, "{}", line);
+            self.last_line = line;
+            self.last_frame = Some(Instant::now());
+        }
+    }
+}
+
+/// Print all available blocks, their options and placeholders
+fn list_blocks() {
+    for block in BLOCKS {
+        println!("{}", block.name);
+        let mut options = (block.config_fields)().to_vec();
+        options.extend_from_slice(blocks::common_config_fields());
+        if !options.contains(&"format_alt") {
+            options.push("format_alt");
+        }
+        println!("    options: {}", options.join(", "));
+        if !block.placeholders.is_empty() {
+            println!("    placeholders: {}", block.placeholders.join(", "));
+        }
+        if !block.icons.is_empty() {
+            println!("    icons: {}", block.icons.join(", "));
+        }
+        if !block.widgets.is_empty() {
+            println!("    widgets: {}", block.widgets.join(", "));
         }
     }
 }
@@ -196,3 +1094,380 @@ fn restart() -> ! {
     nix::unistd::execvp(&exe, &arg).unwrap();
     unreachable!();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncWriteExt, DuplexStream};
+
+    /// Sends every line written by the bar to a channel
+    struct Frames {
+        sender: mpsc::UnboundedSender<String>,
+        buf: Vec<u8>,
+    }
+
+    impl Write for Frames {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.buf.extend_from_slice(data);
+            while let Some(end) = self.buf.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<u8> = self.buf.drain(..=end).collect();
+                let _ = self
+                    .sender
+                    .send(String::from_utf8_lossy(&line[..end]).into_owned());
+            }
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A headless bar, driven like i3bar would
+    struct Harness {
+        input: DuplexStream,
+        signals: mpsc::Sender<Signal>,
+        frames: mpsc::UnboundedReceiver<String>,
+        bar: tokio::task::JoinHandle<Result<()>>,
+    }
+
+    impl Harness {
+        /// Start the blocks of `config`, with the default theme and `theme` overrides
+        async fn start(config: &str, theme: &[(&str, &str)]) -> Self {
+            let mut config: Config = toml::from_str(config).unwrap();
+            let overrides = theme
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            config.theme.apply_overrides(&overrides).unwrap();
+
+            let (mut input, bar_input) = tokio::io::duplex(4096);
+            let (signals, signals_receiver) = mpsc::channel(64);
+            let (sender, frames) = mpsc::unbounded_channel();
+            let io = BarIo {
+                input: Box::new(BufReader::new(bar_input)),
+                signals: signals_receiver,
+                output: Box::new(Frames {
+                    sender,
+                    buf: Vec::new(),
+                }),
+                headless: true,
+            };
+            let bar = tokio::spawn(async move { run_bar(config, None, io).await });
+
+            // i3bar opens the infinite array of click events
+            input.write_all(b"[\n").await.unwrap();
+            Self {
+                input,
+                signals,
+                frames,
+                bar,
+            }
+        }
+
+        /// Write click events at once, i.e. faster than any double click
+        async fn click(&mut self, clicks: &[(usize, u8)]) {
+            let mut lines = String::new();
+            for (id, button) in clicks {
+                lines.push_str(&format!(
+                    ",{{\"name\":\"{}\",\"button\":{},\"x\":10,\"y\":10}}\n",
+                    id, button
+                ));
+            }
+            self.write(&lines).await;
+        }
+
+        /// Write to the bar's stdin
+        async fn write(&mut self, text: &str) {
+            self.input.write_all(text.as_bytes()).await.unwrap();
+        }
+
+        async fn signal(&self, signal: Signal) {
+            self.signals.send(signal).await.unwrap();
+        }
+
+        /// The widgets of the next frame
+        async fn frame(&mut self) -> Vec<JsonValue> {
+            let line = tokio::time::timeout(Duration::from_secs(60), self.frames.recv())
+                .await
+                .expect("no frame drawn")
+                .expect("the bar has exited");
+            serde_json::from_str(line.strip_suffix(',').unwrap()).unwrap()
+        }
+
+        /// The full texts of the next frame
+        async fn texts(&mut self) -> Vec<String> {
+            self.frame()
+                .await
+                .iter()
+                .map(|widget| widget["full_text"].as_str().unwrap().to_string())
+                .collect()
+        }
+
+        async fn assert_no_frame(&mut self) {
+            let frame = tokio::time::timeout(Duration::from_secs(5), self.frames.recv()).await;
+            assert!(frame.is_err(), "unexpected frame: {:?}", frame);
+        }
+
+        /// Close stdin, as i3bar does when it exits
+        async fn stop(self) -> Result<()> {
+            drop(self.input);
+            self.bar.await.unwrap()
+        }
+    }
+
+    const POMODORO: &str = "[[block]]\nblock = \"pomodoro\"\n";
+
+    #[tokio::test(start_paused = true)]
+    async fn test_double_click() {
+        let config = format!(
+            "{}click = [{{ button = \"double_left\", update = false }}]\n",
+            POMODORO
+        );
+        let mut bar = Harness::start(&config, &[]).await;
+        assert_eq!(bar.texts().await, [" POMODORO "]);
+
+        // The double click is handled, so the block doesn't get it
+        bar.click(&[(0, 1), (0, 1)]).await;
+        bar.assert_no_frame().await;
+        bar.click(&[(0, 1)]).await;
+        assert_eq!(bar.texts().await, [" POMODORO Task length: 25 "]);
+
+        // A left click followed by another button is not a double click
+        bar.click(&[(0, 1), (0, 4)]).await;
+        assert_eq!(bar.texts().await, [" POMODORO Break length: 6 "]);
+
+        // Too slow for a double click
+        bar.click(&[(0, 1)]).await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        bar.click(&[(0, 1)]).await;
+        assert_eq!(bar.texts().await, [" POMODORO Pomodoros: 4 "]);
+        assert_eq!(bar.texts().await, [" POMODORO 25 min "]);
+
+        bar.stop().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_triple_click() {
+        let config = format!(
+            "double_click_delay = 0.5\n{}click = [{{ button = \"triple_left\", update = false }}]\n",
+            POMODORO
+        );
+        let mut bar = Harness::start(&config, &[]).await;
+        assert_eq!(bar.texts().await, [" POMODORO "]);
+
+        for _ in 0..3 {
+            bar.click(&[(0, 1)]).await;
+            tokio::time::sleep(Duration::from_millis(300)).await;
+        }
+        bar.assert_no_frame().await;
+
+        // Only a triple click is bound, so a double click is two clicks
+        bar.click(&[(0, 1), (0, 1)]).await;
+        assert_eq!(bar.texts().await, [" POMODORO Break length: 5 "]);
+
+        bar.stop().await.unwrap();
+
+        // Without gestures, clicks are not delayed and a double click is two clicks
+        let mut bar = Harness::start(POMODORO, &[]).await;
+        assert_eq!(bar.texts().await, [" POMODORO "]);
+        bar.click(&[(0, 1), (0, 1)]).await;
+        assert_eq!(bar.texts().await, [" POMODORO Break length: 5 "]);
+
+        bar.stop().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_invalid_clicks() {
+        let mut bar = Harness::start(POMODORO, &[]).await;
+        assert_eq!(bar.texts().await, [" POMODORO "]);
+
+        // Skipped with a warning, or sent to no block
+        bar.write("garbage\n,{\"name\":\"clock\",\"button\":1}\n,{\"button\":1,\n")
+            .await;
+        bar.click(&[(42, 1)]).await;
+        bar.assert_no_frame().await;
+        bar.click(&[(0, 1)]).await;
+        assert_eq!(bar.texts().await, [" POMODORO Task length: 25 "]);
+
+        bar.stop().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_click_without_update() {
+        let config = format!(
+            "{}click = [\n\
+             {{ button = \"up\", update = false }},\n\
+             {{ button = \"down\", modifiers = [\"Shift\"], update = false }},\n\
+             ]\n",
+            POMODORO
+        );
+        let mut bar = Harness::start(&config, &[]).await;
+        assert_eq!(bar.texts().await, [" POMODORO "]);
+        bar.click(&[(0, 1)]).await;
+        assert_eq!(bar.texts().await, [" POMODORO Task length: 25 "]);
+
+        // The block doesn't get the clicks handled with `update = false`
+        bar.click(&[(0, 4)]).await;
+        bar.assert_no_frame().await;
+        bar.write(",{\"name\":\"0\",\"button\":5,\"modifiers\":[\"Shift\"]}\n")
+            .await;
+        bar.assert_no_frame().await;
+        bar.click(&[(0, 5)]).await;
+        assert_eq!(bar.texts().await, [" POMODORO Task length: 24 "]);
+
+        bar.stop().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_click_actions() {
+        let config = format!(
+            "{}click = [\n\
+             {{ button = \"left\", action = \"signal_block:second\" }},\n\
+             {{ button = \"middle\", action = \"hide\" }},\n\
+             ]\n{}name = \"second\"\n",
+            POMODORO, POMODORO
+        );
+        let mut bar = Harness::start(&config, &[]).await;
+        assert_eq!(bar.texts().await, [" POMODORO ", " POMODORO "]);
+
+        // The click is passed to the second block instead of the first
+        bar.click(&[(0, 1)]).await;
+        assert_eq!(
+            bar.texts().await,
+            [" POMODORO ", " POMODORO Task length: 25 "]
+        );
+
+        bar.click(&[(0, 2)]).await;
+        assert_eq!(bar.texts().await, [" POMODORO Task length: 25 "]);
+
+        bar.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_signal_request() {
+        let (message_sender, _messages) = mpsc::channel(64);
+        let mut bar = Bar::new(
+            message_sender,
+            Box::new(std::io::sink()),
+            StateStore::default(),
+        );
+        let config = format!("{}{}name = \"second\"\n", POMODORO, POMODORO);
+        bar.apply_config(toml::from_str(&config).unwrap()).unwrap();
+
+        // The first block has stopped, the second still gets the signal
+        let (first, second) = (bar.order[0], bar.order[1]);
+        bar.blocks.get_mut(&first).unwrap().events = mpsc::channel(1).0;
+        let (events, mut received) = mpsc::channel(1);
+        bar.blocks.get_mut(&second).unwrap().events = events;
+        assert_eq!(
+            bar.handle_request(Request::Signal {
+                signal: 1,
+                block: None
+            }),
+            Err("not signalled: block 0 (pomodoro): the block has stopped".to_string())
+        );
+        assert!(matches!(
+            received.try_recv(),
+            Ok(BlockEvent::Signal(Signal::Custom(1)))
+        ));
+
+        // Now its channel is full
+        assert_eq!(
+            bar.handle_request(Request::Signal {
+                signal: 1,
+                block: Some(BlockRef::Name("second".to_string()))
+            }),
+            Ok(JsonValue::Null)
+        );
+        assert_eq!(
+            bar.handle_request(Request::Signal {
+                signal: 1,
+                block: None
+            }),
+            Err("not signalled: block 0 (pomodoro): the block has stopped, \
+                 block 1 (second): the block is busy"
+                .to_string())
+        );
+        bar.shutdown().await;
+    }
+
+    #[test]
+    fn test_swap_formats() {
+        let config: Value = toml::from_str("format = \"{a}\"\nformat_alt = \"{b}\"").unwrap();
+        let swapped = swap_formats(&config);
+        assert_eq!(swapped["format"].as_str(), Some("{b}"));
+        assert_eq!(swapped["format_alt"].as_str(), Some("{a}"));
+        assert_eq!(swap_formats(&swapped), config);
+
+        // Without a `format`, the block switches between `format_alt` and its default format
+        let config: Value = toml::from_str("format_alt = \"{b}\"").unwrap();
+        let swapped = swap_formats(&config);
+        assert_eq!(swapped["format"].as_str(), Some("{b}"));
+        assert!(swapped.get("format_alt").is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_signals_and_clock() {
+        let mut bar = Harness::start(POMODORO, &[]).await;
+        assert_eq!(bar.texts().await, [" POMODORO "]);
+
+        // Nothing is drawn while the bar is hidden, but the blocks still handle clicks
+        bar.signal(Signal::Stop).await;
+        bar.click(&[(0, 1)]).await;
+        bar.assert_no_frame().await;
+        bar.signal(Signal::Cont).await;
+        assert_eq!(bar.texts().await, [" POMODORO Task length: 25 "]);
+
+        for _ in 0..3 {
+            bar.click(&[(0, 1)]).await;
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        assert_eq!(bar.texts().await, [" POMODORO Break length: 5 "]);
+        assert_eq!(bar.texts().await, [" POMODORO Pomodoros: 4 "]);
+        assert_eq!(bar.texts().await, [" POMODORO 25 min "]);
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert_eq!(bar.texts().await, [" POMODORO 24 min "]);
+
+        bar.signal(Signal::Terminate).await;
+        bar.bar.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_separators_and_tints() {
+        let theme = [
+            ("idle_bg", "#101010"),
+            ("idle_fg", "#a0a0a0"),
+            ("separator", "<"),
+            ("separator_bg", "auto"),
+            ("separator_fg", "auto"),
+            ("alternating_tint_bg", "#05050500"),
+            ("alternating_tint_fg", "#05050500"),
+        ];
+        let mut bar = Harness::start(&POMODORO.repeat(2), &theme).await;
+        let frame = bar.frame().await;
+        let colors: Vec<(&str, &str, &str)> = frame
+            .iter()
+            .map(|widget| {
+                (
+                    widget["full_text"].as_str().unwrap(),
+                    widget["color"].as_str().unwrap_or("none"),
+                    widget["background"].as_str().unwrap_or("none"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            colors,
+            [
+                // The separators take the color of the next block and the background of the
+                // previous one. Every second block from the right is tinted.
+                ("<", "#151515FF", "none"),
+                (" POMODORO ", "#A5A5A5FF", "#151515FF"),
+                ("<", "#101010FF", "#151515FF"),
+                (" POMODORO ", "#A0A0A0FF", "#101010FF"),
+            ]
+        );
+
+        bar.stop().await.unwrap();
+    }
+}
@@ -7,7 +7,7 @@ use neli::{
 };
 
 use std::convert::TryInto;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
 use crate::errors::*;
 use crate::util;
@@ -28,7 +28,7 @@ impl NetDevice {
     /// Use the network device `device`. Raises an error if a directory for that
     /// device is not found.
     pub async fn from_interface(interface: String) -> Self {
-        let path = Path::new("/sys/class/net").join(interface.clone());
+        let path = util::sys_path("/sys/class/net").join(interface.clone());
 
         // I don't believe that this should ever change, so set it now:
         let wireless = path.join("wireless").exists();
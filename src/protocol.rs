@@ -2,7 +2,7 @@ pub mod i3bar_block;
 pub mod i3bar_event;
 
 use crate::config::SharedConfig;
-use crate::errors::*;
+use crate::signals::stop_cont_signals;
 use crate::themes::Color;
 
 use i3bar_block::I3BarBlock;
@@ -11,11 +11,17 @@ pub fn init(never_pause: bool) {
     if never_pause {
         println!("{{\"version\": 1, \"click_events\": true, \"stop_signal\": 0}}\n[");
     } else {
-        println!("{{\"version\": 1, \"click_events\": true}}\n[");
+        // Ask for signals we can handle instead of SIGSTOP/SIGCONT, so that we are not frozen
+        let (stop_signal, cont_signal) = stop_cont_signals();
+        println!(
+            "{{\"version\": 1, \"click_events\": true, \"stop_signal\": {}, \"cont_signal\": {}}}\n[",
+            stop_signal, cont_signal
+        );
     }
 }
 
-pub fn print_blocks(blocks: &[Vec<I3BarBlock>], config: &SharedConfig) -> Result<()> {
+/// Render the widgets of all blocks as one line of the i3bar protocol
+pub fn render_blocks(blocks: &[Vec<I3BarBlock>], config: &SharedConfig) -> String {
     let mut last_bg = Color::None;
 
     let mut rendered_blocks = vec![];
@@ -98,7 +104,5 @@ pub fn print_blocks(blocks: &[Vec<I3BarBlock>], config: &SharedConfig) -> Result
         last_bg = rendered_widgets.last().unwrap().background;
     }
 
-    println!("[{}],", rendered_blocks.join(","));
-
-    Ok(())
+    format!("[{}],", rendered_blocks.join(","))
 }
@@ -1,85 +1,258 @@
-use std::time::Duration;
+use std::io::ErrorKind;
 
 use serde_derive::Deserialize;
+use serde_json::Value as JsonValue;
 
-use tokio::io::{AsyncBufReadExt, BufReader, Stdin};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
 use tokio::sync::mpsc::Sender;
 
 use crate::click::MouseButton;
 
-#[derive(Deserialize, Debug, Clone)]
-struct I3BarEventInternal {
-    pub name: Option<String>,
-    pub instance: Option<String>,
-    pub button: MouseButton,
+/// A click event as written by the bar. Only `name` and `button` are required, since bars and
+/// wrapper scripts don't all send the same fields.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+struct RawEvent {
+    name: Option<JsonValue>,
+    instance: Option<JsonValue>,
+    button: Option<MouseButton>,
+    x: f64,
+    y: f64,
+    relative_x: f64,
+    relative_y: f64,
+    width: f64,
+    height: f64,
+    modifiers: Vec<String>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct I3BarEvent {
     pub id: usize,
     pub instance: Option<usize>,
     pub button: MouseButton,
+    /// The position of the pointer on the output
+    pub x: i32,
+    pub y: i32,
+    /// The position of the pointer relative to the top left corner of the widget
+    pub relative_x: i32,
+    pub relative_y: i32,
+    /// The size of the widget, or zero if the bar didn't send it
+    pub width: i32,
+    pub height: i32,
+    /// The modifier keys held during the click, e.g. `Shift` or `Mod4`
+    pub modifiers: Vec<String>,
+    /// Whether a click handler of the block's config is bound to this click. Blocks don't run
+    /// their own action for such clicks, such as setting the volume to the clicked position.
+    pub handled: bool,
 }
 
-async fn get_event(input: &mut BufReader<Stdin>, invert_scrolling: bool) -> I3BarEvent {
+impl I3BarEvent {
+    /// Where the widget was clicked horizontally, from 0 (left edge) to 1 (right edge). `None` if
+    /// the bar didn't send the width of the widget.
+    pub fn relative_position(&self) -> Option<f64> {
+        if self.width > 0 {
+            Some((self.relative_x as f64 / self.width as f64).clamp(0.0, 1.0))
+        } else {
+            None
+        }
+    }
+
+    /// Whether `other` is a click on the same widget with the same button
+    pub fn same_click(&self, other: &Self) -> bool {
+        self.id == other.id && self.instance == other.instance && self.button == other.button
+    }
+}
+
+/// Read the next event. Returns `None` once the input is closed, i.e. the bar has exited.
+///
+/// Lines which are not valid events are skipped with a warning, since a wrapper script or another
+/// bar may write anything.
+async fn get_event(input: &mut (impl AsyncBufRead + Unpin)) -> Option<I3BarEvent> {
     let mut buf = String::new();
     loop {
-        input.read_line(&mut buf).await.unwrap();
-
-        // Take only the valid JSON object betweem curly braces (cut off leading bracket, commas and whitespace)
-        let slice = buf.trim_start_matches(|c| c != '{');
-        let slice = slice.trim_end_matches(|c| c != '}');
-
-        if !slice.is_empty() {
-            let event: I3BarEventInternal = serde_json::from_str(slice).unwrap();
-            let id = match event.name {
-                Some(name) => name.parse().unwrap(),
-                None => continue,
-            };
-            let instance = event.instance.map(|x| x.parse::<usize>().unwrap());
-
-            use MouseButton::*;
-            let button = match (event.button, invert_scrolling) {
-                (WheelUp, false) | (WheelDown, true) => WheelUp,
-                (WheelUp, true) | (WheelDown, false) => WheelDown,
-                (other, _) => other,
-            };
-
-            return I3BarEvent {
-                id,
-                instance,
-                button,
-            };
+        buf.clear();
+        match input.read_line(&mut buf).await {
+            Ok(0) => return None,
+            Ok(_) => (),
+            // Not UTF-8: the line has been consumed, so go on with the next one
+            Err(error) if error.kind() == ErrorKind::InvalidData => {
+                log::warn!("Ignoring click event: {}", error);
+                continue;
+            }
+            Err(error) => {
+                log::warn!("Failed to read click events: {}", error);
+                return None;
+            }
+        }
+
+        match parse_event(&buf) {
+            Ok(Some(event)) => return Some(event),
+            Ok(None) => (),
+            Err(error) => log::warn!("Ignoring click event {:?}: {}", buf.trim(), error),
         }
     }
 }
 
-pub async fn process_events(sender: Sender<I3BarEvent>, invert_scrolling: bool) {
-    let mut stdin = BufReader::new(tokio::io::stdin());
+/// Parse a line of the infinite array of events. Returns `None` for lines with no event, such as
+/// the opening bracket.
+fn parse_event(line: &str) -> Result<Option<I3BarEvent>, String> {
+    // Take only the valid JSON object betweem curly braces (cut off leading bracket, commas and whitespace)
+    let slice = line.trim_start_matches(|c| c != '{');
+    let slice = slice.trim_end_matches(|c| c != '}');
+    if slice.is_empty() {
+        return Ok(None);
+    }
 
-    loop {
-        // Get next event
-        let mut event = get_event(&mut stdin, invert_scrolling).await;
-
-        // Handle double left click. Max delay between two clicks is 150ms.
-        // TODO: make delay configurable
-        if event.button == MouseButton::Left {
-            tokio::select! {
-                _ = tokio::time::sleep(Duration::from_millis(150)) => (),
-                new_event = get_event(&mut stdin, invert_scrolling) => {
-                    if event == new_event {
-                        event.button = MouseButton::DoubleLeft;
-                    } else {
-                        sender.send(event).await.unwrap();
-                        event = new_event;
+    let event: RawEvent = serde_json::from_str(slice).map_err(|error| error.to_string())?;
+    let id = match event.name {
+        Some(name) => parse_number(&name).ok_or("the name is not a block id")?,
+        None => return Err("no name".to_string()),
+    };
+    let instance = match event.instance {
+        Some(instance) => Some(parse_number(&instance).ok_or("the instance is not a number")?),
+        None => None,
+    };
+    let button = event.button.ok_or("no button")?;
+
+    Ok(Some(I3BarEvent {
+        id,
+        instance,
+        button,
+        x: event.x as i32,
+        y: event.y as i32,
+        relative_x: event.relative_x as i32,
+        relative_y: event.relative_y as i32,
+        width: event.width as i32,
+        height: event.height as i32,
+        modifiers: event.modifiers,
+        handled: false,
+    }))
+}
+
+/// Names and instances are numbers written as strings, but accept plain numbers too
+fn parse_number(value: &JsonValue) -> Option<usize> {
+    match value {
+        JsonValue::String(string) => string.parse().ok(),
+        JsonValue::Number(number) => number.as_u64().map(|number| number as usize),
+        _ => None,
+    }
+}
+
+/// Send the click events read from `input` (stdin, unless testing) to `sender`. Returns once the
+/// input is closed, or once nobody receives the events anymore.
+pub async fn process_events(mut input: impl AsyncBufRead + Unpin, sender: Sender<I3BarEvent>) {
+    while let Some(event) = get_event(&mut input).await {
+        if sender.send(event).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_event() {
+        let event = parse_event(
+            r#",{"name":"3","instance":"1","button":1,"modifiers":["Shift"],"x":1830,"y":10,"relative_x":30.5,"relative_y":10,"output_x":1830,"output_y":10,"width":62,"height":22,"event":272}"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            event,
+            I3BarEvent {
+                id: 3,
+                instance: Some(1),
+                button: MouseButton::Left,
+                x: 1830,
+                y: 10,
+                relative_x: 30,
+                relative_y: 10,
+                width: 62,
+                height: 22,
+                modifiers: vec!["Shift".to_string()],
+                handled: false,
+            }
+        );
+        assert_eq!(event.relative_position(), Some(30.0 / 62.0));
+
+        // Only the name and the button are needed
+        let event = parse_event(r#"{"name":4,"button":"up"}"#).unwrap().unwrap();
+        assert_eq!((event.id, event.button), (4, MouseButton::WheelUp));
+
+        assert_eq!(event.relative_position(), None);
+
+        assert_eq!(parse_event("[\n"), Ok(None));
+        assert!(parse_event(r#"{"name":"clock","button":1}"#).is_err());
+        assert!(parse_event(r#"{"name":"1","instance":"play","button":1}"#).is_err());
+        assert!(parse_event(r#"{"name":"1"}"#).is_err());
+        assert!(parse_event(r#"{"name":"1",,"button":1}"#).is_err());
+    }
+
+    /// Feed the reader random garbage mixed with valid events: it must never panic, and must still
+    /// find the valid events
+    #[test]
+    fn test_get_event_fuzz() {
+        const VALID: &str = r#"{"name":"7","instance":"2","button":3,"x":5,"y":5}"#;
+        const PIECES: &[&str] = &[
+            "{",
+            "}",
+            "[",
+            "]",
+            ",",
+            ":",
+            "\"",
+            "\\",
+            "name",
+            "instance",
+            "button",
+            "null",
+            "-1",
+            "1e999",
+            "18446744073709551616",
+            "\"7\"",
+            "true",
+            " ",
+            "\u{fffd}",
+            "\u{0}",
+        ];
+
+        // xorshift, so that failures can be reproduced
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut random = move |n: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as usize % n
+        };
+
+        for _ in 0..500 {
+            let mut input = Vec::new();
+            let mut valid = 0;
+            for _ in 0..random(10) {
+                if random(3) == 0 {
+                    input.extend_from_slice(VALID.as_bytes());
+                    valid += 1;
+                } else {
+                    for _ in 0..random(20) {
+                        input.extend_from_slice(PIECES[random(PIECES.len())].as_bytes());
+                    }
+                    // Invalid UTF-8
+                    if random(10) == 0 {
+                        input.push(0xff);
                     }
                 }
+                input.push(b'\n');
             }
-        }
 
-        sender
-            .send(event)
-            .await
-            .expect("channel closed while sending event");
+            let mut reader = &input[..];
+            let mut found = 0;
+            while let Some(event) = tokio_test::block_on(get_event(&mut reader)) {
+                assert_eq!(event.id, 7);
+                found += 1;
+            }
+            assert!(found >= valid, "only {} of {} events found", found, valid);
+        }
     }
 }
@@ -0,0 +1,520 @@
+//! Samples of `/proc` and `/sys` shared between blocks
+//!
+//! Blocks which read the same kernel interfaces (`cpu` and `load` both read `/proc/cpuinfo`,
+//! two `disk_space` blocks may watch the same path...) get them from the [`Sampler`] in
+//! [`SharedConfig`](crate::config::SharedConfig) instead of reading them on their own. Each source
+//! is read and parsed at most once per tick: a sample taken less than [`MAX_AGE`] ago is handed
+//! out again. Since interval updates are aligned to the wall clock (see
+//! [`scheduler`](crate::scheduler)), the blocks updated together share their samples.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use nix::sys::statvfs::statvfs;
+use tokio::fs::{read_dir, read_to_string};
+use tokio::time::Instant;
+
+use crate::errors::*;
+use crate::util::{read_file, sys_path};
+
+/// How long a sample is handed out before the source is read again
+pub const MAX_AGE: Duration = Duration::from_millis(200);
+
+const CPU_BOOST_PATH: &str = "/sys/devices/system/cpu/cpufreq/boost";
+const CPU_NO_TURBO_PATH: &str = "/sys/devices/system/cpu/intel_pstate/no_turbo";
+const HWMON_PATH: &str = "/sys/class/hwmon";
+
+#[derive(Default)]
+pub struct Sampler {
+    proc_stat: Source<ProcStat>,
+    cpuinfo: Source<CpuInfo>,
+    loadavg: Source<LoadAvg>,
+    meminfo: Source<MemInfo>,
+    cpu_boost: Source<Option<bool>>,
+    /// By mount point
+    disks: Mutex<HashMap<PathBuf, Arc<Source<DiskUsage>>>>,
+    /// By chip name
+    hwmon: Mutex<HashMap<String, Arc<Source<Vec<i32>>>>>,
+}
+
+impl Sampler {
+    pub async fn proc_stat(&self) -> Result<Arc<ProcStat>> {
+        self.proc_stat
+            .get(async {
+                let text = read(&sys_path("/proc/stat")).await?;
+                ProcStat::parse(&text).with_message("failed to parse /proc/stat")
+            })
+            .await
+    }
+
+    pub async fn cpuinfo(&self) -> Result<Arc<CpuInfo>> {
+        self.cpuinfo
+            .get(async {
+                let text = read(&sys_path("/proc/cpuinfo")).await?;
+                CpuInfo::parse(&text).with_message("failed to parse /proc/cpuinfo")
+            })
+            .await
+    }
+
+    pub async fn loadavg(&self) -> Result<Arc<LoadAvg>> {
+        self.loadavg
+            .get(async {
+                let text = read(&sys_path("/proc/loadavg")).await?;
+                LoadAvg::parse(&text).with_message("failed to parse /proc/loadavg")
+            })
+            .await
+    }
+
+    /// `/proc/meminfo`, with the size of the ZFS ARC if there is one
+    pub async fn meminfo(&self) -> Result<Arc<MemInfo>> {
+        self.meminfo
+            .get(async {
+                let text = read(&sys_path("/proc/meminfo")).await?;
+                let mut meminfo =
+                    MemInfo::parse(&text).with_message("failed to parse /proc/meminfo")?;
+                if let Ok(arcstats) = read_file(&sys_path("/proc/spl/kstat/zfs/arcstats")).await {
+                    meminfo.zfs_arc_cache = parse_arc_size(&arcstats)
+                        .with_message("failed to find zfs_arc_cache size")?;
+                }
+                Ok(meminfo)
+            })
+            .await
+    }
+
+    /// Whether turbo boost is enabled, from the kernel or the intel pstate interface
+    pub async fn cpu_boost(&self) -> Option<bool> {
+        let boost = self
+            .cpu_boost
+            .get(async {
+                Ok(
+                    if let Ok(boost) = read_file(&sys_path(CPU_BOOST_PATH)).await {
+                        Some(boost.starts_with('1'))
+                    } else if let Ok(no_turbo) = read_file(&sys_path(CPU_NO_TURBO_PATH)).await {
+                        Some(no_turbo.starts_with('0'))
+                    } else {
+                        None
+                    },
+                )
+            })
+            .await;
+        boost.ok().and_then(|boost| *boost)
+    }
+
+    /// The usage of the filesystem mounted at `path`
+    pub async fn disk_usage(&self, path: &Path) -> Result<Arc<DiskUsage>> {
+        keyed(&self.disks, path.to_path_buf())
+            .get(async {
+                let statvfs = statvfs(path).map_err(error("failed to retrieve statvfs"))?;
+                Ok(DiskUsage {
+                    total: statvfs.blocks() as u64 * statvfs.fragment_size() as u64,
+                    used: (statvfs.blocks() as u64 - statvfs.blocks_free() as u64)
+                        * statvfs.fragment_size() as u64,
+                    available: statvfs.blocks_available() as u64 * statvfs.block_size() as u64,
+                    free: statvfs.blocks_free() as u64 * statvfs.block_size() as u64,
+                })
+            })
+            .await
+    }
+
+    /// The temperatures of the sensors of a hwmon chip, in degrees Celsius
+    pub async fn temperatures(&self, chip: &str) -> Result<Arc<Vec<i32>>> {
+        keyed(&self.hwmon, chip.to_string())
+            .get(read_temperatures(&sys_path(HWMON_PATH), chip))
+            .await
+    }
+}
+
+impl fmt::Debug for Sampler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Sampler")
+    }
+}
+
+/// One source, with its last sample
+struct Source<T> {
+    sample: tokio::sync::Mutex<Option<(Instant, Arc<T>)>>,
+}
+
+impl<T> Default for Source<T> {
+    fn default() -> Self {
+        Self {
+            sample: tokio::sync::Mutex::new(None),
+        }
+    }
+}
+
+impl<T> Source<T> {
+    /// The last sample if it is recent enough, or else a new one taken with `read`. Blocks asking
+    /// for a sample while it is being taken wait for it rather than reading the source again.
+    async fn get(&self, read: impl Future<Output = Result<T>>) -> Result<Arc<T>> {
+        let mut sample = self.sample.lock().await;
+        if let Some((taken, value)) = &*sample {
+            if taken.elapsed() < MAX_AGE {
+                return Ok(value.clone());
+            }
+        }
+        let value = Arc::new(read.await?);
+        *sample = Some((Instant::now(), value.clone()));
+        Ok(value)
+    }
+}
+
+fn keyed<K, T>(sources: &Mutex<HashMap<K, Arc<Source<T>>>>, key: K) -> Arc<Source<T>>
+where
+    K: std::hash::Hash + Eq,
+{
+    sources.lock().unwrap().entry(key).or_default().clone()
+}
+
+async fn read(path: &Path) -> Result<String> {
+    read_file(path)
+        .await
+        .map_err(error(&format!("failed to read {}", path.display())))
+}
+
+/// The sampler is shared, so its errors don't name a block. The block which gets them is named
+/// when they are reported.
+fn error<E: fmt::Display>(message: &str) -> impl FnOnce(E) -> Error + '_ {
+    move |e| Error::Message {
+        message: format!("{}: {}", message, e),
+    }
+}
+
+/// The time spent by a CPU (or all of them), in ticks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuTime {
+    pub idle: u64,
+    pub non_idle: u64,
+}
+
+impl CpuTime {
+    fn parse(s: &str) -> Option<Self> {
+        let mut s = s.trim().split_ascii_whitespace();
+        let user = u64::from_str(s.next()?).ok()?;
+        let nice = u64::from_str(s.next()?).ok()?;
+        let system = u64::from_str(s.next()?).ok()?;
+        let idle = u64::from_str(s.next()?).ok()?;
+        let iowait = u64::from_str(s.next()?).ok()?;
+        let irq = u64::from_str(s.next()?).ok()?;
+        let softirq = u64::from_str(s.next()?).ok()?;
+
+        Some(Self {
+            idle: idle + iowait,
+            non_idle: user + nice + system + irq + softirq,
+        })
+    }
+
+    /// The share of the time since `old` that was not spent idle. The counters go backwards when
+    /// a CPU is unplugged, in which case nothing is counted.
+    pub fn utilization(&self, old: Self) -> f64 {
+        let elapsed = (self.idle + self.non_idle).saturating_sub(old.idle + old.non_idle);
+        if elapsed == 0 {
+            // Both come from the same sample
+            return 0.;
+        }
+        let non_idle = self.non_idle.saturating_sub(old.non_idle);
+        (non_idle as f64 / elapsed as f64).clamp(0., 1.)
+    }
+}
+
+/// `/proc/stat`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcStat {
+    pub total: CpuTime,
+    /// By CPU
+    pub cpus: Vec<CpuTime>,
+}
+
+impl ProcStat {
+    fn parse(text: &str) -> Option<Self> {
+        let mut total = None;
+        let mut cpus = Vec::new();
+        for line in text.lines() {
+            let data = line.trim_start_matches(|c: char| !c.is_ascii_whitespace());
+            if line.starts_with("cpu ") {
+                total = Some(CpuTime::parse(data)?);
+            } else if line.starts_with("cpu") {
+                cpus.push(CpuTime::parse(data)?);
+            }
+        }
+        Some(Self {
+            total: total?,
+            cpus,
+        })
+    }
+}
+
+/// `/proc/cpuinfo`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuInfo {
+    /// The current frequency of each logical core, in Hz
+    pub frequencies: Vec<f64>,
+    pub logical_cores: usize,
+}
+
+impl CpuInfo {
+    fn parse(text: &str) -> Option<Self> {
+        let mut frequencies = Vec::new();
+        let mut logical_cores = 0;
+        for line in text.lines() {
+            if line.starts_with("processor") {
+                logical_cores += 1;
+            } else if line.starts_with("cpu MHz") {
+                let mhz = line
+                    .trim_end()
+                    .trim_start_matches(|c: char| !c.is_ascii_digit());
+                frequencies.push(f64::from_str(mhz).ok()? * 1e6);
+            }
+        }
+        Some(Self {
+            frequencies,
+            logical_cores,
+        })
+    }
+}
+
+/// `/proc/loadavg`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoadAvg {
+    pub m1: f64,
+    pub m5: f64,
+    pub m15: f64,
+}
+
+impl LoadAvg {
+    fn parse(text: &str) -> Option<Self> {
+        let mut values = text.split_ascii_whitespace().map(f64::from_str);
+        Some(Self {
+            m1: values.next()?.ok()?,
+            m5: values.next()?.ok()?,
+            m15: values.next()?.ok()?,
+        })
+    }
+}
+
+/// The fields of `/proc/meminfo` the blocks use, in KiB
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MemInfo {
+    pub mem_total: u64,
+    pub mem_free: u64,
+    pub buffers: u64,
+    pub cached: u64,
+    pub s_reclaimable: u64,
+    pub shmem: u64,
+    pub swap_total: u64,
+    pub swap_free: u64,
+    /// The size of the ZFS ARC, in bytes
+    pub zfs_arc_cache: u64,
+}
+
+impl MemInfo {
+    fn parse(text: &str) -> Option<Self> {
+        let mut meminfo = Self::default();
+        for line in text.lines() {
+            let mut words = line.split_ascii_whitespace();
+            let name = match words.next() {
+                Some(name) => name,
+                None => continue,
+            };
+            let val = u64::from_str(words.next()?).ok()?;
+            match name {
+                "MemTotal:" => meminfo.mem_total = val,
+                "MemFree:" => meminfo.mem_free = val,
+                "Buffers:" => meminfo.buffers = val,
+                "Cached:" => meminfo.cached = val,
+                "SReclaimable:" => meminfo.s_reclaimable = val,
+                "Shmem:" => meminfo.shmem = val,
+                "SwapTotal:" => meminfo.swap_total = val,
+                "SwapFree:" => meminfo.swap_free = val,
+                _ => (),
+            }
+        }
+        Some(meminfo)
+    }
+}
+
+/// The `size` of `/proc/spl/kstat/zfs/arcstats`
+fn parse_arc_size(text: &str) -> Option<u64> {
+    text.lines().find_map(|line| {
+        let mut words = line.split_ascii_whitespace();
+        if words.next()? == "size" {
+            u64::from_str(words.nth(1)?).ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// The usage of a filesystem, in bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiskUsage {
+    pub total: u64,
+    pub used: u64,
+    pub available: u64,
+    pub free: u64,
+}
+
+/// Read the `temp*_input` files of the chip called `chip` in `hwmon_dir`
+async fn read_temperatures(hwmon_dir: &Path, chip: &str) -> Result<Vec<i32>> {
+    let mut hwmon_dir = read_dir(hwmon_dir)
+        .await
+        .map_err(error("failed to read /sys/class/hwmon directory"))?;
+    while let Some(dir) = hwmon_dir
+        .next_entry()
+        .await
+        .map_err(error("failed to read /sys/class/hwmon directory"))?
+    {
+        if read_to_string(dir.path().join("name"))
+            .await
+            .map(|name| name.trim() == chip)
+            .unwrap_or(false)
+        {
+            let mut chip_dir = read_dir(dir.path())
+                .await
+                .map_err(error("failed to read chip's sysfs directory"))?;
+            let mut temperatures = Vec::new();
+            while let Some(entry) = chip_dir
+                .next_entry()
+                .await
+                .map_err(error("failed to read chip's sysfs directory"))?
+            {
+                let file_name = entry.file_name();
+                let file_name = file_name.to_string_lossy();
+                if file_name.starts_with("temp") && file_name.ends_with("_input") {
+                    let millidegrees: i32 = read_to_string(entry.path())
+                        .await
+                        .map_err(error("failed to read chip's temperature"))?
+                        .trim()
+                        .parse()
+                        .map_err(error("temperature is not an integer"))?;
+                    temperatures.push(millidegrees / 1000);
+                }
+            }
+            // The order of directory entries is unspecified
+            temperatures.sort_unstable();
+            return Ok(temperatures);
+        }
+    }
+    Err(Error::Message {
+        message: format!("chip '{}' not found", chip),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(path: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(path)
+    }
+
+    fn read_fixture(path: &str) -> String {
+        std::fs::read_to_string(fixture(path)).unwrap()
+    }
+
+    #[test]
+    fn test_parse_proc_stat() {
+        let stat = ProcStat::parse(&read_fixture("proc/stat")).unwrap();
+        assert_eq!(
+            stat.total,
+            CpuTime {
+                idle: 3699176 + 23060,
+                non_idle: 4705 + 356 + 584 + 277,
+            }
+        );
+        assert_eq!(stat.cpus.len(), 4);
+        assert_eq!(stat.cpus[3].non_idle, 1128 + 17 + 56 + 20);
+
+        let later = CpuTime {
+            idle: stat.total.idle + 300,
+            non_idle: stat.total.non_idle + 100,
+        };
+        assert_eq!(later.utilization(stat.total), 0.25);
+        // Counters which went backwards
+        assert_eq!(stat.total.utilization(later), 0.);
+        let reset = CpuTime {
+            idle: stat.total.idle + 10000,
+            non_idle: 0,
+        };
+        assert_eq!(reset.utilization(stat.total), 0.);
+
+        assert_eq!(ProcStat::parse("intr 1 2 3\n"), None);
+    }
+
+    #[test]
+    fn test_parse_cpuinfo() {
+        let cpuinfo = CpuInfo::parse(&read_fixture("proc/cpuinfo")).unwrap();
+        assert_eq!(cpuinfo.logical_cores, 4);
+        assert_eq!(cpuinfo.frequencies, [1.8e9, 2.4005e9, 0.8e9, 1e9]);
+    }
+
+    #[test]
+    fn test_parse_loadavg() {
+        assert_eq!(
+            LoadAvg::parse(&read_fixture("proc/loadavg")),
+            Some(LoadAvg {
+                m1: 0.52,
+                m5: 0.58,
+                m15: 0.59,
+            })
+        );
+        assert_eq!(LoadAvg::parse("0.52 oops"), None);
+    }
+
+    #[test]
+    fn test_parse_meminfo() {
+        let meminfo = MemInfo::parse(&read_fixture("proc/meminfo")).unwrap();
+        assert_eq!(
+            meminfo,
+            MemInfo {
+                mem_total: 16273256,
+                mem_free: 8170304,
+                buffers: 393284,
+                cached: 3599144,
+                s_reclaimable: 287768,
+                shmem: 569220,
+                swap_total: 8388604,
+                swap_free: 8126460,
+                zfs_arc_cache: 0,
+            }
+        );
+        assert_eq!(
+            parse_arc_size(&read_fixture("proc/spl/kstat/zfs/arcstats")),
+            Some(1073741824)
+        );
+    }
+
+    #[test]
+    fn test_read_temperatures() {
+        let hwmon = fixture("sys/class/hwmon");
+        let temperatures = tokio_test::block_on(read_temperatures(&hwmon, "coretemp"));
+        assert_eq!(temperatures.unwrap(), [43, 45, 51]);
+        assert!(tokio_test::block_on(read_temperatures(&hwmon, "k10temp")).is_err());
+    }
+
+    #[test]
+    fn test_sample_reuse() {
+        let source = Source::default();
+        let reads = std::cell::Cell::new(0);
+        let read = || async {
+            reads.set(reads.get() + 1);
+            Ok(reads.get())
+        };
+
+        tokio_test::block_on(async {
+            assert_eq!(*source.get(read()).await.unwrap(), 1);
+            assert_eq!(*source.get(read()).await.unwrap(), 1);
+            // Make the sample too old
+            source.sample.lock().await.as_mut().unwrap().0 -= MAX_AGE;
+            assert_eq!(*source.get(read()).await.unwrap(), 2);
+        });
+        assert_eq!(reads.get(), 2);
+    }
+}
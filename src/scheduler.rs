@@ -0,0 +1,127 @@
+//! Decide when interval-driven blocks are updated
+//!
+//! Rather than every block waking up `interval` after its last update, all blocks ask the same
+//! scheduler for their next tick. Ticks are aligned to the wall clock (a block updated every 10
+//! seconds is updated at :00, :10, :20...), so that blocks with compatible intervals wake up
+//! together. With some `slack`, a tick can also be delayed to coincide with a wakeup that is
+//! already planned.
+
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_derive::Deserialize;
+use tokio::time::Instant;
+
+use crate::de::deserialize_duration;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+pub struct SchedulerConfig {
+    /// Align ticks to multiples of the interval on the wall clock
+    pub align: bool,
+    /// How much later than planned a block may be updated, in order to share a wakeup with
+    /// another block
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub slack: Duration,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            align: true,
+            slack: Duration::from_secs(0),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    config: SchedulerConfig,
+    /// The planned wakeups, which other ticks may be moved to
+    wakeups: Mutex<BTreeSet<Instant>>,
+}
+
+impl Scheduler {
+    pub fn new(config: SchedulerConfig) -> Self {
+        Self {
+            config,
+            wakeups: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// When a block updated every `interval` should be updated next
+    pub fn next_tick(&self, interval: Duration) -> Instant {
+        let now = Instant::now();
+        let tick = if self.config.align {
+            now + until_aligned(SystemTime::now(), interval)
+        } else {
+            now + interval
+        };
+
+        let mut wakeups = self.wakeups.lock().unwrap();
+        // Forget the wakeups which have already happened
+        *wakeups = wakeups.split_off(&now);
+        let tick = wakeups
+            .range(tick..=tick + self.config.slack)
+            .next()
+            .copied()
+            .unwrap_or(tick);
+        wakeups.insert(tick);
+        tick
+    }
+}
+
+/// Two schedulers are the same if they schedule the same way
+impl PartialEq for Scheduler {
+    fn eq(&self, other: &Self) -> bool {
+        self.config == other.config
+    }
+}
+
+/// The time from `now` to the next multiple of `interval` since the UNIX epoch
+fn until_aligned(now: SystemTime, interval: Duration) -> Duration {
+    let interval_nanos = interval.as_nanos();
+    if interval_nanos == 0 {
+        return interval;
+    }
+    let since_epoch = now
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let until = interval_nanos - since_epoch % interval_nanos;
+    Duration::from_nanos(until as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_until_aligned() {
+        let at = |millis: u64| UNIX_EPOCH + Duration::from_millis(millis);
+        let secs = Duration::from_secs;
+
+        assert_eq!(until_aligned(at(1_000_003_000), secs(10)), secs(7));
+        // On a boundary, the next tick is a whole interval away
+        assert_eq!(until_aligned(at(1_000_020_000), secs(60)), secs(60));
+        assert_eq!(
+            until_aligned(at(1_000_000_250), Duration::from_millis(500)),
+            Duration::from_millis(250)
+        );
+        assert_eq!(until_aligned(at(5_000), secs(0)), secs(0));
+    }
+
+    #[test]
+    fn test_slack() {
+        let scheduler = Scheduler::new(SchedulerConfig {
+            align: false,
+            slack: Duration::from_secs(1),
+        });
+        let first = scheduler.next_tick(Duration::from_secs(5));
+        // Within the slack of the first tick, so they share it
+        assert_eq!(scheduler.next_tick(Duration::from_millis(4500)), first);
+        // Too early to be delayed that much
+        assert!(scheduler.next_tick(Duration::from_secs(3)) < first);
+    }
+}
@@ -7,16 +7,32 @@ use tokio::sync::mpsc;
 pub enum Signal {
     Usr1,
     Usr2,
+    /// The bar is hidden, see [`stop_cont_signals`]
+    Stop,
+    /// The bar is visible again
+    Cont,
+    /// `SIGTERM` or `SIGINT`: swaystatus should exit
+    Terminate,
     Custom(i32),
 }
 
+/// The signals i3bar is asked to send instead of `SIGSTOP` and `SIGCONT` when the bar is hidden
+/// and shown: `SIGRTMAX-1` and `SIGRTMAX-2`. They can't be used as custom signals.
+pub fn stop_cont_signals() -> (i32, i32) {
+    let sigmax = unsafe { __libc_current_sigrtmax() };
+    (sigmax - 1, sigmax - 2)
+}
+
 /// Starts a thread that listens for provided signals and sends these on the provided channel
 pub async fn process_signals(sender: mpsc::Sender<Signal>) {
     let (sigmin, sigmax) = unsafe { (__libc_current_sigrtmin(), __libc_current_sigrtmax()) };
+    let (stop_signal, cont_signal) = stop_cont_signals();
 
     let mut signals: Vec<i32> = (sigmin..sigmax).collect();
     signals.push(consts::SIGUSR1);
     signals.push(consts::SIGUSR2);
+    signals.push(consts::SIGTERM);
+    signals.push(consts::SIGINT);
 
     let signals = Signals::new(&signals).unwrap();
     let mut signals = signals.fuse();
@@ -26,6 +42,9 @@ pub async fn process_signals(sender: mpsc::Sender<Signal>) {
             .send(match signals.next().await.unwrap() {
                 signal_hook::consts::SIGUSR1 => Signal::Usr1,
                 signal_hook::consts::SIGUSR2 => Signal::Usr2,
+                signal_hook::consts::SIGTERM | signal_hook::consts::SIGINT => Signal::Terminate,
+                x if x == stop_signal => Signal::Stop,
+                x if x == cont_signal => Signal::Cont,
                 x => Signal::Custom(x - sigmin),
             })
             .await
@@ -0,0 +1,162 @@
+//! Block state which survives restarts
+//!
+//! Blocks expose the state worth keeping through [`Block::state`](crate::blocks::Block::state).
+//! The runtime records it after every update, and gives it back to the block when it is
+//! restarted. The state of all blocks is saved to `$XDG_STATE_HOME/swaystatus/state.json` when
+//! swaystatus exits or restarts itself, and loaded on startup.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value as JsonValue;
+
+use crate::errors::*;
+use crate::util::xdg_state_home;
+
+/// The saved state of all blocks, by block key
+#[derive(Debug, Clone, Default)]
+pub struct StateStore {
+    states: Arc<Mutex<HashMap<String, JsonValue>>>,
+    /// The file the state is saved to, if any
+    path: Option<PathBuf>,
+}
+
+impl StateStore {
+    /// Load the state saved by the previous run to `$XDG_STATE_HOME/swaystatus/state.json`
+    pub fn load() -> Self {
+        match state_file() {
+            Some(path) => Self::at(path),
+            None => Self::default(),
+        }
+    }
+
+    /// Load the state saved to `path`. A missing or invalid state file is not an error, the
+    /// blocks just start from scratch.
+    pub fn at(path: PathBuf) -> Self {
+        let states = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        Self {
+            states: Arc::new(Mutex::new(states)),
+            path: Some(path),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = self
+            .path
+            .as_ref()
+            .internal_error("state", "no state file, XDG_STATE_HOME and HOME are unset")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .internal_error("state", "failed to create the state directory")?;
+        }
+        let text = serde_json::to_string(&*self.states.lock().unwrap())
+            .internal_error("state", "failed to serialize the state")?;
+
+        // Write to a temporary file first, so that the state file is never half-written
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, text).internal_error("state", "failed to write the state file")?;
+        std::fs::rename(&tmp, path).internal_error("state", "failed to write the state file")
+    }
+
+    /// The handle through which a block's state is kept.
+    ///
+    /// Blocks are identified by their `name`, or else by their type and how many blocks of the
+    /// same type precede them, so that adding other blocks to the config doesn't mix up states.
+    pub fn block(&self, block: &str, name: Option<&str>, nth: usize) -> BlockState {
+        let key = match name {
+            Some(name) => format!("name:{}", name),
+            None => format!("{}:{}", block, nth),
+        };
+        BlockState {
+            store: self.clone(),
+            key,
+        }
+    }
+}
+
+/// The state of one block
+#[derive(Debug, Clone)]
+pub struct BlockState {
+    store: StateStore,
+    key: String,
+}
+
+impl BlockState {
+    pub fn get(&self) -> Option<JsonValue> {
+        self.store.states.lock().unwrap().get(&self.key).cloned()
+    }
+
+    pub fn set(&self, state: JsonValue) {
+        self.store
+            .states
+            .lock()
+            .unwrap()
+            .insert(self.key.clone(), state);
+    }
+}
+
+fn state_file() -> Option<PathBuf> {
+    xdg_state_home().map(|dir| dir.join("swaystatus/state.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_state() {
+        let store = StateStore::default();
+        let cpu = store.block("cpu", None, 0);
+        let other_cpu = store.block("cpu", None, 1);
+        let named = store.block("cpu", Some("main"), 0);
+
+        cpu.set(JsonValue::Bool(true));
+        assert_eq!(cpu.get(), Some(JsonValue::Bool(true)));
+        assert_eq!(other_cpu.get(), None);
+        assert_eq!(named.get(), None);
+
+        // A restarted block gets a new handle with the same key
+        assert_eq!(
+            store.block("cpu", None, 0).get(),
+            Some(JsonValue::Bool(true))
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("swaystatus-state-{}", std::process::id()));
+        let path = dir.join("swaystatus/state.json");
+
+        // Nothing saved yet
+        assert_eq!(
+            StateStore::at(path.clone()).block("cpu", None, 0).get(),
+            None
+        );
+
+        let store = StateStore::at(path.clone());
+        store.block("cpu", None, 0).set(JsonValue::from(1));
+        store
+            .block("cpu", Some("main"), 1)
+            .set(JsonValue::from("x"));
+        store.save().unwrap();
+        let loaded = StateStore::at(path.clone());
+        assert_eq!(loaded.block("cpu", None, 0).get(), Some(JsonValue::from(1)));
+        assert_eq!(
+            loaded.block("cpu", Some("main"), 1).get(),
+            Some(JsonValue::from("x"))
+        );
+
+        // An invalid state file is ignored
+        std::fs::write(&path, "{\"cpu:0\": ").unwrap();
+        assert_eq!(StateStore::at(path).block("cpu", None, 0).get(), None);
+
+        // A store kept in memory has nowhere to be saved
+        assert!(StateStore::default().save().is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
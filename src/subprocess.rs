@@ -1,29 +1,169 @@
+//! Commands run by blocks and clicks
+//!
+//! Every command is run in its own process group, and logged at the debug level with its exit
+//! status and how long it ran.
+
 use std::io;
-use std::process::{Command, Stdio};
-use std::thread;
+use std::process::{ExitStatus, Output, Stdio};
+use std::time::Instant;
 
-/// Spawns a new child process. This closes stdin and stdout, and returns to the caller after the
-/// child has been started, while a background thread waits for the child to exit.
-pub fn spawn_shell(cmd: &str) -> io::Result<()> {
-    let mut child = Command::new("sh")
-        .args(&["-c", cmd])
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .spawn()?;
-    thread::Builder::new()
-        .name("subprocess".into())
-        .spawn(move || child.wait())
-        .unwrap();
-    Ok(())
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+use tokio::io::AsyncReadExt;
+use tokio::process::{Child, ChildStdout, Command};
+
+/// A child process running in its own process group. The whole group is killed if this is
+/// dropped before the child has exited, so that a stopped block (or swaystatus exiting) doesn't
+/// leave the commands it started running.
+pub struct GroupChild {
+    child: Child,
+    /// `None` once the child has exited
+    pgid: Option<Pid>,
+    /// The command, for the logs
+    command: String,
+    started: Instant,
+}
+
+impl GroupChild {
+    pub fn spawn(command: &mut Command) -> io::Result<Self> {
+        let (child, started) = start(command)?;
+        let pgid = child.id().map(|id| Pid::from_raw(id as i32));
+        Ok(Self {
+            child,
+            pgid,
+            command: describe(command),
+            started,
+        })
+    }
+
+    /// Take the handle to the child's stdout, if it was piped
+    pub fn stdout(&mut self) -> Option<ChildStdout> {
+        self.child.stdout.take()
+    }
+
+    /// Wait for the child to exit. Processes it left in the background are not killed.
+    /// Cancel-safe.
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        let status = self.child.wait().await;
+        log_exit(&self.command, &status, self.started);
+        let status = status?;
+        self.pgid = None;
+        Ok(status)
+    }
+}
+
+impl Drop for GroupChild {
+    fn drop(&mut self) {
+        if let Some(pgid) = self.pgid {
+            log::debug!("Killing {}", self.command);
+            let _ = killpg(pgid, Signal::SIGTERM);
+        }
+    }
+}
+
+/// Start `command` in a new process group
+fn start(command: &mut Command) -> io::Result<(Child, Instant)> {
+    let started = Instant::now();
+    match new_process_group(command).spawn() {
+        Ok(child) => {
+            log::debug!("Started {}", describe(command));
+            Ok((child, started))
+        }
+        Err(error) => {
+            log::warn!("Failed to start {}: {}", describe(command), error);
+            Err(error)
+        }
+    }
+}
+
+fn describe(command: &Command) -> String {
+    format!("{:?}", command.as_std())
 }
 
-pub async fn spawn_shell_sync(cmd: &str) -> io::Result<()> {
-    tokio::process::Command::new("sh")
+fn log_exit(command: &str, status: &io::Result<ExitStatus>, started: Instant) {
+    let elapsed = started.elapsed();
+    match status {
+        Ok(status) if status.success() => {
+            log::debug!("{} exited after {:.2?}", command, elapsed)
+        }
+        Ok(status) => log::info!("{} {} after {:.2?}", command, status, elapsed),
+        Err(error) => log::warn!("Failed to wait for {}: {}", command, error),
+    }
+}
+
+/// Make the command start a new process group
+fn new_process_group(command: &mut Command) -> &mut Command {
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Run the command in its own process group and collect its output, like
+/// [`Command::output`]. The command is killed if the returned future is dropped.
+pub async fn output(command: &mut Command) -> io::Result<Output> {
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = GroupChild::spawn(command)?;
+    let mut stdout_pipe = child.stdout().unwrap();
+    let mut stderr_pipe = child.child.stderr.take().unwrap();
+
+    let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+    let (status, _, _) = tokio::try_join!(
+        child.wait(),
+        stdout_pipe.read_to_end(&mut stdout),
+        stderr_pipe.read_to_end(&mut stderr)
+    )?;
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+/// A command running `cmd` with `sh`, with stdin and stdout closed
+pub fn shell(cmd: &str) -> Command {
+    let mut command = Command::new("sh");
+    command
         .args(&["-c", cmd])
         .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .spawn()?
-        .wait()
-        .await?;
+        .stdout(Stdio::null());
+    command
+}
+
+/// Spawns a new child process and returns to the caller after it has been started. The child
+/// runs in its own process group and is not killed when swaystatus exits, since it is usually a
+/// program started by a click.
+pub fn spawn(command: &mut Command) -> io::Result<()> {
+    let (mut child, started) = start(command)?;
+    let command = describe(command);
+    tokio::spawn(async move {
+        let status = child.wait().await;
+        log_exit(&command, &status, started);
+    });
     Ok(())
 }
+
+/// Spawns `cmd` with `sh`, like [`spawn`]
+pub fn spawn_shell(cmd: &str) -> io::Result<()> {
+    spawn(&mut shell(cmd))
+}
+
+/// Run `program` with `args` in the background, without a shell, so that the arguments need no
+/// quoting
+pub fn spawn_program(program: &str, args: &[&str]) -> io::Result<()> {
+    spawn(
+        Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null()),
+    )
+}
+
+/// Spawns a new child process and returns it, so the caller can wait for it to exit.
+pub fn spawn_shell_async(cmd: &str) -> io::Result<GroupChild> {
+    GroupChild::spawn(&mut shell(cmd))
+}
@@ -91,6 +91,31 @@ pub fn xdg_config_home() -> Option<PathBuf> {
         .map(PathBuf::from)
 }
 
+pub fn xdg_state_home() -> Option<PathBuf> {
+    // If XDG_STATE_HOME is not set, fall back to use HOME/.local/state
+    env::var("XDG_STATE_HOME")
+        .ok()
+        .or_else(|| {
+            env::var("HOME")
+                .ok()
+                .map(|home| format!("{}/.local/state", home))
+        })
+        .map(PathBuf::from)
+}
+
+/// The environment variable holding the directory in which `/sys` and `/proc` are looked up
+pub const FS_ROOT_VAR: &str = "SWAYSTATUS_FS_ROOT";
+
+/// Resolve an absolute path into `/sys` or `/proc` against `$SWAYSTATUS_FS_ROOT`, so that blocks
+/// can be run against a fake tree
+pub fn sys_path(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    match env::var_os(FS_ROOT_VAR) {
+        Some(root) => Path::new(&root).join(path.strip_prefix("/").unwrap_or(path)),
+        None => path.to_path_buf(),
+    }
+}
+
 pub fn deserialize_file<T>(path: &Path) -> Result<T>
 where
     T: DeserializeOwned,
@@ -128,6 +153,8 @@ pub async fn has_command(block_name: &str, command: &str) -> Result<bool> {
         .map(|status| status.success())
 }
 
+// Blocks use `map_to_owned!`, since their values are kept for click commands
+#[allow(unused_macros)]
 macro_rules! map {
     ($($key:expr => $value:expr),+ $(,)*) => {{
         let mut m = ::std::collections::HashMap::new();
@@ -170,9 +197,32 @@ pub fn format_vec_to_bar_graph(content: &[f64]) -> String {
         .collect()
 }
 
+/// Returns `", did you mean 'x'?"` where `x` is the candidate most similar to `name`, or an empty
+/// string if none of the candidates is similar enough to be a likely typo.
+pub fn did_you_mean<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> String {
+    candidates
+        .into_iter()
+        .map(|candidate| (strsim::jaro_winkler(name, candidate), candidate))
+        .filter(|(similarity, _)| *similarity > 0.8)
+        .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+        .map(|(_, candidate)| format!(", did you mean '{}'?", candidate))
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::util::has_command;
+    use crate::util::{did_you_mean, has_command};
+
+    #[test]
+    fn test_did_you_mean() {
+        let candidates = ["cpu", "custom", "time"];
+        assert_eq!(did_you_mean("cpuu", candidates), ", did you mean 'cpu'?");
+        assert_eq!(
+            did_you_mean("custon", candidates),
+            ", did you mean 'custom'?"
+        );
+        assert_eq!(did_you_mean("weather", candidates), "");
+    }
 
     #[test]
     // we assume sh is always available
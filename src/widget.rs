@@ -3,6 +3,7 @@ use serde_derive::Deserialize;
 use crate::config::SharedConfig;
 use crate::errors::*;
 use crate::protocol::i3bar_block::I3BarBlock;
+use crate::protocol::i3bar_event::I3BarEvent;
 use crate::themes::{Color, Theme};
 
 #[derive(Debug, Copy, Clone, Deserialize)]
@@ -150,6 +151,35 @@ impl Widget {
         self.short_spacing = spacing;
     }
 
+    /// Where the text of the widget was clicked, from 0 (start of the text) to 1 (end). `None` if
+    /// the icon or the padding was clicked, or if the bar didn't send the width of the widget.
+    ///
+    /// The bar only reports the size of the whole widget, so all characters are assumed to be
+    /// equally wide.
+    pub fn text_position(&self, click: &I3BarEvent) -> Option<f64> {
+        let text = self
+            .full_text
+            .as_deref()
+            .unwrap_or_default()
+            .chars()
+            .count();
+        let total = self.get_data().full_text.chars().count();
+        if text == 0 {
+            return None;
+        }
+        let before = match &self.icon {
+            Some(icon) => icon.chars().count(),
+            None if matches!(self.full_spacing, Spacing::Normal) => 1,
+            None => 0,
+        };
+        let position = (click.relative_position()? * total as f64 - before as f64) / text as f64;
+        if (0.0..=1.0).contains(&position) {
+            Some(position)
+        } else {
+            None
+        }
+    }
+
     /// Constuct `I3BarBlock` from this widget
     pub fn get_data(&self) -> I3BarBlock {
         let mut data = self.inner.clone();
@@ -197,3 +227,29 @@ impl Widget {
         data
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_position() {
+        let mut widget = Widget::new(0, SharedConfig::default());
+        widget.set_full_text("50%".to_string());
+        let click = |relative_x| I3BarEvent {
+            relative_x,
+            width: 50,
+            ..Default::default()
+        };
+
+        // " 50% " is 5 characters wide, 10 pixels each
+        assert_eq!(widget.text_position(&click(10)), Some(0.0));
+        assert_eq!(widget.text_position(&click(25)), Some(0.5));
+        assert_eq!(widget.text_position(&click(40)), Some(1.0));
+        // The padding
+        assert_eq!(widget.text_position(&click(5)), None);
+        assert_eq!(widget.text_position(&click(45)), None);
+        // No width
+        assert_eq!(widget.text_position(&I3BarEvent::default()), None);
+    }
+}
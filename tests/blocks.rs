@@ -0,0 +1,494 @@
+//! Run swaystatus against fake `/sys` and `/proc` trees and check what the blocks render
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use serde_json::Value;
+
+/// How long to wait for the expected frame
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A temporary directory holding the config, the fake filesystem root and the runtime and state
+/// directories
+struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("swaystatus-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        for dir in &["root", "run", "state"] {
+            fs::create_dir_all(path.join(dir)).unwrap();
+        }
+        Self { path }
+    }
+
+    fn root(&self) -> PathBuf {
+        self.path.join("root")
+    }
+
+    /// Write a file of the fake root, e.g. `sys/class/backlight/acpi_video0/brightness`
+    fn file(&self, path: &str, contents: &str) -> &Self {
+        let path = self.root().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+        self
+    }
+
+    /// Copy a file or directory of `tests/fixtures` to the same place in the fake root
+    fn fixture(&self, path: &str) -> &Self {
+        copy(&fixtures().join(path), &self.root().join(path));
+        self
+    }
+}
+
+/// Write `config` to the config file of `dir`, after a theme giving every state its own foreground
+/// color
+fn write_config(dir: &TestDir, config: &str) -> PathBuf {
+    let theme = format!(
+        "[theme]\n\
+         file = \"{}/files/themes/plain.toml\"\n\
+         [theme.overrides]\n\
+         idle_fg = \"#000000\"\n\
+         info_fg = \"#0000ff\"\n\
+         good_fg = \"#00ff00\"\n\
+         warning_fg = \"#ffff00\"\n\
+         critical_fg = \"#ff0000\"\n",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    let config_path = dir.path.join("config.toml");
+    fs::write(&config_path, format!("{}\n{}", theme, config)).unwrap();
+    config_path
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn copy(from: &Path, to: &Path) {
+    if from.is_dir() {
+        for entry in fs::read_dir(from).unwrap() {
+            let entry = entry.unwrap();
+            copy(&entry.path(), &to.join(entry.file_name()));
+        }
+    } else {
+        fs::create_dir_all(to.parent().unwrap()).unwrap();
+        fs::copy(from, to).unwrap();
+    }
+}
+
+/// A running swaystatus
+struct Bar {
+    child: Child,
+    frames: mpsc::Receiver<Vec<Value>>,
+    /// The last frame received. Since an unchanged bar is not printed again, it may be the
+    /// final one.
+    last_frame: Vec<Value>,
+}
+
+impl Bar {
+    /// Start swaystatus in `dir` with the blocks of `config`, see [`write_config`]
+    fn start(dir: &TestDir, config: &str) -> Self {
+        let config_path = write_config(dir, config);
+
+        let mut child = Command::new(env!("CARGO_BIN_EXE_swaystatus"))
+            .arg(&config_path)
+            .arg("--fs-root")
+            .arg(dir.root())
+            .env("XDG_RUNTIME_DIR", dir.path.join("run"))
+            .env("XDG_STATE_HOME", dir.path.join("state"))
+            .env_remove("DBUS_SESSION_BUS_ADDRESS")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let (sender, frames) = mpsc::channel();
+        std::thread::spawn(move || {
+            // Skip the header and the opening bracket of the infinite array
+            for line in stdout.lines().skip(2) {
+                let line = line.unwrap();
+                let frame = serde_json::from_str(line.trim_end_matches(',')).unwrap();
+                if sender.send(frame).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            child,
+            frames,
+            last_frame: Vec::new(),
+        }
+    }
+
+    /// The widgets of block `id`, once it satisfies `done`
+    fn block_until(&mut self, id: usize, done: impl Fn(&[Value]) -> bool) -> Vec<Value> {
+        let frame = self.frame_until(|frame| {
+            let widgets = widgets(frame, id);
+            !widgets.is_empty() && done(&widgets)
+        });
+        widgets(&frame, id)
+    }
+
+    /// The first frame, possibly the last one received, which satisfies `done`
+    fn frame_until(&mut self, done: impl Fn(&[Value]) -> bool) -> Vec<Value> {
+        while !done(&self.last_frame) {
+            self.last_frame = self.frames.recv_timeout(TIMEOUT).unwrap_or_else(|_| {
+                panic!(
+                    "the bar was not rendered as expected, last frame: {:?}",
+                    self.last_frame
+                )
+            });
+        }
+        self.last_frame.clone()
+    }
+
+    /// The widgets of block `id`, once it has been rendered
+    fn block(&mut self, id: usize) -> Vec<Value> {
+        self.block_until(id, |_| true)
+    }
+
+    /// Click block `id` with `button`, numbered as by the bar
+    fn click(&mut self, id: usize, button: u64) {
+        let stdin = self.child.stdin.as_mut().unwrap();
+        writeln!(stdin, r#"{{"name":"{}","button":{}}},"#, id, button).unwrap();
+        stdin.flush().unwrap();
+    }
+}
+
+impl Drop for Bar {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// The widgets of block `id` in `frame`
+fn widgets(frame: &[Value], id: usize) -> Vec<Value> {
+    let name = id.to_string();
+    frame
+        .iter()
+        .filter(|widget| widget["name"] == name)
+        .cloned()
+        .collect()
+}
+
+fn full_text(widget: &Value) -> &str {
+    widget["full_text"].as_str().unwrap()
+}
+
+/// The state a widget is in, from its color
+fn state(widget: &Value) -> &str {
+    match widget["color"].as_str().unwrap_or_default() {
+        "#000000FF" => "idle",
+        "#0000FFFF" => "info",
+        "#00FF00FF" => "good",
+        "#FFFF00FF" => "warning",
+        "#FF0000FF" => "critical",
+        color => panic!("unexpected color {}", color),
+    }
+}
+
+#[test]
+fn test_battery() {
+    let dir = TestDir::new("battery");
+    dir.file("sys/class/power_supply/AC/type", "Mains\n")
+        .file("sys/class/power_supply/AC/online", "0\n")
+        .file("sys/class/power_supply/BAT0/type", "Battery\n")
+        .file("sys/class/power_supply/BAT0/status", "Discharging\n")
+        .file("sys/class/power_supply/BAT0/capacity", "42\n")
+        .file("sys/class/power_supply/BAT0/energy_now", "21000000\n")
+        .file("sys/class/power_supply/BAT0/energy_full", "50000000\n")
+        .file("sys/class/power_supply/BAT0/power_now", "10500000\n")
+        .file("sys/class/power_supply/BAT1/type", "Battery\n")
+        .file("sys/class/power_supply/BAT1/status", "Charging\n")
+        .file("sys/class/power_supply/BAT1/charge_now", "1000000\n")
+        .file("sys/class/power_supply/BAT1/charge_full", "4000000\n")
+        .file("sys/class/power_supply/BAT1/current_now", "1500000\n")
+        .file("sys/class/power_supply/BAT1/voltage_now", "12000000\n");
+
+    let mut bar = Bar::start(
+        &dir,
+        r#"
+        [[block]]
+        block = "battery"
+        device = "BAT0"
+        format = "{percentage} {time} {power}"
+
+        [[block]]
+        block = "battery"
+        device = "BAT1"
+        format = "{percentage} {time}"
+
+        [[block]]
+        block = "battery"
+        device = "BAT2"
+        "#,
+    );
+
+    let bat0 = bar.block(0);
+    assert_eq!(full_text(&bat0[0]), " BAT 42% 2:00  10W ");
+    assert_eq!(state(&bat0[0]), "info");
+
+    // Charge and time computed from charge_* and current_now * voltage_now
+    let bat1 = bar.block(1);
+    assert_eq!(full_text(&bat1[0]), " BAT 25% 0:10 ");
+    assert_eq!(state(&bat1[0]), "good");
+
+    let bat2 = bar.block(2);
+    assert_eq!(full_text(&bat2[0]), " BAT N/A ");
+    assert_eq!(state(&bat2[0]), "warning");
+}
+
+#[test]
+fn test_backlight() {
+    let dir = TestDir::new("backlight");
+    dir.file(
+        "sys/class/backlight/intel_backlight/max_brightness",
+        "1200\n",
+    )
+    .file(
+        "sys/class/backlight/intel_backlight/actual_brightness",
+        "300\n",
+    )
+    .file("sys/class/backlight/intel_backlight/brightness", "300\n");
+
+    let mut bar = Bar::start(
+        &dir,
+        r#"
+        [[block]]
+        block = "backlight"
+
+        [[block]]
+        block = "backlight"
+        device = "intel_backlight"
+        root_scaling = 2.0
+        "#,
+    );
+
+    assert_eq!(full_text(&bar.block(0)[0]), " BRIGHT 25% ");
+    assert_eq!(full_text(&bar.block(1)[0]), " BRIGHT 50% ");
+
+    // Changes are picked up with inotify
+    dir.file(
+        "sys/class/backlight/intel_backlight/actual_brightness",
+        "1200\n",
+    );
+    bar.block_until(0, |widgets| full_text(&widgets[0]) == " BRIGHT 100% ");
+}
+
+#[test]
+fn test_temperature() {
+    let dir = TestDir::new("temperature");
+    dir.fixture("sys/class/hwmon");
+
+    let mut bar = Bar::start(
+        &dir,
+        r#"
+        [[block]]
+        block = "temperature"
+        format = "{min} {max} {average} {avg}"
+
+        [[block]]
+        block = "temperature"
+        chip = "acpitz"
+        format = "{max}"
+
+        [[block]]
+        block = "temperature"
+        chip = "k10temp"
+        "#,
+    );
+
+    let coretemp = bar.block(0);
+    assert_eq!(full_text(&coretemp[0]), " TEMP 43° 51° 46° 46° ");
+    assert_eq!(state(&coretemp[0]), "info");
+
+    let acpitz = bar.block(1);
+    assert_eq!(full_text(&acpitz[0]), " TEMP 27° ");
+    assert_eq!(state(&acpitz[0]), "idle");
+
+    let missing = bar.block(2);
+    assert_eq!(full_text(&missing[0]), " chip 'k10temp' not found ");
+    assert_eq!(state(&missing[0]), "critical");
+}
+
+#[test]
+fn test_net() {
+    let dir = TestDir::new("net");
+    dir.file("sys/class/net/eth0/uevent", "INTERFACE=eth0\nIFINDEX=2\n")
+        .file("sys/class/net/eth0/statistics/rx_bytes", "1000\n")
+        .file("sys/class/net/eth0/statistics/tx_bytes", "1000\n")
+        .file(
+            "sys/class/net/wg0/uevent",
+            "DEVTYPE=wireguard\nINTERFACE=wg0\nIFINDEX=3\n",
+        )
+        .file("sys/class/net/wg0/statistics/rx_bytes", "0\n")
+        .file("sys/class/net/wg0/statistics/tx_bytes", "0\n");
+
+    let mut bar = Bar::start(
+        &dir,
+        r#"
+        [[block]]
+        block = "net"
+        device = "eth0"
+        format = "{device} {speed_down;K} {speed_up;K}"
+        interval = 1
+
+        [[block]]
+        block = "net"
+        device = "wg0"
+        format = "{device}"
+        "#,
+    );
+
+    let eth0 = bar.block(0);
+    assert_eq!(full_text(&eth0[0]), " ETH eth0  DOWN 0.0KB  UP  0.0KB ");
+
+    let wg0 = bar.block(1);
+    assert_eq!(full_text(&wg0[0]), " VPN wg0 ");
+
+    // Speeds are computed from the statistics of two updates
+    dir.file("sys/class/net/eth0/statistics/rx_bytes", "2001000\n");
+    bar.block_until(0, |widgets| !full_text(&widgets[0]).contains("DOWN 0.0KB"));
+}
+
+#[test]
+fn test_proc() {
+    let dir = TestDir::new("proc");
+    dir.fixture("proc");
+
+    let mut bar = Bar::start(
+        &dir,
+        r#"
+        [[block]]
+        block = "cpu"
+        format = "{utilization} {frequency} {frequency2}"
+
+        [[block]]
+        block = "load"
+        format = "{1m} {5m} {15m}"
+
+        [[block]]
+        block = "memory"
+        format_mem = "{mem_total;M} {mem_free;M} {cached;M}"
+        "#,
+    );
+
+    let cpu = bar.block(0);
+    assert_eq!(full_text(&cpu[0]), " CPU  0% 1.5GHz 2.4GHz ");
+    assert_eq!(state(&cpu[0]), "idle");
+
+    // 0.52 over 4 cores
+    let load = bar.block(1);
+    assert_eq!(full_text(&load[0]), " LOAD 0.5 0.6 0.6 ");
+    assert_eq!(state(&load[0]), "idle");
+
+    // Cached includes the ZFS ARC
+    let memory = bar.block(2);
+    assert_eq!(full_text(&memory[0]), " MEM  16GB 7.8GB 4.2GB ");
+    assert_eq!(state(&memory[0]), "idle");
+}
+
+#[test]
+fn test_toggle_format() {
+    let dir = TestDir::new("toggle_format");
+    dir.fixture("proc");
+
+    let mut bar = Bar::start(
+        &dir,
+        r#"
+        [[block]]
+        block = "cpu"
+        format = "A{utilization}"
+        format_alt = "B{utilization}"
+        [[block.click]]
+        button = "right"
+        action = "toggle_format"
+
+        [[block]]
+        block = "load"
+        format = "A{1m}"
+        format_alt = "B{1m}"
+        [[block.click]]
+        button = "right"
+        action = "toggle_format"
+        "#,
+    );
+    // The id of the block showing `text`, since blocks get a new id when they are restarted
+    let shown = |bar: &mut Bar, text: &str| {
+        let found = |frame: &[Value]| {
+            frame
+                .iter()
+                .find(|widget| full_text(widget).trim().starts_with(text))
+                .map(|widget| widget["name"].as_str().unwrap().parse::<usize>().unwrap())
+        };
+        let frame = bar.frame_until(|frame| found(frame).is_some());
+        found(&frame).unwrap()
+    };
+    let cpu = shown(&mut bar, "CPU A");
+    let load = shown(&mut bar, "LOAD A");
+    bar.click(load, 3);
+    let load = shown(&mut bar, "LOAD B");
+
+    // `cpu` switches formats itself, and is not restarted with its formats swapped as well
+    bar.click(cpu, 3);
+    assert_eq!(shown(&mut bar, "CPU B"), cpu);
+    bar.click(cpu, 3);
+    shown(&mut bar, "CPU A");
+    bar.click(cpu, 1);
+    shown(&mut bar, "CPU B");
+    bar.click(cpu, 3);
+    shown(&mut bar, "CPU A");
+    bar.click(load, 3);
+    shown(&mut bar, "LOAD A");
+}
+
+#[test]
+fn test_reload() {
+    let dir = TestDir::new("reload");
+    let config = |second: &str| {
+        format!(
+            "[[block]]\n\
+             block = \"custom\"\n\
+             command = \"echo $$\"\n\
+             one_shot = true\n\
+             [[block]]\n\
+             block = \"custom\"\n\
+             command = \"echo {}\"\n\
+             one_shot = true\n",
+            second
+        )
+    };
+    let mut bar = Bar::start(&dir, &config("first"));
+    let texts = |frame: &[Value]| frame.iter().map(full_text).collect::<Vec<_>>().join("|");
+    bar.frame_until(|frame| texts(frame).ends_with(" first "));
+    let pid = full_text(&bar.block(0)[0]).to_string();
+
+    // Only the changed block is restarted, so the first one still shows the same PID
+    write_config(&dir, &config("second"));
+    let frame = bar.frame_until(|frame| texts(frame).ends_with(" second "));
+    assert_eq!(full_text(&widgets(&frame, 0)[0]), pid);
+
+    // An invalid config is reported, and the blocks keep running
+    write_config(&dir, "[[block]]\nblock = \"cpuu\"\n");
+    let frame = bar.frame_until(|frame| texts(frame).contains("Failed to reload config"));
+    assert!(texts(&frame).contains(" second "));
+    assert!(texts(&frame).contains("cpuu"));
+    assert_eq!(state(frame.last().unwrap()), "critical");
+}